@@ -1,104 +1,446 @@
-use std::fs;
+mod zip;
 
-use zed_extension_api::{self as zed, Command, LanguageServerId, Result, Worktree};
+use std::{
+    collections::HashMap,
+    fs,
+    io::Read,
+    path::{Component, Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use semver::Version;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use zed_extension_api::{
+    self as zed, settings::LspSettings, Command, LanguageServerId, Result, Worktree,
+};
+
+/// Extension-side options read from `lsp.wakatime.settings`.
+#[derive(Deserialize, Default)]
+struct ExtensionSettings {
+    cache_dir: Option<String>,
+    wakatime_cli_arch: Option<String>,
+    wakatime_ls_arch: Option<String>,
+    wakatime_cli_version: Option<String>,
+    wakatime_ls_version: Option<String>,
+    release_channel: Option<String>,
+    /// wakatime-cli to use instead of a downloaded one, relative to the
+    /// worktree root unless absolute or starting with `~/`.
+    #[serde(alias = "wakatime_cli_path")]
+    cli_path: Option<String>,
+    /// wakatime-ls to run instead of a downloaded one, like Zed's own
+    /// `binary.path`, which wins.
+    wakatime_ls_path: Option<String>,
+}
+
+/// Architectures each project publishes release assets for, in its own naming.
+const PUBLISHED_ARCHS: &[(&str, &[&str])] = &[
+    ("wakatime-cli", &["amd64", "arm64", "386", "arm"]),
+    ("wakatime-ls", &["x86_64", "aarch64", "i686"]),
+];
+
+impl ExtensionSettings {
+    fn for_worktree(worktree: &Worktree) -> Self {
+        LspSettings::for_worktree("wakatime", worktree)
+            .ok()
+            .and_then(|lsp| lsp.settings)
+            .and_then(|settings| serde_json::from_value(settings).ok())
+            .unwrap_or_default()
+    }
+
+    /// `lsp.wakatime.binary.path`, else `wakatime_ls_path`: the wakatime-ls
+    /// to run instead of a downloaded one.
+    fn ls_path(&self, worktree: &Worktree) -> Option<String> {
+        LspSettings::for_worktree("wakatime", worktree)
+            .ok()
+            .and_then(|lsp| lsp.binary)
+            .and_then(|binary| binary.path)
+            .or_else(|| self.wakatime_ls_path.clone())
+            .filter(|path| !path.is_empty())
+    }
+
+    /// Architecture to download `binary` for instead of the detected one, e.g.
+    /// under Rosetta 2.
+    fn forced_arch(&self, binary: &str) -> Option<&str> {
+        match binary {
+            "wakatime-cli" => self.wakatime_cli_arch.as_deref(),
+            _ => self.wakatime_ls_arch.as_deref(),
+        }
+        .filter(|arch| !arch.is_empty())
+    }
+
+    /// Version to download `binary` at instead of the latest release, as
+    /// `major.minor.patch`.
+    fn pinned_version(&self, binary: &str) -> Result<Option<&str>, String> {
+        let version = match binary {
+            "wakatime-cli" => self.wakatime_cli_version.as_deref(),
+            _ => self.wakatime_ls_version.as_deref(),
+        };
+        let Some(version) = version.filter(|version| !version.is_empty()) else {
+            return Ok(None);
+        };
+
+        if Version::parse(version)
+            .is_ok_and(|parsed| parsed.pre.is_empty() && parsed.build.is_empty())
+        {
+            Ok(Some(version))
+        } else {
+            Err(format!(
+                "invalid {binary} version {version:?}, expected major.minor.patch"
+            ))
+        }
+    }
+
+    /// The Zed release channel, from the setting or guessed from the data dir
+    /// the extension runs in.
+    fn release_channel(&self) -> Option<String> {
+        if let Some(channel) = self.release_channel.as_deref().filter(|c| !c.is_empty()) {
+            return Some(channel.to_string());
+        }
+
+        let work_dir = std::env::current_dir().ok()?;
+        let work_dir = work_dir.to_string_lossy().to_ascii_lowercase();
+        ["preview", "nightly", "dev"]
+            .into_iter()
+            .find(|channel| {
+                work_dir.contains(&format!("zed-{channel}"))
+                    || work_dir.contains(&format!("zed {channel}"))
+            })
+            .map(str::to_string)
+    }
+
+    /// Directory holding the downloaded version directories, defaulting to
+    /// the extension work dir. Zed only lets the extension write inside its
+    /// work dir, so anything outside it is rejected up front instead of
+    /// failing on the first download.
+    fn cache_dir(&self) -> Result<PathBuf, String> {
+        let Some(dir) = self.cache_dir.as_deref().filter(|dir| !dir.is_empty()) else {
+            return Ok(PathBuf::from("."));
+        };
+
+        if dir.starts_with('~') || leaves_dir(Path::new(dir)) {
+            return Err(format!(
+                "cache_dir {dir:?} is outside the extension work dir, the only place the \
+                 extension can write to; use a relative path like \"cache\" or remove it"
+            ));
+        }
+
+        Ok(PathBuf::from(dir))
+    }
+}
+
+/// Whether `path`, taken relative to some directory, is absolute or leads out
+/// of that directory through `..`.
+fn leaves_dir(path: &Path) -> bool {
+    let mut depth = 0i32;
+    for component in path.components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir => return true,
+            Component::ParentDir => depth -= 1,
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+        }
+        if depth < 0 {
+            return true;
+        }
+    }
+
+    false
+}
+
+/// Expands a leading `~/` with the worktree shell's `HOME`.
+fn expand_home(path: &str, worktree: &Worktree) -> PathBuf {
+    match path.strip_prefix("~/") {
+        Some(rest) => worktree
+            .shell_env()
+            .into_iter()
+            .find(|(key, _)| key == "HOME")
+            .map(|(_, home)| PathBuf::from(home).join(rest))
+            .unwrap_or_else(|| PathBuf::from(path)),
+        None => PathBuf::from(path),
+    }
+}
+
+/// Resolves a configured binary path: `~/` against `HOME`, and relative paths
+/// against the worktree root rather than the extension work dir, so a binary
+/// vendored in the repository can be used. The extension can't see files
+/// outside its work dir, so the result isn't checked here: wakatime-ls reports
+/// a missing wakatime-cli once it runs, while a missing wakatime-ls fails when
+/// Zed spawns it, with Zed's own error.
+fn resolve_binary_path(path: &str, worktree: &Worktree) -> String {
+    let expanded = expand_home(path, worktree);
+    if expanded.is_absolute() {
+        return expanded.to_string_lossy().to_string();
+    }
+
+    if leaves_dir(&expanded) {
+        eprintln!("wakatime: binary path {path:?} points outside the worktree");
+    }
+
+    Path::new(&worktree.root_path())
+        .join(expanded)
+        .to_string_lossy()
+        .to_string()
+}
 
 struct WakatimeExtension {
     cached_ls_binary_path: Option<String>,
     cached_wakatime_cli_binary_path: Option<String>,
+    /// Per binary, downloads that failed for lack of disk space or
+    /// permissions aren't retried until the backoff has passed.
+    download_backoff: HashMap<String, Backoff>,
 }
 
+struct Backoff {
+    until: Instant,
+    delay: Duration,
+    error: String,
+}
+
+const MIN_DOWNLOAD_BACKOFF: Duration = Duration::from_secs(30);
+const MAX_DOWNLOAD_BACKOFF: Duration = Duration::from_secs(10 * 60);
+
 impl WakatimeExtension {
-    fn target_triple(&self, binary: &str) -> Result<String, String> {
-        let (platform, arch) = zed::current_platform();
-        let (arch, os) = {
-            let arch = match arch {
-                zed::Architecture::Aarch64 if binary == "wakatime-cli" => "arm64",
-                zed::Architecture::Aarch64 if binary == "wakatime-ls" => "aarch64",
-                zed::Architecture::X8664 if binary == "wakatime-cli" => "amd64",
-                zed::Architecture::X8664 if binary == "wakatime-ls" => "x86_64",
-                _ => return Err(format!("unsupported architecture: {arch:?}")),
-            };
+    fn arch(&self, binary: &str, forced: Option<&str>) -> Result<String, String> {
+        if let Some(arch) = forced {
+            let published = PUBLISHED_ARCHS
+                .iter()
+                .find(|(name, _)| *name == binary)
+                .map_or(&[][..], |(_, archs)| *archs);
 
-            let os = match platform {
-                zed::Os::Mac if binary == "wakatime-cli" => "darwin",
-                zed::Os::Mac if binary == "wakatime-ls" => "apple-darwin",
-                zed::Os::Linux if binary == "wakatime-cli" => "linux",
-                zed::Os::Linux if binary == "wakatime-ls" => "unknown-linux-gnu",
-                zed::Os::Windows if binary == "wakatime-cli" => "windows",
-                zed::Os::Windows if binary == "wakatime-ls" => "pc-windows-msvc",
-                _ => return Err("unsupported platform".to_string()),
+            return if published.contains(&arch) {
+                Ok(arch.to_string())
+            } else {
+                Err(format!(
+                    "unsupported {binary} architecture {arch:?}, expected one of: {}",
+                    published.join(", ")
+                ))
             };
+        }
 
-            (arch, os)
+        let (_, arch) = zed::current_platform();
+        arch_name(binary, arch)
+    }
+
+    fn target_triple(&self, binary: &str, arch: &str) -> Result<String, String> {
+        let (platform, _) = zed::current_platform();
+        target_triple(binary, platform, arch)
+    }
+
+    /// Moves version directories downloaded into the work dir before
+    /// `cache_dir` was configured, so switching it doesn't force a re-download.
+    fn migrate_cache(&self, binary: &str, cache_dir: &Path) {
+        if cache_dir == Path::new(".") {
+            return;
+        }
+
+        let Ok(entries) = fs::read_dir(".") else {
+            return;
         };
 
-        Ok(match binary {
-            "wakatime-cli" => format!("{binary}-{os}-{arch}"),
-            _ => format!("{binary}-{arch}-{os}"),
-        })
+        for entry in entries.flatten() {
+            if let Some(file_name) = entry.file_name().to_str() {
+                let target = cache_dir.join(file_name);
+                let is_cache_dir = cache_dir
+                    .strip_prefix(".")
+                    .unwrap_or(cache_dir)
+                    .starts_with(file_name);
+                if file_name.starts_with(binary) && !is_cache_dir && !target.exists() {
+                    // Left behind, the version is only downloaded again.
+                    if let Err(err) = fs::rename(entry.path(), &target) {
+                        eprintln!(
+                            "wakatime: failed to move {file_name} to {}: {err}",
+                            target.display()
+                        );
+                    }
+                }
+            }
+        }
     }
 
     fn download(
-        &self,
+        &mut self,
         language_server_id: &LanguageServerId,
         binary: &str,
         repo: &str,
+        cache_dir: &Path,
+        arch: &str,
+        pinned_version: Option<&str>,
     ) -> Result<String> {
-        let release = zed::latest_github_release(
-            repo,
-            zed::GithubReleaseOptions {
-                require_assets: true,
-                pre_release: false,
-            },
-        )?;
+        if let Some(backoff) = self.download_backoff.get(binary) {
+            if Instant::now() < backoff.until {
+                return Err(backoff.error.clone());
+            }
+        }
 
-        let target_triple = self.target_triple(binary)?;
+        let target_triple = self.target_triple(binary, arch)?;
+        // The static musl build of wakatime-ls also runs without glibc, as on
+        // Alpine or NixOS, so it's preferred wherever it's published.
+        let mut asset_names = vec![format!("{target_triple}.zip")];
+        if let Some(gnu) = target_triple.strip_suffix("-gnu") {
+            asset_names.insert(0, format!("{gnu}-musl.zip"));
+        }
 
-        let asset_name = format!("{target_triple}.zip");
-        let asset = release
-            .assets
-            .iter()
-            .find(|asset| asset.name == asset_name)
-            .ok_or_else(|| format!("no asset found matching {:?}", asset_name))?;
+        // A pinned version is downloaded directly, without querying the API.
+        let (version, download_url, asset_name) = match pinned_version {
+            Some(version) => {
+                let asset_name = pinned_asset(&asset_names, arch, version).to_string();
+                (
+                    format!("v{version}"),
+                    release_asset_url(repo, version, &asset_name),
+                    asset_name,
+                )
+            }
+            None => match release_with_asset(repo, &asset_names) {
+                Ok(found) => found,
+                Err(err) => {
+                    let err = format!("couldn't check for updates: {err}");
+                    return fall_back_to_cached(binary, cache_dir, arch, &target_triple, err);
+                }
+            },
+        };
+        let libc_suffix = if asset_name.ends_with("-musl.zip") {
+            "-musl"
+        } else {
+            ""
+        };
+
+        if let Err(err) = fs::create_dir_all(cache_dir) {
+            let err = err.to_string();
+            let dir = cache_dir.to_string_lossy();
+            return Err(match describe_write_error(&err, &dir) {
+                Some(message) => self.fail_download(language_server_id, binary, message),
+                None => format!("failed to create cache dir {dir}: {err}"),
+            });
+        }
+        self.migrate_cache(binary, cache_dir);
 
-        let version_dir = format!("{binary}-{}", release.version);
+        // Keyed on the architecture so changing a forced arch downloads anew.
+        let version_dir_name = format!("{binary}-{version}-{arch}{libc_suffix}");
+        let version_dir = cache_dir.join(&version_dir_name);
         let binary_path = if binary == "wakatime-cli" {
-            format!("{version_dir}/{target_triple}")
+            version_dir.join(&target_triple)
         } else {
-            format!("{version_dir}/{binary}")
+            version_dir.join(binary)
         };
+        let version_dir = version_dir.to_string_lossy().to_string();
+        let binary_path = binary_path.to_string_lossy().to_string();
+
+        let installed = fs::metadata(&binary_path).map_or(false, |stat| stat.is_file());
+        if installed && !check_install_stamp(&version_dir, &binary_path) {
+            eprintln!("wakatime: cached {binary_path} is corrupted, downloading it again");
+            fs::remove_dir_all(&version_dir).ok();
+        }
 
+        // The extracted binary, when its archive passed the checksum.
+        let mut verified_binary = None;
         if !fs::metadata(&binary_path).map_or(false, |stat| stat.is_file()) {
             zed::set_language_server_installation_status(
                 language_server_id,
                 &zed::LanguageServerInstallationStatus::Downloading,
             );
 
-            zed::download_file(
-                &asset.download_url,
-                &version_dir,
-                zed::DownloadedFileType::Zip,
-            )
-            .map_err(|err| format!("failed to download file: {err}"))?;
+            let archive_path = format!("{version_dir}.zip");
+            let downloaded =
+                download_archive(&download_url, &asset_name, &archive_path).and_then(|verified| {
+                    let content = install_binary(&archive_path, &binary_path)?;
+                    Ok(verified.then_some(content))
+                });
+            fs::remove_file(&archive_path).ok();
+            // A partially written dir would pass or confuse the is-downloaded
+            // check on the next attempt.
+            if downloaded.is_err() {
+                fs::remove_dir_all(&version_dir).ok();
+            }
+
+            verified_binary = match downloaded {
+                Ok(content) => content,
+                Err(err) => {
+                    if let Some(message) = describe_write_error(&err, &version_dir) {
+                        return Err(self.fail_download(language_server_id, binary, message));
+                    }
+                    // Only the pinned version will do when one is set.
+                    if pinned_version.is_some() {
+                        return Err(err);
+                    }
+                    let err = format!("couldn't download {version}: {err}");
+                    return fall_back_to_cached(binary, cache_dir, arch, &target_triple, err);
+                }
+            };
+
+            if pinned_version.is_some() {
+                fs::write(Path::new(&version_dir).join(PINNED_MARKER), "").ok();
+            }
 
-            let entries = fs::read_dir(".")
-                .map_err(|err| format!("failed to list working directory {err}"))?;
+            let entries = fs::read_dir(cache_dir)
+                .map_err(|err| format!("failed to list {}: {err}", cache_dir.display()))?;
 
+            // Pinned versions are kept, since another worktree may pin the
+            // version this one moved away from.
+            let mut names = Vec::new();
             for entry in entries {
                 let entry = entry.map_err(|err| format!("failed to load directory entry {err}"))?;
                 if let Some(file_name) = entry.file_name().to_str() {
-                    if file_name.starts_with(binary) && file_name != version_dir {
-                        fs::remove_dir_all(entry.path()).ok();
+                    if file_name.starts_with(binary) && !entry.path().join(PINNED_MARKER).exists() {
+                        names.push(file_name.to_string());
                     }
                 }
             }
+
+            for name in dirs_to_prune(binary, &names, KEPT_VERSIONS) {
+                if name != version_dir_name {
+                    fs::remove_dir_all(cache_dir.join(name)).ok();
+                }
+            }
+        }
+
+        if let Err(err) = validate_binary_magic(&binary_path) {
+            fs::remove_dir_all(&version_dir).ok();
+            return Err(err);
+        }
+        if let Some(content) = verified_binary {
+            if let Err(err) = write_install_stamp(&version_dir, &content) {
+                eprintln!("wakatime: {err}");
+            }
         }
 
         zed::make_file_executable(&binary_path)?;
+        self.download_backoff.remove(binary);
 
         Ok(binary_path)
     }
 
+    /// Reports a download that can't succeed until the user frees space or
+    /// fixes permissions, and backs off further attempts.
+    fn fail_download(
+        &mut self,
+        language_server_id: &LanguageServerId,
+        binary: &str,
+        message: String,
+    ) -> String {
+        zed::set_language_server_installation_status(
+            language_server_id,
+            &zed::LanguageServerInstallationStatus::Failed(message.clone()),
+        );
+
+        let delay = self
+            .download_backoff
+            .get(binary)
+            .map_or(MIN_DOWNLOAD_BACKOFF, |backoff| {
+                (backoff.delay * 2).min(MAX_DOWNLOAD_BACKOFF)
+            });
+        self.download_backoff.insert(
+            binary.to_string(),
+            Backoff {
+                until: Instant::now() + delay,
+                delay,
+                error: message.clone(),
+            },
+        );
+
+        message
+    }
+
     fn language_server_binary_path(
         &mut self,
         language_server_id: &LanguageServerId,
@@ -109,23 +451,38 @@ impl WakatimeExtension {
             &zed::LanguageServerInstallationStatus::CheckingForUpdate,
         );
 
+        let settings = ExtensionSettings::for_worktree(worktree);
+        if let Some(path) = settings.ls_path(worktree) {
+            return Ok(resolve_binary_path(&path, worktree));
+        }
+
         if let Some(path) = worktree.which("wakatime-ls") {
             return Ok(path.clone());
         }
 
-        let target_triple = self.target_triple("wakatime-ls")?;
+        let arch = self.arch("wakatime-ls", settings.forced_arch("wakatime-ls"))?;
+        let target_triple = self.target_triple("wakatime-ls", &arch)?;
         if let Some(path) = worktree.which(&target_triple) {
             return Ok(path.clone());
         }
 
+        let pinned_version = settings.pinned_version("wakatime-ls")?;
+        let cache_dir = settings.cache_dir()?;
+
         if let Some(path) = &self.cached_ls_binary_path {
-            if fs::metadata(path).map_or(false, |stat| stat.is_file()) {
+            if is_cached(path, &cache_dir, &arch, pinned_version) {
                 return Ok(path.clone());
             }
         }
 
-        let binary_path =
-            self.download(language_server_id, "wakatime-ls", "wakatime/zed-wakatime")?;
+        let binary_path = self.download(
+            language_server_id,
+            "wakatime-ls",
+            "wakatime/zed-wakatime",
+            &cache_dir,
+            &arch,
+            pinned_version,
+        )?;
 
         self.cached_ls_binary_path = Some(binary_path.clone());
 
@@ -142,18 +499,33 @@ impl WakatimeExtension {
             &zed::LanguageServerInstallationStatus::CheckingForUpdate,
         );
 
+        let settings = ExtensionSettings::for_worktree(worktree);
+        if let Some(path) = settings.cli_path.as_deref().filter(|path| !path.is_empty()) {
+            return Ok(resolve_binary_path(path, worktree));
+        }
+
         if let Some(path) = worktree.which("wakatime-cli") {
             return Ok(path.clone());
         }
 
+        let arch = self.arch("wakatime-cli", settings.forced_arch("wakatime-cli"))?;
+        let pinned_version = settings.pinned_version("wakatime-cli")?;
+        let cache_dir = settings.cache_dir()?;
+
         if let Some(path) = &self.cached_wakatime_cli_binary_path {
-            if fs::metadata(path).map_or(false, |stat| stat.is_file()) {
+            if is_cached(path, &cache_dir, &arch, pinned_version) {
                 return Ok(path.clone());
             }
         }
 
-        let binary_path =
-            self.download(language_server_id, "wakatime-cli", "wakatime/wakatime-cli")?;
+        let binary_path = self.download(
+            language_server_id,
+            "wakatime-cli",
+            "wakatime/wakatime-cli",
+            &cache_dir,
+            &arch,
+            pinned_version,
+        )?;
 
         self.cached_wakatime_cli_binary_path = Some(binary_path.clone());
 
@@ -161,11 +533,534 @@ impl WakatimeExtension {
     }
 }
 
+/// The name `binary`'s release assets use for `arch`.
+fn arch_name(binary: &str, arch: zed::Architecture) -> Result<String, String> {
+    let name = match arch {
+        zed::Architecture::Aarch64 if binary == "wakatime-cli" => "arm64",
+        zed::Architecture::Aarch64 if binary == "wakatime-ls" => "aarch64",
+        zed::Architecture::X8664 if binary == "wakatime-cli" => "amd64",
+        zed::Architecture::X8664 if binary == "wakatime-ls" => "x86_64",
+        zed::Architecture::X86 if binary == "wakatime-cli" => "386",
+        zed::Architecture::X86 if binary == "wakatime-ls" => "i686",
+        _ => return Err(format!("unsupported architecture: {arch:?}")),
+    };
+
+    Ok(name.to_string())
+}
+
+/// The release asset name, without extension, of `binary` for `platform`
+/// and an architecture named by [`arch_name`].
+fn target_triple(binary: &str, platform: zed::Os, arch: &str) -> Result<String, String> {
+    let os = match platform {
+        zed::Os::Mac if binary == "wakatime-cli" => "darwin",
+        zed::Os::Mac if binary == "wakatime-ls" => "apple-darwin",
+        zed::Os::Linux if binary == "wakatime-cli" => "linux",
+        zed::Os::Linux if binary == "wakatime-ls" => "unknown-linux-gnu",
+        zed::Os::Windows if binary == "wakatime-cli" => "windows",
+        zed::Os::Windows if binary == "wakatime-ls" => "pc-windows-msvc",
+        _ => return Err("unsupported platform".to_string()),
+    };
+
+    // Neither project builds 32-bit macOS binaries.
+    if matches!(platform, zed::Os::Mac) && matches!(arch, "386" | "i686" | "arm") {
+        return Err(format!("unsupported architecture on macOS: {arch}"));
+    }
+
+    Ok(match binary {
+        "wakatime-cli" => format!("{binary}-{os}-{arch}"),
+        _ => format!("{binary}-{arch}-{os}"),
+    })
+}
+
+/// Linux architectures wakatime-ls publishes a static musl build for, and the
+/// first release that does.
+const MUSL_ARCHS: &[&str] = &["x86_64", "aarch64"];
+const FIRST_MUSL_RELEASE: Version = Version::new(0, 1, 8);
+
+/// Which of `asset_names`, the musl build first when there's a choice, a
+/// pinned `version` publishes for `arch`. Decided from the version alone, as
+/// pinned versions are downloaded without querying the API.
+fn pinned_asset<'a>(asset_names: &'a [String], arch: &str, version: &str) -> &'a str {
+    let has_musl = MUSL_ARCHS.contains(&arch)
+        && Version::parse(version).is_ok_and(|version| version >= FIRST_MUSL_RELEASE);
+
+    match asset_names {
+        [musl, _] if has_musl => musl,
+        [.., asset_name] => asset_name,
+        [] => "",
+    }
+}
+
+fn release_asset_url(repo: &str, version: &str, asset_name: &str) -> String {
+    format!("https://github.com/{repo}/releases/download/v{version}/{asset_name}")
+}
+
+/// How many releases before the latest are tried when it lacks an asset.
+const MAX_RELEASE_FALLBACKS: usize = 3;
+
+/// Finds the newest release with one of `asset_names`, returning its version,
+/// the asset's download url and name. Walks back a few releases, across minor
+/// versions, when the latest one hasn't had all platform assets uploaded yet.
+fn release_with_asset(repo: &str, asset_names: &[String]) -> Result<(String, String, String)> {
+    let latest = with_retries(|| {
+        zed::latest_github_release(
+            repo,
+            zed::GithubReleaseOptions {
+                require_assets: true,
+                pre_release: false,
+            },
+        )
+    })?;
+    let mut tag = latest.version.clone();
+    let mut release = Some(latest);
+
+    // A tag that doesn't exist, as when a minor version was skipped, still
+    // counts as a lookup and moves on to the one before it.
+    for lookups in 0..=MAX_RELEASE_FALLBACKS {
+        if let Some(found) = release
+            .as_ref()
+            .and_then(|release| find_asset(release, asset_names))
+        {
+            return Ok(found);
+        }
+        if lookups == MAX_RELEASE_FALLBACKS {
+            break;
+        }
+
+        let Some(previous) = previous_tag(&tag) else {
+            break;
+        };
+        release = zed::github_release_by_tag_name(repo, &previous).ok();
+        tag = previous;
+    }
+
+    Err(missing_assets(asset_names))
+}
+
+/// Attempts at a GitHub request before giving up, waiting `RETRY_DELAY` after
+/// the first failure and twice as long after each following one.
+const NETWORK_ATTEMPTS: u32 = 3;
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Runs a GitHub request, retrying failures that may go away on their own.
+/// Rate limiting lasts longer than any reasonable wait, and a missing release
+/// or a full disk doesn't go away at all, so those fail right away.
+fn with_retries<T>(mut request: impl FnMut() -> Result<T>) -> Result<T> {
+    let mut delay = RETRY_DELAY;
+    let mut attempt = 1;
+    loop {
+        match request() {
+            Ok(value) => return Ok(value),
+            Err(err) if attempt < NETWORK_ATTEMPTS && is_transient(&err) => {
+                std::thread::sleep(delay);
+                delay *= 2;
+                attempt += 1;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+fn is_transient(err: &str) -> bool {
+    let lower = err.to_ascii_lowercase();
+    !["rate limit", "403", "404", "not found"]
+        .iter()
+        .any(|pattern| lower.contains(pattern))
+        && describe_write_error(err, "").is_none()
+}
+
+/// Uses the newest cached binary when a newer one can't be had, and otherwise
+/// says that there's no binary at all.
+fn fall_back_to_cached(
+    binary: &str,
+    cache_dir: &Path,
+    arch: &str,
+    target_triple: &str,
+    err: String,
+) -> Result<String> {
+    match newest_cached(binary, cache_dir, arch, target_triple) {
+        Some(path) => {
+            eprintln!("wakatime: {err}; using the cached {path}");
+            Ok(path)
+        }
+        None => Err(format!("no {binary} binary available: {err}")),
+    }
+}
+
+/// The first of `asset_names`, in order of preference, that `release` has.
+fn find_asset(
+    release: &zed::GithubRelease,
+    asset_names: &[String],
+) -> Option<(String, String, String)> {
+    asset_names.iter().find_map(|name| {
+        let asset = release.assets.iter().find(|asset| asset.name == *name)?;
+        Some((
+            release.version.clone(),
+            asset.download_url.clone(),
+            name.clone(),
+        ))
+    })
+}
+
+fn missing_assets(asset_names: &[String]) -> String {
+    match asset_names {
+        [asset_name] => format!("no asset found matching {asset_name:?}"),
+        _ => format!("no asset found matching any of {asset_names:?}"),
+    }
+}
+
+/// The tag of the release before `tag`: the previous patch, or the first
+/// release of the previous minor version, e.g. `v1.89.0` for `v1.90.0`, since
+/// its last patch isn't known without listing releases. Major versions, which
+/// may not be compatible, aren't crossed, nor are pre-releases walked back
+/// from.
+fn previous_tag(tag: &str) -> Option<String> {
+    let (prefix, version) = tag.split_at(tag.find(|c: char| c.is_ascii_digit())?);
+    let Version {
+        major,
+        minor,
+        patch,
+        pre,
+        ..
+    } = Version::parse(version).ok()?;
+    if !pre.is_empty() {
+        return None;
+    }
+
+    if patch > 0 {
+        Some(format!("{prefix}{major}.{minor}.{}", patch - 1))
+    } else if minor > 0 {
+        Some(format!("{prefix}{major}.{}.0", minor - 1))
+    } else {
+        None
+    }
+}
+
+/// Downloads the release archive at `download_url` to `archive_path` and
+/// checks it against the SHA-256 published next to it, either as
+/// `<asset>.sha256` or in the release's `checksums.txt`. A mismatch, as from a
+/// truncated download, is retried `CHECKSUM_ATTEMPTS` times in all. A release
+/// without a checksum is accepted with a warning, returning `false`.
+fn download_archive(download_url: &str, asset_name: &str, archive_path: &str) -> Result<bool> {
+    let expected = fetch_checksum(download_url, asset_name, archive_path);
+    if expected.is_none() {
+        eprintln!("wakatime: no checksum published for {asset_name}, skipping verification");
+    }
+
+    let mut actual = String::new();
+    for _ in 0..CHECKSUM_ATTEMPTS {
+        fs::remove_file(archive_path).ok();
+        with_retries(|| {
+            zed::download_file(
+                download_url,
+                archive_path,
+                zed::DownloadedFileType::Uncompressed,
+            )
+        })
+        .map_err(|err| format!("failed to download file: {err}"))?;
+        validate_archive_magic(Path::new(archive_path), zed::DownloadedFileType::Zip)?;
+
+        let Some(expected) = &expected else {
+            return Ok(false);
+        };
+        actual = sha256_file(archive_path)?;
+        if actual == *expected {
+            return Ok(true);
+        }
+        eprintln!(
+            "wakatime: checksum mismatch for {asset_name}, expected {expected}, got {actual}"
+        );
+    }
+
+    Err(format!(
+        "checksum mismatch for {asset_name} after {CHECKSUM_ATTEMPTS} downloads: expected \
+         {}, got {actual}",
+        expected.unwrap_or_default()
+    ))
+}
+
+/// Extracts the binary at `binary_path` from the downloaded archive. The
+/// extension API only extracts archives it downloads itself, which would
+/// install a second, unchecked download, so the checked archive is read here.
+/// Returns the installed bytes.
+fn install_binary(archive_path: &str, binary_path: &str) -> Result<Vec<u8>> {
+    let path = Path::new(binary_path);
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let archive =
+        fs::read(archive_path).map_err(|err| format!("failed to read {archive_path}: {err}"))?;
+    let content = zip::extract(&archive, &name)
+        .map_err(|err| format!("failed to extract {name} from {archive_path}: {err}"))?;
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .map_err(|err| format!("failed to create {}: {err}", dir.display()))?;
+    }
+    fs::write(path, &content).map_err(|err| format!("failed to write {binary_path}: {err}"))?;
+    Ok(content)
+}
+
+/// Downloads of an archive whose checksum doesn't match before giving up.
+const CHECKSUM_ATTEMPTS: usize = 2;
+
+fn sha256_file(path: &str) -> Result<String> {
+    let content = fs::read(path).map_err(|err| format!("failed to read {path}: {err}"))?;
+    Ok(format!("{:x}", Sha256::digest(&content)))
+}
+
+/// Downloaded versions of each binary kept in the cache, the newest first, so
+/// the previous one is there to fall back on.
+const KEPT_VERSIONS: usize = 2;
+
+/// The version in `--version` output like `wakatime-cli/1.90.0` or
+/// `wakatime-ls 0.4.0`: the first word, split at spaces and slashes, that
+/// parses as semver once a leading `v` is dropped.
+fn parse_binary_version(output: &str) -> Option<Version> {
+    output
+        .split(|c: char| c.is_whitespace() || c == '/')
+        .map(|word| word.strip_prefix('v').unwrap_or(word))
+        .find_map(|word| Version::parse(word).ok())
+}
+
+/// The release version in a version directory name like
+/// `wakatime-cli-v1.90.0-amd64`. Only releases are downloaded, so the tag
+/// ends at the first `-`, before the architecture.
+fn cached_version(binary: &str, dir_name: &str) -> Option<Version> {
+    let rest = dir_name.strip_prefix(binary)?.strip_prefix('-')?;
+    let (tag, _) = rest.split_once('-')?;
+    parse_binary_version(tag.strip_prefix('v')?)
+}
+
+/// Which of `dir_names` to delete to keep the `keep` newest versions of
+/// `binary`. Names without a version, from older layouts, are deleted too.
+fn dirs_to_prune(binary: &str, dir_names: &[String], keep: usize) -> Vec<String> {
+    let mut versioned: Vec<(Version, &String)> = Vec::new();
+    let mut prune = Vec::new();
+
+    for name in dir_names {
+        match cached_version(binary, name) {
+            Some(version) => versioned.push((version, name)),
+            None => prune.push(name.clone()),
+        }
+    }
+
+    let mut versions: Vec<&Version> = versioned.iter().map(|(version, _)| version).collect();
+    versions.sort_unstable_by(|a, b| b.cmp(a));
+    versions.dedup();
+    let oldest_kept = versions.get(keep.saturating_sub(1)).cloned().cloned();
+
+    prune.extend(
+        versioned
+            .iter()
+            .filter(|(version, _)| oldest_kept.as_ref().is_some_and(|oldest| version < oldest))
+            .map(|(_, name)| (*name).clone()),
+    );
+    prune
+}
+
+/// The binary from the newest cached version for `arch`, if any.
+fn newest_cached(
+    binary: &str,
+    cache_dir: &Path,
+    arch: &str,
+    target_triple: &str,
+) -> Option<String> {
+    let mut cached: Vec<(Version, PathBuf)> = fs::read_dir(cache_dir)
+        .ok()?
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_str()?.to_string();
+            if !name.ends_with(&format!("-{arch}")) && !name.ends_with(&format!("-{arch}-musl")) {
+                return None;
+            }
+
+            let file = if binary == "wakatime-cli" {
+                target_triple
+            } else {
+                binary
+            };
+            Some((cached_version(binary, &name)?, entry.path().join(file)))
+        })
+        .collect();
+    cached.sort_unstable_by(|(a, _), (b, _)| b.cmp(a));
+
+    cached
+        .into_iter()
+        .map(|(_, path)| path)
+        .find(|path| path.is_file())
+        .map(|path| path.to_string_lossy().to_string())
+}
+
+/// Marks a version directory downloaded for a pinned version.
+const PINNED_MARKER: &str = ".wakatime-pinned";
+
+/// Records the size and SHA-256 of an installed binary next to it, so a copy
+/// corrupted later can be told apart from a good one.
+const INSTALL_STAMP: &str = ".wakatime-install";
+
+/// Stamps the binary in `version_dir` with the size and SHA-256 of `content`,
+/// the bytes extracted from an archive that passed its checksum.
+fn write_install_stamp(version_dir: &str, content: &[u8]) -> Result<()> {
+    let stamp = Path::new(version_dir).join(INSTALL_STAMP);
+    let hash = format!("{:x}", Sha256::digest(content));
+
+    fs::write(&stamp, format!("{} {hash}", content.len()))
+        .map_err(|err| format!("failed to write {}: {err}", stamp.display()))
+}
+
+/// Whether the binary still matches its install stamp. Installs without one,
+/// from before stamps existed or from a release without a checksum, are
+/// trusted as they were.
+fn check_install_stamp(version_dir: &str, binary_path: &str) -> bool {
+    let Ok(stamp) = fs::read_to_string(Path::new(version_dir).join(INSTALL_STAMP)) else {
+        return true;
+    };
+    let Some((size, hash)) = stamp.trim().split_once(' ') else {
+        return false;
+    };
+
+    fs::metadata(binary_path).is_ok_and(|stat| size == stat.len().to_string())
+        && sha256_file(binary_path).is_ok_and(|actual| actual == hash)
+}
+
+/// The expected SHA-256 of `asset_name`, lowercased, or `None` when the
+/// release doesn't publish one.
+fn fetch_checksum(download_url: &str, asset_name: &str, archive_path: &str) -> Option<String> {
+    let (release_url, _) = download_url.rsplit_once('/')?;
+    let checksum_path = format!("{archive_path}.sha256");
+
+    let candidates = [
+        format!("{download_url}.sha256"),
+        format!("{release_url}/checksums.txt"),
+    ];
+    candidates.iter().find_map(|url| {
+        zed::download_file(url, &checksum_path, zed::DownloadedFileType::Uncompressed).ok()?;
+        let content = fs::read_to_string(&checksum_path).ok();
+        fs::remove_file(&checksum_path).ok();
+        parse_checksum(&content?, asset_name)
+    })
+}
+
+/// Finds the hash for `asset_name` in `sha256sum` output, where each line is
+/// `<hash>  <file>` or `<hash> *<file>`. A single hash without a file name, as
+/// in a per-asset `.sha256` file, applies to the asset.
+fn parse_checksum(content: &str, asset_name: &str) -> Option<String> {
+    content.lines().find_map(|line| {
+        let mut fields = line.split_whitespace();
+        let hash = fields.next()?;
+        let file = fields.next().map(|file| file.trim_start_matches('*'));
+        let file = file.map(|file| file.rsplit('/').next().unwrap_or(file));
+
+        let is_hash = hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit());
+        (is_hash && file.map_or(true, |file| file == asset_name)).then(|| hash.to_ascii_lowercase())
+    })
+}
+
+/// Describes disk-full and permission errors, which retrying won't fix, naming
+/// the directory that couldn't be written.
+fn describe_write_error(err: &str, dir: &str) -> Option<String> {
+    let lower = err.to_ascii_lowercase();
+    let matches = |patterns: &[&str]| patterns.iter().any(|pattern| lower.contains(pattern));
+
+    if matches(&[
+        "no space left",
+        "not enough space",
+        "enospc",
+        "(os error 28)",
+        "(os error 112)",
+    ]) {
+        Some(format!("not enough disk space to write {dir}: {err}"))
+    } else if matches(&[
+        "permission denied",
+        "access is denied",
+        "eacces",
+        "(os error 13)",
+        "(os error 5)",
+    ]) {
+        Some(format!("no permission to write {dir}: {err}"))
+    } else {
+        None
+    }
+}
+
+/// Whether a previously resolved binary still exists in the configured cache
+/// dir and was downloaded for `arch`, and for the pinned version if any.
+fn is_cached(path: &str, cache_dir: &Path, arch: &str, pinned_version: Option<&str>) -> bool {
+    let path = Path::new(path);
+    let Some(dir) = path.parent().and_then(Path::file_name) else {
+        return false;
+    };
+    let dir = dir.to_string_lossy();
+
+    path.starts_with(cache_dir)
+        && (dir.ends_with(&format!("-{arch}")) || dir.ends_with(&format!("-{arch}-musl")))
+        && pinned_version.map_or(true, |version| dir.contains(&format!("-v{version}-")))
+        && fs::metadata(path).map_or(false, |stat| stat.is_file())
+}
+
+/// Checks that a downloaded archive starts with the magic bytes of `format`,
+/// catching an error page served in place of the release before it's hashed
+/// or extracted. A bad download is deleted.
+fn validate_archive_magic(path: &Path, format: zed::DownloadedFileType) -> Result<(), String> {
+    let (magic, kind): (&[u8], &str) = match format {
+        zed::DownloadedFileType::Zip => (b"PK\x03\x04", "zip"),
+        zed::DownloadedFileType::Gzip | zed::DownloadedFileType::GzipTar => (b"\x1f\x8b", "gzip"),
+        zed::DownloadedFileType::Uncompressed => return Ok(()),
+    };
+
+    let mut header = vec![0u8; magic.len()];
+    let valid = fs::File::open(path)
+        .and_then(|mut file| file.read_exact(&mut header))
+        .is_ok_and(|()| header == magic);
+
+    if valid {
+        Ok(())
+    } else {
+        fs::remove_file(path).ok();
+        Err(format!(
+            "failed to download file: invalid archive format: {} is not a {kind} archive",
+            path.display()
+        ))
+    }
+}
+
+/// Checks that a downloaded binary starts with the executable magic bytes of
+/// the current platform, catching an error page served in place of the
+/// release archive.
+fn validate_binary_magic(path: &str) -> Result<(), String> {
+    let mut magic = [0u8; 4];
+    fs::File::open(path)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .map_err(|err| format!("failed to read {path}: {err}"))?;
+
+    let (platform, _) = zed::current_platform();
+    let valid = match platform {
+        zed::Os::Mac => matches!(
+            magic,
+            [0xcf, 0xfa, 0xed, 0xfe] | [0xce, 0xfa, 0xed, 0xfe] | [0xca, 0xfe, 0xba, 0xbe]
+        ),
+        zed::Os::Linux => magic == *b"\x7fELF",
+        zed::Os::Windows => magic.starts_with(b"MZ"),
+    };
+
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "invalid archive format: {path} is not an executable for this platform"
+        ))
+    }
+}
+
 impl zed::Extension for WakatimeExtension {
     fn new() -> Self {
         Self {
             cached_ls_binary_path: None,
             cached_wakatime_cli_binary_path: None,
+            download_backoff: HashMap::new(),
         }
     }
 
@@ -179,7 +1074,7 @@ impl zed::Extension for WakatimeExtension {
 
         let ls_binary_path = self.language_server_binary_path(language_server_id, worktree)?;
 
-        let args = vec!["--wakatime-cli".to_string(), {
+        let mut args = vec!["--wakatime-cli".to_string(), {
             use std::env;
             let current = env::current_dir().unwrap();
             let waka_cli = current
@@ -190,6 +1085,12 @@ impl zed::Extension for WakatimeExtension {
 
             waka_cli
         }];
+        args.push("--extension-version".to_string());
+        args.push(env!("CARGO_PKG_VERSION").to_string());
+        if let Some(channel) = ExtensionSettings::for_worktree(worktree).release_channel() {
+            args.push("--release-channel".to_string());
+            args.push(channel);
+        }
 
         Ok(Command {
             args,
@@ -197,6 +1098,558 @@ impl zed::Extension for WakatimeExtension {
             env: worktree.shell_env(),
         })
     }
+
+    fn language_server_initialization_options(
+        &mut self,
+        _language_server_id: &LanguageServerId,
+        worktree: &Worktree,
+    ) -> Result<Option<serde_json::Value>> {
+        let Ok(lsp) = LspSettings::for_worktree("wakatime", worktree) else {
+            return Ok(None);
+        };
+
+        Ok(lsp.initialization_options.or(lsp.settings))
+    }
+
+    /// Answers the server's `workspace/configuration` pull for the `wakatime`
+    /// section with `lsp.wakatime.settings`.
+    fn language_server_workspace_configuration(
+        &mut self,
+        _language_server_id: &LanguageServerId,
+        worktree: &Worktree,
+    ) -> Result<Option<serde_json::Value>> {
+        let settings = LspSettings::for_worktree("wakatime", worktree)
+            .ok()
+            .and_then(|lsp| lsp.settings);
+
+        Ok(settings.map(|settings| serde_json::json!({ "wakatime": settings })))
+    }
 }
 
 zed::register_extension!(WakatimeExtension);
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    /// A fresh directory under the system temp dir.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("zed-wakatime-{}-{name}", std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn path_str(path: &Path) -> String {
+        path.to_string_lossy().to_string()
+    }
+
+    fn settings_with_cache_dir(dir: &str) -> ExtensionSettings {
+        ExtensionSettings {
+            cache_dir: Some(dir.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn cache_dir_defaults_to_the_work_dir() {
+        assert_eq!(
+            ExtensionSettings::default().cache_dir(),
+            Ok(PathBuf::from("."))
+        );
+        assert_eq!(
+            settings_with_cache_dir("").cache_dir(),
+            Ok(PathBuf::from("."))
+        );
+    }
+
+    #[test]
+    fn cache_dir_inside_the_work_dir_is_accepted() {
+        assert_eq!(
+            settings_with_cache_dir("cache").cache_dir(),
+            Ok(PathBuf::from("cache"))
+        );
+        assert_eq!(
+            settings_with_cache_dir("downloads/../cache").cache_dir(),
+            Ok(PathBuf::from("downloads/../cache"))
+        );
+    }
+
+    #[test]
+    fn cache_dir_outside_the_work_dir_is_rejected() {
+        for dir in [
+            "/var/cache/zed-wakatime",
+            "~/.cache/zed-wakatime",
+            "../cache",
+            "cache/../../elsewhere",
+        ] {
+            let err = settings_with_cache_dir(dir).cache_dir().unwrap_err();
+            assert!(
+                err.contains("outside the extension work dir"),
+                "{dir}: {err}"
+            );
+        }
+    }
+
+    #[test]
+    fn installs_the_binary_from_the_checked_archive() {
+        let dir = temp_dir("install");
+        let archive = dir.join("wakatime-ls-v0.1.7-x86_64.zip");
+        let binary = dir.join("wakatime-ls-v0.1.7-x86_64").join("wakatime-ls");
+        let content = b"\x7fELF wakatime-ls";
+        fs::write(
+            &archive,
+            zip::tests::zip(&[("wakatime-ls", &content[..])], true),
+        )
+        .unwrap();
+
+        let installed = install_binary(&path_str(&archive), &path_str(&binary)).unwrap();
+        assert_eq!(installed, content);
+        assert_eq!(fs::read(&binary).unwrap(), content);
+    }
+
+    #[test]
+    fn archive_without_the_binary_installs_nothing() {
+        let dir = temp_dir("install-missing");
+        let archive = dir.join("wakatime-cli-linux-amd64.zip");
+        let binary = dir
+            .join("wakatime-cli-v1.90.0-amd64")
+            .join("wakatime-cli-linux-amd64");
+        fs::write(
+            &archive,
+            zip::tests::zip(&[("README.md", &b"readme"[..])], false),
+        )
+        .unwrap();
+
+        let err = install_binary(&path_str(&archive), &path_str(&binary)).unwrap_err();
+        assert!(err.contains("not found"), "{err}");
+        assert!(!binary.exists());
+    }
+
+    #[test]
+    fn archive_magic_matches_the_format() {
+        let dir = temp_dir("magic");
+        let zip = dir.join("release.zip");
+        let gzip = dir.join("release.tar.gz");
+        fs::write(&zip, b"PK\x03\x04rest of the archive").unwrap();
+        fs::write(&gzip, b"\x1f\x8b\x08rest of the archive").unwrap();
+
+        assert_eq!(
+            validate_archive_magic(&zip, zed::DownloadedFileType::Zip),
+            Ok(())
+        );
+        assert_eq!(
+            validate_archive_magic(&gzip, zed::DownloadedFileType::GzipTar),
+            Ok(())
+        );
+        assert_eq!(
+            validate_archive_magic(&gzip, zed::DownloadedFileType::Gzip),
+            Ok(())
+        );
+        assert_eq!(
+            validate_archive_magic(&zip, zed::DownloadedFileType::Uncompressed),
+            Ok(())
+        );
+        assert!(zip.exists() && gzip.exists());
+    }
+
+    #[test]
+    fn bad_archive_magic_deletes_the_download() {
+        let dir = temp_dir("bad-magic");
+        let page = dir.join("release.zip");
+        let truncated = dir.join("release.tar.gz");
+        let gzip_as_zip = dir.join("other.zip");
+        fs::write(&page, b"<!DOCTYPE html><title>502 Bad Gateway</title>").unwrap();
+        fs::write(&truncated, b"\x1f").unwrap();
+        fs::write(&gzip_as_zip, b"\x1f\x8b\x08rest of the archive").unwrap();
+
+        for (path, format) in [
+            (&page, zed::DownloadedFileType::Zip),
+            (&truncated, zed::DownloadedFileType::GzipTar),
+            (&gzip_as_zip, zed::DownloadedFileType::Zip),
+        ] {
+            let err = validate_archive_magic(path, format).unwrap_err();
+            assert!(err.contains("invalid archive format"), "{err}");
+            assert!(!path.exists(), "{} was kept", path.display());
+        }
+    }
+
+    #[test]
+    fn missing_download_is_an_invalid_archive() {
+        let path = temp_dir("missing-archive").join("release.zip");
+        let err = validate_archive_magic(&path, zed::DownloadedFileType::Zip).unwrap_err();
+        assert!(err.contains("invalid archive format"), "{err}");
+    }
+
+    #[test]
+    fn stamped_binary_passes_until_it_changes() {
+        let dir = temp_dir("stamp");
+        let binary = dir.join("wakatime-ls");
+        let content = b"\x7fELF wakatime-ls";
+        fs::write(&binary, content).unwrap();
+
+        write_install_stamp(&path_str(&dir), content).unwrap();
+        assert!(check_install_stamp(&path_str(&dir), &path_str(&binary)));
+
+        fs::write(&binary, b"\x7fELF wakatime-lz").unwrap();
+        assert!(!check_install_stamp(&path_str(&dir), &path_str(&binary)));
+        fs::write(&binary, b"\x7fELF").unwrap();
+        assert!(!check_install_stamp(&path_str(&dir), &path_str(&binary)));
+    }
+
+    #[test]
+    fn stamp_records_the_verified_bytes_not_the_file() {
+        let dir = temp_dir("stamp-verified");
+        let binary = dir.join("wakatime-ls");
+        // Replaced on disk after extraction, the binary no longer matches.
+        fs::write(&binary, b"\x7fELF tampered").unwrap();
+
+        write_install_stamp(&path_str(&dir), b"\x7fELF wakatime-ls").unwrap();
+        assert!(!check_install_stamp(&path_str(&dir), &path_str(&binary)));
+    }
+
+    #[test]
+    fn unstamped_binary_is_trusted() {
+        let dir = temp_dir("unstamped");
+        let binary = dir.join("wakatime-ls");
+        fs::write(&binary, b"\x7fELF wakatime-ls").unwrap();
+
+        assert!(check_install_stamp(&path_str(&dir), &path_str(&binary)));
+        assert!(!dir.join(INSTALL_STAMP).exists());
+    }
+
+    #[test]
+    fn previous_tag_steps_back_a_patch() {
+        assert_eq!(previous_tag("v1.90.2").as_deref(), Some("v1.90.1"));
+        assert_eq!(previous_tag("v1.90.1").as_deref(), Some("v1.90.0"));
+        assert_eq!(previous_tag("0.1.7").as_deref(), Some("0.1.6"));
+    }
+
+    #[test]
+    fn previous_tag_crosses_minor_versions() {
+        assert_eq!(previous_tag("v1.90.0").as_deref(), Some("v1.89.0"));
+        assert_eq!(previous_tag("v0.2.0").as_deref(), Some("v0.1.0"));
+        assert_eq!(previous_tag("v0.1.0").as_deref(), Some("v0.0.0"));
+    }
+
+    #[test]
+    fn previous_tag_stops_at_major_versions() {
+        assert_eq!(previous_tag("v2.0.0"), None);
+        assert_eq!(previous_tag("v0.0.0"), None);
+    }
+
+    #[test]
+    fn previous_tag_of_an_unknown_format_is_none() {
+        for tag in ["v1.90", "v1.90.0.1", "v1.90.0-beta.1", "latest", ""] {
+            assert_eq!(previous_tag(tag), None, "{tag}");
+        }
+    }
+
+    fn assets(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn pinned_version_url_is_built_without_the_api() {
+        assert_eq!(
+            release_asset_url(
+                "wakatime/wakatime-cli",
+                "1.90.0",
+                "wakatime-cli-linux-amd64.zip"
+            ),
+            "https://github.com/wakatime/wakatime-cli/releases/download/v1.90.0/wakatime-cli-linux-amd64.zip"
+        );
+    }
+
+    fn asset_name(
+        binary: &str,
+        platform: zed::Os,
+        arch: zed::Architecture,
+    ) -> Result<String, String> {
+        let arch = arch_name(binary, arch)?;
+        Ok(format!("{}.zip", target_triple(binary, platform, &arch)?))
+    }
+
+    #[test]
+    fn cli_asset_names_for_every_supported_platform() {
+        use zed::{Architecture::*, Os::*};
+        let cli = |platform, arch| asset_name("wakatime-cli", platform, arch).unwrap();
+
+        assert_eq!(cli(Mac, Aarch64), "wakatime-cli-darwin-arm64.zip");
+        assert_eq!(cli(Mac, X8664), "wakatime-cli-darwin-amd64.zip");
+        assert_eq!(cli(Linux, Aarch64), "wakatime-cli-linux-arm64.zip");
+        assert_eq!(cli(Linux, X8664), "wakatime-cli-linux-amd64.zip");
+        assert_eq!(cli(Linux, X86), "wakatime-cli-linux-386.zip");
+        assert_eq!(cli(Windows, Aarch64), "wakatime-cli-windows-arm64.zip");
+        assert_eq!(cli(Windows, X8664), "wakatime-cli-windows-amd64.zip");
+        assert_eq!(cli(Windows, X86), "wakatime-cli-windows-386.zip");
+    }
+
+    #[test]
+    fn ls_asset_names_for_every_supported_platform() {
+        use zed::{Architecture::*, Os::*};
+        let ls = |platform, arch| asset_name("wakatime-ls", platform, arch).unwrap();
+
+        assert_eq!(ls(Mac, Aarch64), "wakatime-ls-aarch64-apple-darwin.zip");
+        assert_eq!(ls(Mac, X8664), "wakatime-ls-x86_64-apple-darwin.zip");
+        assert_eq!(
+            ls(Linux, Aarch64),
+            "wakatime-ls-aarch64-unknown-linux-gnu.zip"
+        );
+        assert_eq!(ls(Linux, X8664), "wakatime-ls-x86_64-unknown-linux-gnu.zip");
+        assert_eq!(ls(Linux, X86), "wakatime-ls-i686-unknown-linux-gnu.zip");
+        assert_eq!(
+            ls(Windows, Aarch64),
+            "wakatime-ls-aarch64-pc-windows-msvc.zip"
+        );
+        assert_eq!(ls(Windows, X8664), "wakatime-ls-x86_64-pc-windows-msvc.zip");
+        assert_eq!(ls(Windows, X86), "wakatime-ls-i686-pc-windows-msvc.zip");
+    }
+
+    #[test]
+    fn no_32_bit_macos_assets() {
+        for binary in ["wakatime-cli", "wakatime-ls"] {
+            assert!(asset_name(binary, zed::Os::Mac, zed::Architecture::X86).is_err());
+        }
+    }
+
+    #[test]
+    fn unknown_binaries_have_no_assets() {
+        assert!(arch_name("wakatime", zed::Architecture::X8664).is_err());
+        assert!(target_triple("wakatime", zed::Os::Linux, "x86_64").is_err());
+    }
+
+    #[test]
+    fn pinned_linux_ls_prefers_musl_where_published() {
+        let linux = assets(&[
+            "wakatime-ls-x86_64-unknown-linux-musl.zip",
+            "wakatime-ls-x86_64-unknown-linux-gnu.zip",
+        ]);
+        assert_eq!(
+            pinned_asset(&linux, "x86_64", "0.1.8"),
+            "wakatime-ls-x86_64-unknown-linux-musl.zip"
+        );
+        assert_eq!(
+            pinned_asset(&linux, "x86_64", "0.2.0"),
+            "wakatime-ls-x86_64-unknown-linux-musl.zip"
+        );
+        // Released before the musl builds.
+        assert_eq!(
+            pinned_asset(&linux, "x86_64", "0.1.7"),
+            "wakatime-ls-x86_64-unknown-linux-gnu.zip"
+        );
+
+        let i686 = assets(&[
+            "wakatime-ls-i686-unknown-linux-musl.zip",
+            "wakatime-ls-i686-unknown-linux-gnu.zip",
+        ]);
+        assert_eq!(
+            pinned_asset(&i686, "i686", "0.2.0"),
+            "wakatime-ls-i686-unknown-linux-gnu.zip"
+        );
+    }
+
+    #[test]
+    fn pinned_asset_without_a_choice_is_the_only_one() {
+        let mac = assets(&["wakatime-ls-aarch64-apple-darwin.zip"]);
+        assert_eq!(
+            pinned_asset(&mac, "aarch64", "0.2.0"),
+            "wakatime-ls-aarch64-apple-darwin.zip"
+        );
+        let cli = assets(&["wakatime-cli-linux-arm64.zip"]);
+        assert_eq!(
+            pinned_asset(&cli, "arm64", "1.90.0"),
+            "wakatime-cli-linux-arm64.zip"
+        );
+    }
+
+    fn version(text: &str) -> Version {
+        Version::parse(text).unwrap()
+    }
+
+    #[test]
+    fn binary_version_is_parsed_from_version_output() {
+        for (output, expected) in [
+            ("wakatime-cli/1.90.0", "1.90.0"),
+            ("wakatime-ls 0.4.0", "0.4.0"),
+            ("wakatime-ls v0.4.0\n", "0.4.0"),
+            ("1.10.2", "1.10.2"),
+            ("wakatime-cli/1.90.0-alpha.1 linux/amd64", "1.90.0-alpha.1"),
+            ("wakatime-ls 0.5.0-rc.2+build.7", "0.5.0-rc.2+build.7"),
+        ] {
+            assert_eq!(
+                parse_binary_version(output),
+                Some(version(expected)),
+                "{output}"
+            );
+        }
+    }
+
+    #[test]
+    fn output_without_a_version_is_none() {
+        for output in [
+            "",
+            "wakatime-cli",
+            "wakatime-cli/1.90",
+            "version 1.90.0.1",
+            "vx.y.z",
+        ] {
+            assert_eq!(parse_binary_version(output), None, "{output}");
+        }
+    }
+
+    #[test]
+    fn versions_compare_numerically() {
+        assert!(version("1.9.0") < version("1.10.0"));
+        assert!(version("1.90.0") > version("1.89.12"));
+        assert!(version("0.10.0") > version("0.9.9"));
+        assert!(version("2.0.0") > version("1.100.100"));
+    }
+
+    #[test]
+    fn pre_releases_come_before_their_release() {
+        assert!(version("1.90.0-alpha.1") < version("1.90.0-beta.1"));
+        assert!(version("1.90.0-rc.2") < version("1.90.0-rc.10"));
+        assert!(version("1.90.0-rc.10") < version("1.90.0"));
+        assert!(version("1.90.0-rc.1") > version("1.89.0"));
+    }
+
+    #[test]
+    fn cached_version_is_read_from_the_directory_name() {
+        assert_eq!(
+            cached_version("wakatime-cli", "wakatime-cli-v1.90.0-amd64"),
+            Some(version("1.90.0"))
+        );
+        assert_eq!(
+            cached_version("wakatime-ls", "wakatime-ls-v0.1.8-x86_64-musl"),
+            Some(version("0.1.8"))
+        );
+        assert_eq!(cached_version("wakatime-cli", "wakatime-cli-amd64"), None);
+        assert_eq!(
+            cached_version("wakatime-cli", "wakatime-ls-v0.1.8-x86_64"),
+            None
+        );
+        assert_eq!(
+            cached_version("wakatime-cli", "wakatime-cli-v1.90-amd64"),
+            None
+        );
+    }
+
+    #[test]
+    fn pruning_keeps_the_numerically_newest_versions() {
+        let names = assets(&[
+            "wakatime-cli-v1.9.0-amd64",
+            "wakatime-cli-v1.10.0-amd64",
+            "wakatime-cli-v1.100.0-amd64",
+            "wakatime-cli-v1.100.0-arm64",
+            "wakatime-cli",
+        ]);
+        let mut pruned = dirs_to_prune("wakatime-cli", &names, 2);
+        pruned.sort();
+        assert_eq!(
+            pruned,
+            assets(&["wakatime-cli", "wakatime-cli-v1.9.0-amd64"])
+        );
+    }
+
+    #[test]
+    fn newest_cached_compares_versions_numerically() {
+        let cache = temp_dir("newest-cached");
+        for name in ["wakatime-ls-v0.9.0-x86_64", "wakatime-ls-v0.10.0-x86_64"] {
+            fs::create_dir_all(cache.join(name)).unwrap();
+            fs::write(cache.join(name).join("wakatime-ls"), b"binary").unwrap();
+        }
+
+        assert_eq!(
+            newest_cached("wakatime-ls", &cache, "x86_64", ""),
+            Some(path_str(
+                &cache.join("wakatime-ls-v0.10.0-x86_64").join("wakatime-ls")
+            ))
+        );
+    }
+
+    #[test]
+    fn pruning_nothing_while_within_the_limit() {
+        let names = assets(&["wakatime-ls-v0.1.7-x86_64", "wakatime-ls-v0.1.8-x86_64"]);
+        assert!(dirs_to_prune("wakatime-ls", &names, 2).is_empty());
+        assert!(dirs_to_prune("wakatime-ls", &[], 2).is_empty());
+    }
+
+    #[test]
+    fn pruning_keeps_every_build_of_a_kept_version() {
+        let names = assets(&[
+            "wakatime-ls-v0.1.6-x86_64",
+            "wakatime-ls-v0.1.7-x86_64",
+            "wakatime-ls-v0.1.8-x86_64",
+            "wakatime-ls-v0.1.8-x86_64-musl",
+            "wakatime-ls-v0.1.8-aarch64",
+        ]);
+        let mut pruned = dirs_to_prune("wakatime-ls", &names, 2);
+        pruned.sort();
+        assert_eq!(pruned, assets(&["wakatime-ls-v0.1.6-x86_64"]));
+
+        let mut pruned = dirs_to_prune("wakatime-ls", &names, 1);
+        pruned.sort();
+        assert_eq!(
+            pruned,
+            assets(&["wakatime-ls-v0.1.6-x86_64", "wakatime-ls-v0.1.7-x86_64"])
+        );
+    }
+
+    #[test]
+    fn newest_cached_only_considers_the_architecture() {
+        let cache = temp_dir("newest-cached-arch");
+        let triple = "wakatime-cli-linux-amd64";
+        for name in ["wakatime-cli-v1.90.0-amd64", "wakatime-cli-v1.91.0-arm64"] {
+            fs::create_dir_all(cache.join(name)).unwrap();
+            fs::write(cache.join(name).join(triple), b"binary").unwrap();
+        }
+
+        let expected = cache.join("wakatime-cli-v1.90.0-amd64").join(triple);
+        assert_eq!(
+            newest_cached("wakatime-cli", &cache, "amd64", triple),
+            Some(path_str(&expected))
+        );
+        assert_eq!(newest_cached("wakatime-cli", &cache, "386", triple), None);
+    }
+
+    #[test]
+    fn pinned_versions_are_plain_releases() {
+        for (pinned, valid) in [
+            ("1.90.0", true),
+            ("0.1.8", true),
+            ("1.90", false),
+            ("v1.90.0", false),
+            ("1.90.0-rc.1", false),
+            ("01.90.0", false),
+        ] {
+            let settings = ExtensionSettings {
+                wakatime_cli_version: Some(pinned.to_string()),
+                ..Default::default()
+            };
+            assert_eq!(
+                settings.pinned_version("wakatime-cli").is_ok(),
+                valid,
+                "{pinned}"
+            );
+        }
+    }
+
+    #[test]
+    fn pinned_pre_musl_version_compares_numerically() {
+        let linux = assets(&[
+            "wakatime-ls-x86_64-unknown-linux-musl.zip",
+            "wakatime-ls-x86_64-unknown-linux-gnu.zip",
+        ]);
+        assert_eq!(
+            pinned_asset(&linux, "x86_64", "0.1.10"),
+            "wakatime-ls-x86_64-unknown-linux-musl.zip"
+        );
+        assert_eq!(
+            pinned_asset(&linux, "x86_64", "0.0.9"),
+            "wakatime-ls-x86_64-unknown-linux-gnu.zip"
+        );
+    }
+}
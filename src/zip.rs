@@ -0,0 +1,227 @@
+//! Just enough of the zip format to take a binary out of a release archive
+//! that was checked against its published checksum, so the bytes installed
+//! are the bytes verified.
+
+const LOCAL_HEADER: u32 = 0x0403_4b50;
+const CENTRAL_HEADER: u32 = 0x0201_4b50;
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x0605_4b50;
+
+/// Size of the end of central directory record without its comment.
+const END_OF_CENTRAL_DIRECTORY_SIZE: usize = 22;
+
+/// The contents of the file named `name`, at any depth in the archive. Stored
+/// and deflated entries are supported, and checked against their CRC-32.
+pub fn extract(archive: &[u8], name: &str) -> Result<Vec<u8>, String> {
+    let end =
+        find_end_of_central_directory(archive).ok_or_else(|| "not a zip archive".to_string())?;
+    let entries = u16_at(archive, end + 10)?;
+    let mut offset = u32_at(archive, end + 16)? as usize;
+
+    for _ in 0..entries {
+        if u32_at(archive, offset)? != CENTRAL_HEADER {
+            return Err("corrupt zip central directory".to_string());
+        }
+        let method = u16_at(archive, offset + 10)?;
+        let crc = u32_at(archive, offset + 16)?;
+        let compressed_size = u32_at(archive, offset + 20)? as usize;
+        let size = u32_at(archive, offset + 24)? as usize;
+        let name_len = u16_at(archive, offset + 28)? as usize;
+        let extra_len = u16_at(archive, offset + 30)? as usize;
+        let comment_len = u16_at(archive, offset + 32)? as usize;
+        let local_header = u32_at(archive, offset + 42)? as usize;
+        let entry_name = archive
+            .get(offset + 46..offset + 46 + name_len)
+            .ok_or_else(truncated)?;
+        offset += 46 + name_len + extra_len + comment_len;
+
+        let entry_name = String::from_utf8_lossy(entry_name);
+        if entry_name.rsplit('/').next() != Some(name) {
+            continue;
+        }
+
+        let data = entry_data(archive, local_header, compressed_size)?;
+        let content = match method {
+            0 => data.to_vec(),
+            8 => miniz_oxide::inflate::decompress_to_vec(data)
+                .map_err(|err| format!("failed to inflate {name}: {err:?}"))?,
+            _ => {
+                return Err(format!(
+                    "unsupported compression method {method} for {name}"
+                ))
+            }
+        };
+
+        if content.len() != size || crc32(&content) != crc {
+            return Err(format!("{name} is corrupted in the archive"));
+        }
+        return Ok(content);
+    }
+
+    Err(format!("{name} not found in the archive"))
+}
+
+fn find_end_of_central_directory(archive: &[u8]) -> Option<usize> {
+    let last = archive.len().checked_sub(END_OF_CENTRAL_DIRECTORY_SIZE)?;
+    let first = last.saturating_sub(u16::MAX as usize);
+    (first..=last)
+        .rev()
+        .find(|&offset| u32_at(archive, offset) == Ok(END_OF_CENTRAL_DIRECTORY))
+}
+
+/// The compressed bytes of the entry whose local header is at `offset`.
+fn entry_data(archive: &[u8], offset: usize, size: usize) -> Result<&[u8], String> {
+    if u32_at(archive, offset)? != LOCAL_HEADER {
+        return Err("corrupt zip local header".to_string());
+    }
+    let name_len = u16_at(archive, offset + 26)? as usize;
+    let extra_len = u16_at(archive, offset + 28)? as usize;
+    let start = offset + 30 + name_len + extra_len;
+
+    archive.get(start..start + size).ok_or_else(truncated)
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Result<u16, String> {
+    data.get(offset..offset + 2)
+        .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+        .ok_or_else(truncated)
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Result<u32, String> {
+    data.get(offset..offset + 4)
+        .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .ok_or_else(truncated)
+}
+
+fn truncated() -> String {
+    "truncated zip archive".to_string()
+}
+
+/// CRC-32 (IEEE), as stored in zip headers.
+fn crc32(data: &[u8]) -> u32 {
+    let mut table = [0u32; 256];
+    for (index, entry) in table.iter_mut().enumerate() {
+        let mut crc = index as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+        *entry = crc;
+    }
+
+    !data.iter().fold(!0u32, |crc, &byte| {
+        table[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// A zip archive of `files`, stored or deflated.
+    pub fn zip(files: &[(&str, &[u8])], deflate: bool) -> Vec<u8> {
+        let method: u16 = if deflate { 8 } else { 0 };
+        let mut archive = Vec::new();
+        let mut central = Vec::new();
+
+        for (name, content) in files {
+            let data = if deflate {
+                miniz_oxide::deflate::compress_to_vec(content, 6)
+            } else {
+                content.to_vec()
+            };
+            let local_header = archive.len() as u32;
+
+            // Version needed, flags, method, time and date.
+            let mut fields = Vec::new();
+            fields.extend_from_slice(&20u16.to_le_bytes());
+            fields.extend_from_slice(&0u16.to_le_bytes());
+            fields.extend_from_slice(&method.to_le_bytes());
+            fields.extend_from_slice(&[0; 4]);
+            fields.extend_from_slice(&crc32(content).to_le_bytes());
+            fields.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            fields.extend_from_slice(&(content.len() as u32).to_le_bytes());
+            fields.extend_from_slice(&(name.len() as u16).to_le_bytes());
+
+            archive.extend_from_slice(&LOCAL_HEADER.to_le_bytes());
+            archive.extend_from_slice(&fields);
+            archive.extend_from_slice(&0u16.to_le_bytes());
+            archive.extend_from_slice(name.as_bytes());
+            archive.extend_from_slice(&data);
+
+            central.extend_from_slice(&CENTRAL_HEADER.to_le_bytes());
+            central.extend_from_slice(&20u16.to_le_bytes());
+            central.extend_from_slice(&fields);
+            // Extra and comment lengths, disk, internal and external
+            // attributes.
+            central.extend_from_slice(&[0; 12]);
+            central.extend_from_slice(&local_header.to_le_bytes());
+            central.extend_from_slice(name.as_bytes());
+        }
+
+        let central_offset = archive.len() as u32;
+        archive.extend_from_slice(&central);
+        archive.extend_from_slice(&END_OF_CENTRAL_DIRECTORY.to_le_bytes());
+        archive.extend_from_slice(&[0; 4]);
+        archive.extend_from_slice(&(files.len() as u16).to_le_bytes());
+        archive.extend_from_slice(&(files.len() as u16).to_le_bytes());
+        archive.extend_from_slice(&(central.len() as u32).to_le_bytes());
+        archive.extend_from_slice(&central_offset.to_le_bytes());
+        archive.extend_from_slice(&0u16.to_le_bytes());
+        archive
+    }
+
+    const BINARY: &[u8] = b"\x7fELF binary contents, binary contents, binary contents";
+
+    #[test]
+    fn crc32_matches_the_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn extracts_stored_and_deflated_entries() {
+        for deflate in [false, true] {
+            let archive = zip(
+                &[("README.md", &b"readme"[..]), ("wakatime-ls", BINARY)],
+                deflate,
+            );
+            assert_eq!(extract(&archive, "wakatime-ls"), Ok(BINARY.to_vec()));
+            assert_eq!(extract(&archive, "README.md"), Ok(b"readme".to_vec()));
+        }
+    }
+
+    #[test]
+    fn finds_entries_in_a_directory() {
+        let archive = zip(&[("wakatime-ls-x86_64/wakatime-ls", BINARY)], true);
+        assert_eq!(extract(&archive, "wakatime-ls"), Ok(BINARY.to_vec()));
+    }
+
+    #[test]
+    fn missing_entry_is_an_error() {
+        let archive = zip(&[("README.md", &b"readme"[..])], false);
+        let err = extract(&archive, "wakatime-ls").unwrap_err();
+        assert!(err.contains("not found"), "{err}");
+    }
+
+    #[test]
+    fn corrupted_entry_is_an_error() {
+        let mut archive = zip(&[("wakatime-ls", BINARY)], false);
+        // The stored contents follow the 30 byte local header and the name.
+        archive[30 + "wakatime-ls".len() + 5] ^= 0xff;
+
+        let err = extract(&archive, "wakatime-ls").unwrap_err();
+        assert!(err.contains("corrupted"), "{err}");
+    }
+
+    #[test]
+    fn html_error_page_is_not_a_zip() {
+        let page = b"<!DOCTYPE html><html><body>502 Bad Gateway</body></html>";
+        assert_eq!(
+            extract(page, "wakatime-ls"),
+            Err("not a zip archive".to_string())
+        );
+        assert!(extract(&[], "wakatime-ls").is_err());
+    }
+}
@@ -0,0 +1,155 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Read, Seek, Write},
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Local, TimeDelta};
+use serde::{Deserialize, Serialize};
+
+use crate::{file_lock::try_lock, settings::wakatime_home};
+
+/// The last heartbeat sent by any wakatime-ls process, so that Zed windows,
+/// each running its own server, don't both send one for the same file.
+pub fn active_path() -> Option<PathBuf> {
+    wakatime_home().map(|home| home.join(".wakatime").join("zed-active.json"))
+}
+
+#[derive(Serialize, Deserialize)]
+struct Active {
+    uri: String,
+    timestamp: String,
+    pid: u32,
+    #[serde(default)]
+    is_write: bool,
+}
+
+/// Claims a heartbeat for `uri`, returning `false` when another process sent
+/// one of the same kind for it within `interval`. The check and the claim
+/// happen under an exclusive lock, so two windows can't both claim it. The
+/// lock isn't waited for: while another window holds it, and on errors, the
+/// heartbeat is claimed, as a busy or broken shared file must not stall or
+/// stop tracking. It still does blocking file I/O, so call it off the async
+/// runtime.
+pub fn claim(uri: &str, is_write: bool, now: DateTime<Local>, interval: TimeDelta) -> bool {
+    active_path()
+        .ok_or_else(|| io::Error::other("no home directory"))
+        .and_then(|path| try_claim(&path, uri, is_write, now, interval))
+        .unwrap_or(true)
+}
+
+fn try_claim(
+    path: &Path,
+    uri: &str,
+    is_write: bool,
+    now: DateTime<Local>,
+    interval: TimeDelta,
+) -> io::Result<bool> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)?;
+    try_lock(&file)?;
+
+    let mut content = String::new();
+    file.read_to_string(&mut content)?;
+    let pid = std::process::id();
+
+    if let Ok(active) = serde_json::from_str::<Active>(&content) {
+        let recent = DateTime::parse_from_rfc3339(&active.timestamp)
+            .is_ok_and(|timestamp| now.fixed_offset() - timestamp < interval);
+        if active.pid != pid && active.uri == uri && active.is_write == is_write && recent {
+            return Ok(false);
+        }
+    }
+
+    let active = Active {
+        uri: uri.to_string(),
+        timestamp: now.to_rfc3339(),
+        pid,
+        is_write,
+    };
+    file.set_len(0)?;
+    file.rewind()?;
+    file.write_all(&serde_json::to_vec(&active)?)?;
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::{file_lock::lock, test_support::temp_dir};
+
+    const URI: &str = "/home/user/api/src/main.rs";
+
+    fn interval() -> TimeDelta {
+        TimeDelta::minutes(2)
+    }
+
+    fn other_window(path: &Path, now: DateTime<Local>, is_write: bool) {
+        let active = Active {
+            uri: URI.to_string(),
+            timestamp: now.to_rfc3339(),
+            pid: std::process::id() + 1,
+            is_write,
+        };
+        fs::write(path, serde_json::to_vec(&active).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn heartbeat_another_window_just_sent_is_not_claimed() {
+        let path = temp_dir("active-other").join("zed-active.json");
+        let now = Local::now();
+        other_window(&path, now - TimeDelta::seconds(30), false);
+
+        assert!(!try_claim(&path, URI, false, now, interval()).unwrap());
+        assert!(try_claim(&path, URI, true, now, interval()).unwrap());
+        assert!(try_claim(&path, "/home/user/api/src/lib.rs", false, now, interval()).unwrap());
+    }
+
+    #[test]
+    fn heartbeat_is_claimed_once_the_interval_passed() {
+        let path = temp_dir("active-expired").join("zed-active.json");
+        let now = Local::now();
+        other_window(&path, now - interval(), false);
+
+        assert!(try_claim(&path, URI, false, now, interval()).unwrap());
+        let active: Active = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        assert_eq!(active.pid, std::process::id());
+    }
+
+    #[test]
+    fn own_heartbeats_are_always_claimed() {
+        let path = temp_dir("active-own").join("zed-active.json");
+        let now = Local::now();
+
+        assert!(try_claim(&path, URI, false, now, interval()).unwrap());
+        assert!(try_claim(&path, URI, false, now, interval()).unwrap());
+    }
+
+    #[test]
+    fn held_lock_is_not_waited_for() {
+        let path = temp_dir("active-locked").join("zed-active.json");
+        let holder = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .unwrap();
+        lock(&holder).unwrap();
+        let started = Instant::now();
+
+        assert!(try_claim(&path, URI, false, Local::now(), interval()).is_err());
+        assert!(started.elapsed() < std::time::Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,43 @@
+use std::{
+    mem,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+
+use crate::heartbeat::Event;
+
+/// Heartbeats waiting for the next batched wakatime-cli invocation.
+#[derive(Default)]
+pub struct Batch {
+    pending: Mutex<Vec<(Event, f64)>>,
+    scheduled: AtomicBool,
+}
+
+impl Batch {
+    /// Queues a heartbeat, returning true when no flush is scheduled yet and
+    /// the caller should schedule one.
+    pub fn push(&self, event: Event, timestamp: f64) -> bool {
+        self.pending.lock().unwrap().push((event, timestamp));
+        !self.scheduled.swap(true, Ordering::AcqRel)
+    }
+
+    /// Marks the latest queued heartbeat for `uri` as a write if it isn't one,
+    /// returning whether it did.
+    pub fn upgrade_to_write(&self, uri: &str) -> bool {
+        let mut pending = self.pending.lock().unwrap();
+        match pending.iter_mut().rev().find(|(event, _)| event.uri == uri) {
+            Some((event, _)) if !event.is_write => {
+                event.is_write = true;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn take(&self) -> Vec<(Event, f64)> {
+        self.scheduled.store(false, Ordering::Release);
+        mem::take(&mut *self.pending.lock().unwrap())
+    }
+}
@@ -0,0 +1,193 @@
+use std::env;
+
+/// Branch variables set by CI systems, in priority order. GitHub's are
+/// preferred because its checkouts are usually a detached HEAD.
+const BRANCH_VARS: &[&str] = &[
+    "GITHUB_HEAD_REF",
+    "GITHUB_REF_NAME",
+    "CODEBUILD_SOURCE_VERSION",
+    "CI_COMMIT_REF_NAME",
+    "BITBUCKET_BRANCH",
+    "CIRCLE_BRANCH",
+    "TRAVIS_BRANCH",
+];
+
+/// Project variables set by CI systems, in priority order.
+const PROJECT_VARS: &[&str] = &[
+    "CI_PROJECT_NAME",
+    "BITBUCKET_REPO_SLUG",
+    "CIRCLE_PROJECT_REPONAME",
+    "TRAVIS_REPO_SLUG",
+];
+
+/// Picks the branch to send: the `branch` setting, then the editor's hint,
+/// then CI environment variables. `None` leaves detection to wakatime-cli,
+/// which asks git.
+pub fn detect_branch(setting: Option<&str>, editor_hint: Option<&str>) -> Option<String> {
+    pick_branch(setting, editor_hint, env_var)
+}
+
+pub fn detect_ci_branch() -> Option<String> {
+    ci_branch(env_var)
+}
+
+pub fn detect_ci_project() -> Option<String> {
+    ci_project(env_var)
+}
+
+fn env_var(name: &str) -> Option<String> {
+    env::var(name).ok()
+}
+
+fn pick_branch(
+    setting: Option<&str>,
+    editor_hint: Option<&str>,
+    var: impl Fn(&str) -> Option<String>,
+) -> Option<String> {
+    setting
+        .or(editor_hint)
+        .filter(|branch| !branch.is_empty())
+        .map(str::to_string)
+        .or_else(|| ci_branch(var))
+}
+
+fn ci_branch(var: impl Fn(&str) -> Option<String>) -> Option<String> {
+    first_var(BRANCH_VARS, var)
+}
+
+fn ci_project(var: impl Fn(&str) -> Option<String>) -> Option<String> {
+    // TRAVIS_REPO_SLUG is "owner/repo".
+    first_var(PROJECT_VARS, var)
+        .map(|project| match project.rsplit_once('/') {
+            Some((_, repo)) => repo.to_string(),
+            None => project,
+        })
+        .filter(|project| !project.is_empty())
+}
+
+fn first_var(names: &[&str], var: impl Fn(&str) -> Option<String>) -> Option<String> {
+    names
+        .iter()
+        .find_map(|name| var(name).filter(|value| !value.is_empty()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An environment holding only `vars`.
+    fn environment<'a>(vars: &'a [(&'a str, &'a str)]) -> impl Fn(&str) -> Option<String> + 'a {
+        move |name| {
+            vars.iter()
+                .find(|(var, _)| *var == name)
+                .map(|(_, value)| value.to_string())
+        }
+    }
+
+    #[test]
+    fn github_pull_request_branch_wins_over_push_ref() {
+        let vars = [
+            ("GITHUB_REF_NAME", "42/merge"),
+            ("GITHUB_HEAD_REF", "feature/login"),
+        ];
+        assert_eq!(
+            ci_branch(environment(&vars)).as_deref(),
+            Some("feature/login")
+        );
+    }
+
+    #[test]
+    fn github_push_ref_is_used_outside_pull_requests() {
+        let vars = [("GITHUB_HEAD_REF", ""), ("GITHUB_REF_NAME", "main")];
+        assert_eq!(ci_branch(environment(&vars)).as_deref(), Some("main"));
+    }
+
+    #[test]
+    fn codebuild_and_gitlab_follow_github() {
+        let vars = [
+            ("CI_COMMIT_REF_NAME", "gitlab-branch"),
+            ("CODEBUILD_SOURCE_VERSION", "codebuild-branch"),
+        ];
+        assert_eq!(
+            ci_branch(environment(&vars)).as_deref(),
+            Some("codebuild-branch")
+        );
+
+        let vars = [("CI_COMMIT_REF_NAME", "gitlab-branch")];
+        assert_eq!(
+            ci_branch(environment(&vars)).as_deref(),
+            Some("gitlab-branch")
+        );
+    }
+
+    #[test]
+    fn branch_setting_wins_over_hint_and_ci() {
+        let vars = [("GITHUB_HEAD_REF", "feature/login")];
+        assert_eq!(
+            pick_branch(Some("release"), Some("hint"), environment(&vars)).as_deref(),
+            Some("release")
+        );
+        assert_eq!(
+            pick_branch(None, Some("hint"), environment(&vars)).as_deref(),
+            Some("hint")
+        );
+        assert_eq!(
+            pick_branch(Some(""), None, environment(&vars)).as_deref(),
+            Some("feature/login")
+        );
+    }
+
+    #[test]
+    fn no_ci_branch_leaves_detection_to_git() {
+        assert_eq!(pick_branch(None, None, environment(&[])), None);
+    }
+
+    #[test]
+    fn each_ci_system_names_the_project_and_branch() {
+        let systems = [
+            ("CI_PROJECT_NAME", "CI_COMMIT_REF_NAME"),
+            ("BITBUCKET_REPO_SLUG", "BITBUCKET_BRANCH"),
+            ("CIRCLE_PROJECT_REPONAME", "CIRCLE_BRANCH"),
+            ("TRAVIS_REPO_SLUG", "TRAVIS_BRANCH"),
+        ];
+
+        for (project_var, branch_var) in systems {
+            let vars = [(project_var, "my-repo"), (branch_var, "develop")];
+            assert_eq!(
+                ci_project(environment(&vars)).as_deref(),
+                Some("my-repo"),
+                "{project_var}"
+            );
+            assert_eq!(
+                ci_branch(environment(&vars)).as_deref(),
+                Some("develop"),
+                "{branch_var}"
+            );
+        }
+    }
+
+    #[test]
+    fn travis_slug_is_cut_to_the_repository() {
+        let vars = [("TRAVIS_REPO_SLUG", "wakatime/zed-wakatime")];
+        assert_eq!(
+            ci_project(environment(&vars)).as_deref(),
+            Some("zed-wakatime")
+        );
+    }
+
+    #[test]
+    fn gitlab_project_wins_over_other_ci_systems() {
+        let vars = [
+            ("TRAVIS_REPO_SLUG", "owner/travis"),
+            ("CI_PROJECT_NAME", "gitlab"),
+            ("CIRCLE_PROJECT_REPONAME", "circle"),
+        ];
+        assert_eq!(ci_project(environment(&vars)).as_deref(), Some("gitlab"));
+    }
+
+    #[test]
+    fn no_ci_project_outside_ci() {
+        assert_eq!(ci_project(environment(&[])), None);
+        assert_eq!(ci_project(environment(&[("CI_PROJECT_NAME", "")])), None);
+    }
+}
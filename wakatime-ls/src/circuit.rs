@@ -0,0 +1,31 @@
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+/// Stops invoking wakatime-cli after repeated failures so a broken install or
+/// an unreachable API isn't hammered with a process per heartbeat.
+#[derive(Default)]
+pub struct CircuitBreaker {
+    consecutive_failures: AtomicU32,
+    open: AtomicBool,
+}
+
+impl CircuitBreaker {
+    pub fn is_open(&self) -> bool {
+        self.open.load(Ordering::Acquire)
+    }
+
+    pub fn record_success(&self) {
+        self.consecutive_failures.store(0, Ordering::Release);
+    }
+
+    /// Counts a failure, returning true when it opens the circuit. A threshold
+    /// of `0` never opens it.
+    pub fn record_failure(&self, threshold: u32) -> bool {
+        let failures = self.consecutive_failures.fetch_add(1, Ordering::AcqRel) + 1;
+        threshold != 0 && failures >= threshold && !self.open.swap(true, Ordering::AcqRel)
+    }
+
+    pub fn close(&self) {
+        self.consecutive_failures.store(0, Ordering::Release);
+        self.open.store(false, Ordering::Release);
+    }
+}
@@ -0,0 +1,93 @@
+/// Commands handled by `workspace/executeCommand`. `ALL` is both what
+/// `initialize` advertises and what `from_name` dispatches on, so a command
+/// can't be handled without being advertised.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WakatimeCommand {
+    ClearCache,
+    DebugStats,
+    RecentHeartbeats,
+    Today,
+    Version,
+}
+
+impl WakatimeCommand {
+    pub const ALL: &'static [WakatimeCommand] = &[
+        WakatimeCommand::ClearCache,
+        WakatimeCommand::DebugStats,
+        WakatimeCommand::RecentHeartbeats,
+        WakatimeCommand::Today,
+        WakatimeCommand::Version,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            WakatimeCommand::ClearCache => "wakatime.clearCache",
+            WakatimeCommand::DebugStats => "wakatime.debugStats",
+            WakatimeCommand::RecentHeartbeats => "wakatime.recentHeartbeats",
+            WakatimeCommand::Today => "wakatime.today",
+            WakatimeCommand::Version => "wakatime.version",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<WakatimeCommand> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|command| command.name() == name)
+    }
+
+    pub fn advertised() -> Vec<String> {
+        Self::ALL
+            .iter()
+            .map(|command| command.name().to_string())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_command_is_advertised() {
+        let advertised = WakatimeCommand::advertised();
+        for command in [
+            WakatimeCommand::ClearCache,
+            WakatimeCommand::DebugStats,
+            WakatimeCommand::RecentHeartbeats,
+            WakatimeCommand::Today,
+            WakatimeCommand::Version,
+        ] {
+            // Fails to compile when a variant is added, until it's listed
+            // above.
+            match command {
+                WakatimeCommand::ClearCache
+                | WakatimeCommand::DebugStats
+                | WakatimeCommand::RecentHeartbeats
+                | WakatimeCommand::Today
+                | WakatimeCommand::Version => {}
+            }
+
+            assert!(WakatimeCommand::ALL.contains(&command), "{command:?}");
+            assert!(
+                advertised.contains(&command.name().to_string()),
+                "{command:?}"
+            );
+            assert_eq!(WakatimeCommand::from_name(command.name()), Some(command));
+        }
+    }
+
+    #[test]
+    fn command_names_are_unique() {
+        let mut names = WakatimeCommand::advertised();
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), WakatimeCommand::ALL.len());
+    }
+
+    #[test]
+    fn unknown_command_is_not_handled() {
+        assert_eq!(WakatimeCommand::from_name("wakatime.unknown"), None);
+        assert_eq!(WakatimeCommand::from_name("clearCache"), None);
+    }
+}
@@ -0,0 +1,77 @@
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde_json::{Map, Value};
+
+use crate::settings::Setting;
+
+/// wakatime-ls's own settings file, for clients where passing LSP settings is
+/// awkward. It holds the same keys as `lsp.wakatime.settings`, which take
+/// precedence over it.
+pub struct ConfigFile {
+    pub path: PathBuf,
+    values: Map<String, Value>,
+}
+
+/// `$WAKATIME_LS_CONFIG`, else `$XDG_CONFIG_HOME/wakatime-ls/config.toml`,
+/// else `~/.config/wakatime-ls/config.toml`.
+pub fn default_path() -> Option<PathBuf> {
+    if let Some(path) = env::var_os("WAKATIME_LS_CONFIG").filter(|s| !s.is_empty()) {
+        return Some(PathBuf::from(path));
+    }
+
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .filter(|s| !s.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            env::var_os("HOME")
+                .or_else(|| env::var_os("USERPROFILE"))
+                .map(|home| PathBuf::from(home).join(".config"))
+        })?;
+
+    Some(config_home.join("wakatime-ls").join("config.toml"))
+}
+
+impl ConfigFile {
+    /// Reads and parses the file. A missing file isn't an error.
+    pub fn load(path: &Path) -> Result<Option<ConfigFile>, String> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(format!("failed to read {}: {e}", path.display())),
+        };
+
+        let values = toml::from_str(&content)
+            .map_err(|e| format!("failed to parse {}: {e}", path.display()))?;
+
+        Ok(Some(ConfigFile {
+            path: path.to_path_buf(),
+            values,
+        }))
+    }
+
+    /// The file's settings on their own, before any LSP settings arrive.
+    pub fn setting(&self) -> (Setting, Vec<String>) {
+        Setting::from_value_lenient(Value::Object(self.values.clone()))
+    }
+
+    /// Layers settings delivered over LSP on top of the file's.
+    pub fn merge(&self, lsp: Option<Value>) -> Value {
+        let mut merged = self.values.clone();
+        if let Some(Value::Object(lsp)) = lsp {
+            merged.extend(lsp);
+        }
+        Value::Object(merged)
+    }
+
+    /// Keys whose effective value comes from this file rather than LSP.
+    pub fn provided_keys(&self, lsp: Option<&Value>) -> Vec<&str> {
+        self.values
+            .keys()
+            .filter(|key| lsp.and_then(|lsp| lsp.get(key.as_str())).is_none())
+            .map(String::as_str)
+            .collect()
+    }
+}
@@ -0,0 +1,158 @@
+use tower_lsp::lsp_types::{Position, TextDocumentContentChangeEvent};
+
+/// An open document's text, kept in sync with incremental changes.
+pub struct Document {
+    pub text: String,
+    /// Not a file on disk, so its text is sent through `--local-file`.
+    pub unsaved: bool,
+}
+
+impl Document {
+    pub fn apply(&mut self, change: &TextDocumentContentChangeEvent) {
+        match change.range {
+            Some(range) => {
+                let start = offset(&self.text, range.start);
+                let end = offset(&self.text, range.end).max(start);
+                self.text.replace_range(start..end, &change.text);
+            }
+            None => self.text = change.text.clone(),
+        }
+    }
+
+    pub fn line_count(&self) -> u64 {
+        self.text.lines().count() as u64
+    }
+}
+
+/// Where the cursor ends up after a change: past the inserted text, as
+/// (line, UTF-16 column) like LSP positions.
+pub fn cursor_after(change: &TextDocumentContentChangeEvent) -> Option<(u64, u64)> {
+    let start = change.range?.start;
+
+    Some(match change.text.rsplit_once('\n') {
+        Some((before, last_line)) => (
+            start.line as u64 + before.matches('\n').count() as u64 + 1,
+            utf16_len(last_line),
+        ),
+        None => (
+            start.line as u64,
+            start.character as u64 + utf16_len(&change.text),
+        ),
+    })
+}
+
+/// The byte offset of an LSP position, whose column counts UTF-16 code units.
+/// Positions past the end of a line or of the text are clamped to it.
+fn offset(text: &str, position: Position) -> usize {
+    let mut line_start = 0;
+    for _ in 0..position.line {
+        match text[line_start..].find('\n') {
+            Some(newline) => line_start += newline + 1,
+            None => return text.len(),
+        }
+    }
+
+    let line = &text[line_start..];
+    let line = &line[..line.find('\n').unwrap_or(line.len())];
+    let mut units = 0;
+    for (index, c) in line.char_indices() {
+        if units >= position.character as usize {
+            return line_start + index;
+        }
+        units += c.len_utf16();
+    }
+
+    line_start + line.len()
+}
+
+fn utf16_len(text: &str) -> u64 {
+    text.encode_utf16().count() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use tower_lsp::lsp_types::Range;
+
+    use super::*;
+
+    fn insert(line: u32, character: u32, text: &str) -> TextDocumentContentChangeEvent {
+        let position = Position::new(line, character);
+        TextDocumentContentChangeEvent {
+            range: Some(Range::new(position, position)),
+            range_length: None,
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn cursor_follows_inserted_text() {
+        assert_eq!(cursor_after(&insert(3, 4, "abc")), Some((3, 7)));
+        assert_eq!(cursor_after(&insert(3, 4, "a\nbc\ndef")), Some((5, 3)));
+        // Columns count UTF-16 code units: the emoji is two.
+        assert_eq!(cursor_after(&insert(0, 0, "é😀")), Some((0, 3)));
+    }
+
+    #[test]
+    fn full_document_change_has_no_cursor() {
+        let change = TextDocumentContentChangeEvent {
+            range: None,
+            range_length: None,
+            text: "fn main() {}".to_string(),
+        };
+        assert_eq!(cursor_after(&change), None);
+    }
+
+    #[test]
+    fn changes_apply_to_the_text() {
+        let mut document = Document {
+            text: "fn main() {\n}\n".to_string(),
+            unsaved: false,
+        };
+
+        document.apply(&insert(0, 11, "\n    let x = 1;"));
+        assert_eq!(document.text, "fn main() {\n    let x = 1;\n}\n");
+        assert_eq!(document.line_count(), 3);
+
+        // Positions past the end are clamped.
+        document.apply(&insert(9, 0, "// end"));
+        assert_eq!(document.text, "fn main() {\n    let x = 1;\n}\n// end");
+
+        document.apply(&TextDocumentContentChangeEvent {
+            range: None,
+            range_length: None,
+            text: "x".to_string(),
+        });
+        assert_eq!(document.text, "x");
+    }
+
+    #[test]
+    fn deletions_reduce_the_line_count() {
+        let mut document = Document {
+            text: "a\nb\nc\nd\n".to_string(),
+            unsaved: false,
+        };
+
+        document.apply(&TextDocumentContentChangeEvent {
+            range: Some(Range::new(Position::new(1, 0), Position::new(3, 0))),
+            range_length: None,
+            text: String::new(),
+        });
+        assert_eq!(document.text, "a\nd\n");
+        assert_eq!(document.line_count(), 2);
+    }
+
+    #[test]
+    fn full_document_change_replaces_the_line_count() {
+        let mut document = Document {
+            text: "a\nb\nc\n".to_string(),
+            unsaved: false,
+        };
+
+        document.apply(&TextDocumentContentChangeEvent {
+            range: None,
+            range_length: None,
+            text: "one line".to_string(),
+        });
+        assert_eq!(document.line_count(), 1);
+    }
+}
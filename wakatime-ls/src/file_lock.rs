@@ -0,0 +1,59 @@
+use std::{fs::File, io};
+
+/// Takes an exclusive lock on `file`, released when it's closed.
+pub fn lock(file: &File) -> io::Result<()> {
+    lock_file(file, true)
+}
+
+/// Like `lock`, but fails instead of waiting while another process holds it.
+pub fn try_lock(file: &File) -> io::Result<()> {
+    lock_file(file, false)
+}
+
+#[cfg(unix)]
+fn lock_file(file: &File, wait: bool) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let operation = if wait {
+        libc::LOCK_EX
+    } else {
+        libc::LOCK_EX | libc::LOCK_NB
+    };
+    if unsafe { libc::flock(file.as_raw_fd(), operation) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(windows)]
+fn lock_file(file: &File, wait: bool) -> io::Result<()> {
+    use std::os::windows::io::AsRawHandle;
+    use winapi::um::{
+        fileapi::LockFileEx,
+        minwinbase::{LOCKFILE_EXCLUSIVE_LOCK, LOCKFILE_FAIL_IMMEDIATELY, OVERLAPPED},
+    };
+
+    let flags = if wait {
+        LOCKFILE_EXCLUSIVE_LOCK
+    } else {
+        LOCKFILE_EXCLUSIVE_LOCK | LOCKFILE_FAIL_IMMEDIATELY
+    };
+    let mut overlapped: OVERLAPPED = unsafe { std::mem::zeroed() };
+    let locked = unsafe {
+        LockFileEx(
+            file.as_raw_handle() as _,
+            flags,
+            0,
+            u32::MAX,
+            u32::MAX,
+            &mut overlapped,
+        )
+    };
+
+    if locked != 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
@@ -0,0 +1,208 @@
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
+use regex::{Regex, RegexBuilder};
+
+/// Prefix marking a pattern as a regular expression rather than a glob.
+const REGEX_PREFIX: &str = "regex:";
+
+/// The `exclude`/`include` settings: entities matching an exclude pattern
+/// aren't tracked unless an include pattern matches them too.
+#[derive(Default)]
+pub struct EntityFilter {
+    exclude: Patterns,
+    include: Patterns,
+}
+
+impl EntityFilter {
+    /// Compiles the patterns, returning the ones that failed alongside.
+    pub fn new(exclude: &[String], include: &[String]) -> (Self, Vec<String>) {
+        let mut invalid = Vec::new();
+        let exclude = Patterns::new(exclude, &mut invalid);
+        let include = Patterns::new(include, &mut invalid);
+
+        (Self { exclude, include }, invalid)
+    }
+
+    pub fn excludes(&self, entity: &str) -> bool {
+        if self.exclude.is_empty() {
+            return false;
+        }
+
+        let entity = entity.replace('\\', "/");
+        self.exclude.is_match(&entity) && !self.include.is_match(&entity)
+    }
+}
+
+/// Globs, plus regexes for patterns starting with `regex:`.
+#[derive(Default)]
+struct Patterns {
+    globs: GlobSet,
+    regexes: Vec<Regex>,
+}
+
+impl Patterns {
+    fn new(patterns: &[String], invalid: &mut Vec<String>) -> Self {
+        let mut globs = GlobSetBuilder::new();
+        let mut regexes = Vec::new();
+
+        for pattern in patterns {
+            if let Some(regex) = pattern.strip_prefix(REGEX_PREFIX) {
+                match build_regex(regex) {
+                    Some(regex) => regexes.push(regex),
+                    None => invalid.push(pattern.clone()),
+                }
+            } else {
+                match build_glob(pattern) {
+                    Some(glob) => {
+                        globs.add(glob);
+                    }
+                    None => invalid.push(pattern.clone()),
+                }
+            }
+        }
+
+        Self {
+            globs: globs.build().unwrap_or_else(|_| GlobSet::empty()),
+            regexes,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.globs.is_empty() && self.regexes.is_empty()
+    }
+
+    fn is_match(&self, entity: &str) -> bool {
+        self.globs.is_match(entity) || self.regexes.iter().any(|regex| regex.is_match(entity))
+    }
+}
+
+/// Globs match against the full path with `/` separators. Relative ones,
+/// like `node_modules/**`, match at any depth. `*` stays within one path
+/// component; only `**` crosses directories.
+fn build_glob(pattern: &str) -> Option<Glob> {
+    let normalized = pattern.replace('\\', "/");
+    let anchored = normalized.starts_with('/')
+        || normalized.starts_with("**")
+        || normalized.as_bytes().get(1) == Some(&b':');
+    let normalized = if anchored {
+        normalized
+    } else {
+        format!("**/{normalized}")
+    };
+
+    GlobBuilder::new(&normalized)
+        .literal_separator(true)
+        .case_insensitive(cfg!(windows))
+        .build()
+        .ok()
+}
+
+/// Regexes search the path with `/` separators, like wakatime-cli's own
+/// `exclude`, so they match anywhere unless anchored with `^` or `$`.
+fn build_regex(pattern: &str) -> Option<Regex> {
+    RegexBuilder::new(pattern)
+        .case_insensitive(cfg!(windows))
+        .build()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(exclude: &[&str], include: &[&str]) -> EntityFilter {
+        let strings =
+            |patterns: &[&str]| patterns.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+        let (filter, invalid) = EntityFilter::new(&strings(exclude), &strings(include));
+        assert!(invalid.is_empty(), "{invalid:?}");
+        filter
+    }
+
+    #[test]
+    fn star_does_not_cross_directories() {
+        let filter = filter(&["/home/me/*.rs"], &[]);
+        assert!(filter.excludes("/home/me/main.rs"));
+        assert!(!filter.excludes("/home/me/src/main.rs"));
+    }
+
+    #[test]
+    fn double_star_crosses_directories() {
+        let filter = filter(&["/home/me/private/**"], &[]);
+        assert!(filter.excludes("/home/me/private/notes.md"));
+        assert!(filter.excludes("/home/me/private/a/b/notes.md"));
+        assert!(!filter.excludes("/home/me/public/notes.md"));
+    }
+
+    #[test]
+    fn relative_patterns_match_at_any_depth() {
+        let filter = filter(&["node_modules/**", "*.log"], &[]);
+        assert!(filter.excludes("/app/node_modules/react/index.js"));
+        assert!(filter.excludes("/app/packages/ui/node_modules/x.js"));
+        assert!(filter.excludes("/app/debug.log"));
+        assert!(filter.excludes("/app/logs/deep/debug.log"));
+        assert!(!filter.excludes("/app/src/index.js"));
+    }
+
+    #[test]
+    fn relative_pattern_with_a_separator_matches_whole_components() {
+        let filter = filter(&["target/*.rs"], &[]);
+        assert!(filter.excludes("/app/target/build.rs"));
+        assert!(!filter.excludes("/app/target/debug/build.rs"));
+        assert!(!filter.excludes("/app/mytarget/build.rs"));
+    }
+
+    #[test]
+    fn backslash_paths_and_patterns_match_like_slashes() {
+        let filter = filter(&["C:\\Users\\me\\private\\**", "dist\\*.js"], &[]);
+        assert!(filter.excludes("C:\\Users\\me\\private\\notes.md"));
+        assert!(filter.excludes("C:/Users/me/private/notes.md"));
+        assert!(filter.excludes("D:\\app\\dist\\bundle.js"));
+        assert!(!filter.excludes("D:\\app\\dist\\chunks\\bundle.js"));
+    }
+
+    #[test]
+    fn include_overrides_exclude() {
+        let filter = filter(&["target/**"], &["target/doc/**"]);
+        assert!(filter.excludes("/app/target/debug/main"));
+        assert!(!filter.excludes("/app/target/doc/index.html"));
+    }
+
+    #[test]
+    fn regex_patterns_search_the_path() {
+        let filter = filter(&[r"regex:/vendor/", r"regex:\.generated\.\w+$"], &[]);
+        assert!(filter.excludes("/app/vendor/lib/a.go"));
+        assert!(filter.excludes("C:\\app\\vendor\\a.go"));
+        assert!(filter.excludes("/app/src/schema.generated.ts"));
+        assert!(!filter.excludes("/app/src/schema.ts"));
+        assert!(!filter.excludes("/app/vendored.go"));
+    }
+
+    #[test]
+    fn regex_patterns_are_not_globs() {
+        let filter = filter(&["regex:^/app/[^/]*\\.log$"], &["regex:keep"]);
+        assert!(filter.excludes("/app/debug.log"));
+        assert!(!filter.excludes("/app/logs/debug.log"));
+        assert!(!filter.excludes("/app/keep.log"));
+    }
+
+    #[test]
+    fn patterns_ignore_case_only_on_windows() {
+        let filter = filter(&["target/**", "regex:/Vendor/"], &[]);
+        assert_eq!(filter.excludes("/app/TARGET/main"), cfg!(windows));
+        assert_eq!(filter.excludes("/app/vendor/a.go"), cfg!(windows));
+    }
+
+    #[test]
+    fn invalid_regexes_are_reported() {
+        let (filter, invalid) =
+            EntityFilter::new(&["regex:(".to_string(), "regex:\\.tmp$".to_string()], &[]);
+        assert_eq!(invalid, ["regex:("]);
+        assert!(filter.excludes("/app/a.tmp"));
+    }
+
+    #[test]
+    fn invalid_patterns_are_reported() {
+        let (filter, invalid) = EntityFilter::new(&["[".to_string(), "*.tmp".to_string()], &[]);
+        assert_eq!(invalid, ["["]);
+        assert!(filter.excludes("/app/a.tmp"));
+    }
+}
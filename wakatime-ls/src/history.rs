@@ -0,0 +1,181 @@
+use std::{collections::VecDeque, path::Path, sync::Mutex};
+
+use chrono::Local;
+use serde_json::{json, Value};
+
+use crate::{heartbeat::Event, settings::Setting};
+
+/// Number of heartbeats kept for `wakatime.recentHeartbeats`.
+pub const HISTORY_SIZE: usize = 50;
+
+/// Stands in for names the `hide_*` settings hide, like wakatime-cli does.
+const HIDDEN: &str = "HIDDEN";
+
+/// What happened to a recorded heartbeat.
+#[derive(Debug, Clone, Copy)]
+pub enum Outcome {
+    /// Dropped by the interval throttle.
+    Suppressed,
+    /// Handed to wakatime-cli; no exit code when it couldn't be run or was
+    /// terminated.
+    Sent { exit_code: Option<i32> },
+    /// Built but not run because of `dry_run`.
+    DryRun,
+}
+
+/// The last heartbeats handed to wakatime-cli or throttled, for debugging
+/// missing or wrong data. Entries are redacted when recorded, so the lock
+/// only guards a push: names the `hide_*` settings hide never get in.
+#[derive(Default)]
+pub struct History {
+    entries: Mutex<VecDeque<Value>>,
+}
+
+impl History {
+    pub fn record(&self, event: &Event, timestamp: f64, outcome: Outcome, settings: &Setting) {
+        let (suppressed, exit_code, dry_run) = match outcome {
+            Outcome::Suppressed => (true, None, false),
+            Outcome::Sent { exit_code } => (false, exit_code, false),
+            Outcome::DryRun => (false, Some(-1), true),
+        };
+        let entity = if settings.hide_file_names.hides(&event.uri) {
+            hidden_file_name(&event.uri)
+        } else {
+            file_name(&event.uri)
+        };
+        let hide_project = settings.hide_project_names.hides(&event.uri);
+        let hide_branch = settings.hide_branch_names.hides(&event.uri);
+
+        let entry = json!({
+            "recorded_at": Local::now().to_rfc3339(),
+            "timestamp": timestamp,
+            "entity": entity,
+            "is_write": event.is_write,
+            "category": "coding",
+            "language": event.language,
+            "lineno": event.lineno,
+            "cursor_pos": event.cursor_pos,
+            "project": hidden(&event.project, hide_project),
+            "alternate_project": hidden(&event.alternate_project, hide_project),
+            "branch": hidden(&event.branch, hide_branch),
+            "suppressed": suppressed,
+            "cli_exit_code": exit_code,
+            "dry_run": dry_run,
+        });
+
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() == HISTORY_SIZE {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+
+    /// The last `count` entries, oldest first.
+    pub fn recent(&self, count: usize) -> Value {
+        let entries = self.entries.lock().unwrap();
+        let skip = entries.len().saturating_sub(count);
+        Value::Array(entries.iter().skip(skip).cloned().collect())
+    }
+}
+
+/// Keeps only the file name so exported history doesn't reveal directory
+/// layouts.
+fn file_name(entity: &str) -> String {
+    Path::new(entity)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+/// A hidden file name keeps only its extension, as wakatime-cli sends it.
+fn hidden_file_name(entity: &str) -> String {
+    match Path::new(entity).extension() {
+        Some(extension) => format!("{HIDDEN}.{}", extension.to_string_lossy()),
+        None => HIDDEN.to_string(),
+    }
+}
+
+fn hidden(name: &Option<String>, hide: bool) -> Option<&str> {
+    name.as_deref().map(|name| if hide { HIDDEN } else { name })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Hide;
+
+    fn recorded(settings: &Setting) -> Value {
+        let history = History::default();
+        let event = Event {
+            uri: "/home/user/secret-client/src/billing.rs".to_string(),
+            project: Some("secret-client".to_string()),
+            alternate_project: Some("monorepo".to_string()),
+            branch: Some("feature/acme-deal".to_string()),
+            ..Default::default()
+        };
+        history.record(&event, 1.0, Outcome::Sent { exit_code: Some(0) }, settings);
+        history.recent(1)[0].clone()
+    }
+
+    #[test]
+    fn names_are_recorded_when_nothing_is_hidden() {
+        let entry = recorded(&Setting::default());
+        assert_eq!(entry["entity"], "billing.rs");
+        assert_eq!(entry["project"], "secret-client");
+        assert_eq!(entry["alternate_project"], "monorepo");
+        assert_eq!(entry["branch"], "feature/acme-deal");
+    }
+
+    #[test]
+    fn hidden_names_are_never_recorded() {
+        let entry = recorded(&Setting {
+            hide_file_names: Hide::All(true),
+            hide_project_names: Hide::All(true),
+            hide_branch_names: Hide::All(true),
+            ..Default::default()
+        });
+        assert_eq!(entry["entity"], "HIDDEN.rs");
+        assert_eq!(entry["project"], "HIDDEN");
+        assert_eq!(entry["alternate_project"], "HIDDEN");
+        assert_eq!(entry["branch"], "HIDDEN");
+    }
+
+    #[test]
+    fn names_of_matching_entities_are_hidden() {
+        let entry = recorded(&Setting {
+            hide_project_names: Hide::Matching(vec!["/secret-".to_string()]),
+            hide_branch_names: Hide::Matching(vec!["/public/".to_string()]),
+            ..Default::default()
+        });
+        assert_eq!(entry["entity"], "billing.rs");
+        assert_eq!(entry["project"], "HIDDEN");
+        assert_eq!(entry["branch"], "feature/acme-deal");
+    }
+
+    #[test]
+    fn missing_names_stay_missing_when_hidden() {
+        let history = History::default();
+        let settings = Setting {
+            hide_file_names: Hide::All(true),
+            hide_project_names: Hide::All(true),
+            ..Default::default()
+        };
+        history.record(
+            &Event {
+                uri: "/home/user/Makefile".to_string(),
+                ..Default::default()
+            },
+            1.0,
+            Outcome::Suppressed,
+            &settings,
+        );
+
+        let entry = &history.recent(1)[0];
+        assert_eq!(entry["entity"], "HIDDEN");
+        assert_eq!(entry["project"], Value::Null);
+    }
+}
@@ -0,0 +1,167 @@
+use std::{collections::HashMap, path::Path};
+
+/// Container formats, by extension, that are attributed to their own language
+/// whatever id the client reports for the region being edited, so a
+/// `<script>` block in a `.vue` file still counts as Vue.
+const CONTAINER_LANGUAGES: &[(&str, &str)] = &[
+    ("vue", "Vue"),
+    ("svelte", "Svelte"),
+    ("astro", "Astro"),
+    ("mdx", "MDX"),
+];
+
+/// Zed language ids, lowercased language names, and the name WakaTime uses
+/// for each language.
+const WAKATIME_NAMES: &[(&str, &str)] = &[
+    ("asciidoc", "AsciiDoc"),
+    ("astro", "Astro"),
+    ("bash", "Bash"),
+    ("binary", "Binary"),
+    ("biome", "JSON"),
+    ("c", "C"),
+    ("c++", "C++"),
+    ("clojure", "Clojure"),
+    ("csharp", "C#"),
+    ("css", "CSS"),
+    ("csv", "CSV"),
+    ("d", "D"),
+    ("dart", "Dart"),
+    ("elixir", "Elixir"),
+    ("elm", "Elm"),
+    ("erlang", "Erlang"),
+    ("fish", "Fish"),
+    ("fsharp", "F#"),
+    ("gdscript", "GDScript"),
+    ("gleam", "Gleam"),
+    ("go", "Go"),
+    ("graphql", "GraphQL"),
+    ("haskell", "Haskell"),
+    ("heex", "HEEx"),
+    ("html", "HTML"),
+    ("hy", "Hy"),
+    ("idris", "Idris"),
+    ("java", "Java"),
+    ("javascript", "JavaScript"),
+    ("json", "JSON"),
+    ("jsonc", "JSON"),
+    ("julia", "Julia"),
+    ("kotlin", "Kotlin"),
+    ("lua", "Lua"),
+    ("markdown", "Markdown"),
+    ("mdx", "MDX"),
+    ("nim", "Nim"),
+    ("nix", "Nix"),
+    ("ocaml", "OCaml"),
+    ("plaintext", "Text"),
+    ("purescript", "PureScript"),
+    ("python", "Python"),
+    ("racket", "Racket"),
+    ("roc", "Roc"),
+    ("ruby", "Ruby"),
+    ("rust", "Rust"),
+    ("scheme", "Scheme"),
+    ("scss", "SCSS"),
+    ("shell script", "Bash"),
+    ("sql", "SQL"),
+    ("svelte", "Svelte"),
+    ("swift", "Swift"),
+    ("toml", "TOML"),
+    ("tsx", "TSX"),
+    ("typescript", "TypeScript"),
+    ("vue", "Vue"),
+    ("wit", "WIT"),
+    ("xml", "XML"),
+    ("yaml", "YAML"),
+    ("zig", "Zig"),
+];
+
+/// The WakaTime name of a language id, looked up in the user's
+/// `language_mappings` first, then the built-in table; both ignore case.
+/// `None` for ids neither knows.
+pub fn wakatime_name(language: &str, mappings: &HashMap<String, String>) -> Option<String> {
+    mappings
+        .iter()
+        .find(|(id, _)| id.eq_ignore_ascii_case(language))
+        .map(|(_, name)| name.clone())
+        .or_else(|| {
+            WAKATIME_NAMES
+                .iter()
+                .find(|(id, _)| id.eq_ignore_ascii_case(language))
+                .map(|(_, name)| name.to_string())
+        })
+}
+
+/// The language reported for `entity`, given the client's language id.
+pub fn resolve(entity: &str, language_id: &str) -> String {
+    let extension = Path::new(entity)
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase());
+
+    extension
+        .and_then(|extension| {
+            CONTAINER_LANGUAGES
+                .iter()
+                .find(|(ext, _)| *ext == extension)
+        })
+        .map_or_else(
+            || language_id.to_string(),
+            |(_, language)| language.to_string(),
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn container_files_keep_their_own_language() {
+        for (entity, language) in [
+            ("/app/src/App.vue", "Vue"),
+            ("/app/src/routes/+page.svelte", "Svelte"),
+            ("/app/src/pages/index.astro", "Astro"),
+            ("/app/docs/intro.mdx", "MDX"),
+        ] {
+            for language_id in ["typescript", "css", "html", "javascript"] {
+                assert_eq!(resolve(entity, language_id), language, "{entity}");
+            }
+        }
+    }
+
+    #[test]
+    fn container_extensions_ignore_case() {
+        assert_eq!(resolve("/app/src/App.VUE", "typescript"), "Vue");
+        assert_eq!(resolve("/app/docs/Intro.Mdx", "markdown"), "MDX");
+    }
+
+    #[test]
+    fn other_files_keep_the_client_language() {
+        assert_eq!(resolve("/app/src/main.ts", "typescript"), "typescript");
+        assert_eq!(resolve("/app/README.md", "markdown"), "markdown");
+        assert_eq!(resolve("/app/Makefile", "makefile"), "makefile");
+        // Only the extension counts, not a directory named like one.
+        assert_eq!(resolve("/app/vue/main.ts", "typescript"), "typescript");
+    }
+
+    #[test]
+    fn container_languages_are_in_the_name_table() {
+        let mappings = HashMap::new();
+        for (extension, language) in CONTAINER_LANGUAGES {
+            assert_eq!(
+                wakatime_name(extension, &mappings).as_deref(),
+                Some(*language)
+            );
+            assert_eq!(
+                wakatime_name(language, &mappings).as_deref(),
+                Some(*language)
+            );
+        }
+    }
+
+    #[test]
+    fn user_mappings_take_precedence_and_ignore_case() {
+        let mappings = HashMap::from([("Vue".to_string(), "Vue.js".to_string())]);
+        assert_eq!(wakatime_name("vue", &mappings).as_deref(), Some("Vue.js"));
+        assert_eq!(wakatime_name("RUST", &mappings).as_deref(), Some("Rust"));
+        assert_eq!(wakatime_name("brainfuck", &mappings), None);
+    }
+}
@@ -0,0 +1,92 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// A copy of a document that isn't on disk, written for `--local-file` so
+/// wakatime-cli can read its content. Removed when dropped, so it's cleaned up
+/// whether or not the cli succeeded.
+pub struct LocalFile {
+    path: PathBuf,
+}
+
+impl LocalFile {
+    /// Writes `content` to a unique file in `dir`, keeping the entity's file
+    /// name so language detection still works.
+    pub fn create(dir: &Path, entity: &str, content: &str) -> io::Result<LocalFile> {
+        fs::create_dir_all(dir)?;
+
+        let name = Path::new(entity)
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| "untitled".to_string());
+        let file = LocalFile {
+            path: dir.join(format!(
+                "wakatime-ls-{}-{}-{name}",
+                process::id(),
+                NEXT_ID.fetch_add(1, Ordering::Relaxed)
+            )),
+        };
+
+        fs::write(&file.path, content)?;
+        Ok(file)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for LocalFile {
+    fn drop(&mut self) {
+        fs::remove_file(&self.path).ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_dir;
+
+    #[test]
+    fn copy_keeps_the_file_name_and_content() {
+        let dir = temp_dir("local-file").join("nested");
+
+        let file = LocalFile::create(&dir, "untitled:/notes/todo.md", "- [ ] ship").unwrap();
+
+        assert!(file.path().starts_with(&dir));
+        assert!(file
+            .path()
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .ends_with("-todo.md"));
+        assert_eq!(fs::read_to_string(file.path()).unwrap(), "- [ ] ship");
+    }
+
+    #[test]
+    fn copy_is_removed_when_dropped() {
+        let dir = temp_dir("local-file-drop");
+        let first = LocalFile::create(&dir, "/remote/main.rs", "fn main() {}").unwrap();
+        let second = LocalFile::create(&dir, "/remote/main.rs", "fn main() {}").unwrap();
+        assert_ne!(first.path(), second.path());
+
+        drop(first);
+        drop(second);
+
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn unwritable_dir_is_an_error() {
+        let dir = temp_dir("local-file-unwritable");
+        let not_a_dir = dir.join("file");
+        fs::write(&not_a_dir, "").unwrap();
+
+        assert!(LocalFile::create(&not_a_dir, "/remote/main.rs", "").is_err());
+    }
+}
@@ -1,249 +1,3549 @@
-use std::sync::Arc;
+mod active;
+mod batch;
+mod ci;
+mod circuit;
+mod commands;
+mod config_file;
+mod diagnostics;
+mod document;
+mod file_lock;
+mod filter;
+mod heartbeat;
+mod history;
+mod language;
+mod local_file;
+mod paths;
+mod process;
+mod project;
+mod proxy;
+mod queue;
+mod retry;
+mod runner;
+mod schedule;
+mod settings;
+mod setup;
+mod stats;
+mod supervisor;
+#[cfg(test)]
+mod test_support;
+mod today;
+mod version;
 
-use arc_swap::ArcSwap;
+use std::{
+    collections::HashMap,
+    future::Future,
+    io::IsTerminal,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, OnceLock, Weak,
+    },
+    time::{Duration, Instant},
+};
+
+use arc_swap::{ArcSwap, ArcSwapOption};
 use chrono::{DateTime, Local, TimeDelta};
-use clap::{Arg, Command};
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use clap_complete::Shell;
 use serde::Deserialize;
-use tokio::{process::Command as TokioCommand, sync::Mutex};
+use tokio::{
+    sync::{oneshot, Mutex, Semaphore},
+    task::JoinHandle,
+};
 use tower_lsp::{jsonrpc::Result, lsp_types::*, Client, LanguageServer, LspService, Server};
 
-#[derive(Deserialize, Default)]
-struct Setting {
-    api_key: Option<String>,
-    api_url: Option<String>,
-}
+use batch::Batch;
+use circuit::CircuitBreaker;
+use commands::WakatimeCommand;
+use config_file::ConfigFile;
+use diagnostics::LogBuffer;
+use document::Document;
+use filter::EntityFilter;
+use heartbeat::{Event, Heartbeat};
+use history::{History, Outcome, HISTORY_SIZE};
+use local_file::LocalFile;
+use paths::SymlinkCache;
+use project::{DetectedProjects, ProjectRoots};
+use retry::RetryQueue;
+use runner::{CommandRunner, TokioRunner};
+use settings::{Hide, MissingKeyBehavior, Setting, SpawnStrategy};
+use stats::Stats;
 
-#[derive(Default, Debug)]
-struct Event {
-    uri: String,
-    is_write: bool,
-    language: Option<String>,
-    lineno: Option<u64>,
-    cursor_pos: Option<u64>,
+/// How long shutdown waits for spawned tasks before aborting them.
+const TASK_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// A save this soon after a change heartbeat for the same file replaces it
+/// instead of sending a second one.
+const SAVE_COLLAPSE_WINDOW: Duration = Duration::from_secs(3);
+
+/// The first change this soon after opening a file is Zed settling the
+/// buffer (formatting, settings), so it's folded into the open heartbeat.
+const OPEN_COLLAPSE_WINDOW: Duration = Duration::from_secs(1);
+
+/// How long shutdown waits for queued heartbeats before terminating the cli
+/// and persisting them.
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// The same warning is shown at most once per this period.
+const WARNING_DEBOUNCE: Duration = Duration::from_secs(5 * 60);
+
+/// Longest wakatime-cli stderr excerpt shown in a warning.
+const STDERR_SNIPPET_CHARS: usize = 200;
+
+/// Attempts at pulling configuration after `initialized`, the delay growing
+/// by `CONFIGURATION_RETRY_DELAY` after each failure.
+const CONFIGURATION_ATTEMPTS: u32 = 3;
+const CONFIGURATION_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// What `send_at` did with a heartbeat.
+enum SendOutcome {
+    /// Excluded, outside the schedule or dropped without an API key.
+    Skipped,
+    /// Throttled, here or by another window's server.
+    Throttled,
+    /// Held for a batch, or persisted until wakatime-cli can be run.
+    Queued,
+    /// Built but not run because of `dry_run`.
+    DryRun,
+    /// Handed to wakatime-cli. Yields its exit code once it's done, none when
+    /// it couldn't be run or was terminated.
+    Sent(oneshot::Receiver<Option<i32>>),
 }
 
 #[derive(Debug)]
 struct CurrentFile {
     uri: String,
     timestamp: DateTime<Local>,
+    /// Last write heartbeat for `uri`, throttled separately from non-writes.
+    write_timestamp: Option<DateTime<Local>>,
+    last_was_write: bool,
+    /// The last heartbeat was for opening `uri`, with no event since.
+    last_was_open: bool,
+    /// A change to `uri` was throttled since the last heartbeat.
+    changed_since: bool,
+}
+
+/// Whether a heartbeat for `uri` at `now` goes out given the previous one:
+/// always for another file, and for the same file once its interval has
+/// passed, which for writes is the time since the last write.
+fn should_send(
+    prev: &CurrentFile,
+    now: DateTime<Local>,
+    uri: &str,
+    is_write: bool,
+    settings: &Setting,
+) -> bool {
+    if uri != prev.uri {
+        return true;
+    }
+
+    if is_write {
+        prev.write_timestamp
+            .map_or(true, |last| now - last >= settings.write_interval())
+    } else {
+        now - prev.timestamp >= settings.interval()
+    }
+}
+
+impl CurrentFile {
+    fn record_throttled(&mut self, event: &Event) {
+        if !event.is_write && event.uri == self.uri {
+            self.changed_since = true;
+        }
+    }
+
+    /// Whether `event` is a save shortly after a change heartbeat for the same
+    /// file, and so could be folded into it.
+    fn follows_change(&self, event: &Event, now: DateTime<Local>) -> bool {
+        event.is_write
+            && event.uri == self.uri
+            && !self.last_was_write
+            && (now - self.timestamp)
+                .to_std()
+                .is_ok_and(|elapsed| elapsed < SAVE_COLLAPSE_WINDOW)
+    }
+
+    /// Whether `event` is the change Zed sends right after opening the file.
+    fn follows_open(&self, event: &Event, now: DateTime<Local>) -> bool {
+        !event.is_write
+            && !event.is_open
+            && event.uri == self.uri
+            && self.last_was_open
+            && (now - self.timestamp)
+                .to_std()
+                .is_ok_and(|elapsed| elapsed < OPEN_COLLAPSE_WINDOW)
+    }
+
+    fn record(&mut self, event: &Event, now: DateTime<Local>) {
+        if event.uri != self.uri {
+            self.uri = event.uri.clone();
+            self.write_timestamp = None;
+        }
+
+        self.timestamp = now;
+        self.last_was_write = event.is_write;
+        self.last_was_open = event.is_open;
+        self.changed_since = false;
+        if event.is_write {
+            self.write_timestamp = Some(now);
+        }
+
+        if event.is_close {
+            self.forget();
+        }
+    }
+
+    /// Drops the throttle state, so the next heartbeat for the file that was
+    /// current goes out whenever it comes.
+    fn forget(&mut self) {
+        self.uri.clear();
+        self.write_timestamp = None;
+        self.last_was_write = false;
+        self.last_was_open = false;
+        self.changed_since = false;
+    }
 }
 
 struct WakatimeLanguageServer {
+    /// Handle to the server itself for work spawned past the current request.
+    this: Weak<WakatimeLanguageServer>,
     client: Client,
     settings: ArcSwap<Setting>,
     wakatime_path: String,
     current_file: Mutex<CurrentFile>,
     platform: ArcSwap<String>,
+    stats: Arc<Stats>,
+    history: History,
+    /// Number of heartbeats sent so far, see `Event::sequence`.
+    sequence: AtomicU64,
+    batch: Arc<Batch>,
+    status_format_warned: AtomicBool,
+    /// Throttle heartbeats against other windows' servers too, see
+    /// `active::claim`.
+    claims_across_windows: AtomicBool,
+    /// The client accepts a registration for `workspace/didChangeConfiguration`.
+    configuration_registration: AtomicBool,
+    last_change: Mutex<Option<DateTime<Local>>>,
+    extension_version: Option<String>,
+    release_channel: Option<String>,
+    config_file: Option<ConfigFile>,
+    /// Settings last received over LSP, before the config file is merged in.
+    lsp_settings: ArcSwapOption<serde_json::Value>,
+    /// Why the config file couldn't be loaded, reported once after startup.
+    config_file_error: Option<String>,
+    /// Last known (line, cursor) per entity, from `did_change`.
+    cursors: Mutex<HashMap<String, (u64, u64)>>,
+    /// Latest document version per entity; reset by `did_open`.
+    versions: Mutex<HashMap<String, i32>>,
+    /// Language id per entity, from `did_open`.
+    languages: Mutex<HashMap<String, String>>,
+    /// Text of each open document, for `--lines-in-file` and, when it isn't
+    /// a file on disk, `--local-file`.
+    documents: Mutex<HashMap<String, Document>>,
+    workspace_folders: ArcSwap<Vec<PathBuf>>,
+    project_roots: ArcSwap<ProjectRoots>,
+    detected_projects: DetectedProjects,
+    filter: ArcSwap<EntityFilter>,
+    initialized_at: OnceLock<Instant>,
+    /// Branch reported by the editor through `wakatime/branch`.
+    branch_hint: ArcSwapOption<String>,
+    symlinks: SymlinkCache,
+    circuit: CircuitBreaker,
+    tasks: std::sync::Mutex<Vec<JoinHandle<()>>>,
+    cli_slots: ArcSwap<Semaphore>,
+    runner: Box<dyn CommandRunner>,
+    /// Heartbeats to send again after wakatime-cli failed, see `retry_later`.
+    retry: RetryQueue,
+    /// Held while `wakatime-cli --today` runs.
+    today_running: Mutex<()>,
+    /// When each kind of warning was last shown, see `warn_debounced`.
+    warnings_shown: std::sync::Mutex<HashMap<String, Instant>>,
+    logs: LogBuffer,
+}
+
+/// `$/wakatime/status`, telling the client when heartbeats are held back.
+enum WakatimeStatus {}
+
+impl notification::Notification for WakatimeStatus {
+    type Params = serde_json::Value;
+    const METHOD: &'static str = "$/wakatime/status";
+}
+
+/// Arguments of `wakatime.clearCache`. Queued heartbeats are kept unless
+/// explicitly included.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct ClearCacheParams {
+    include_offline_queue: bool,
+}
+
+#[derive(Deserialize)]
+struct BranchHint {
+    branch: Option<String>,
+}
+
+/// Params of `wakatime/cursorMoved`.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CursorMoved {
+    text_document: TextDocumentIdentifier,
+    position: Option<Position>,
 }
 
 impl WakatimeLanguageServer {
-    async fn send(&self, event: Event) {
-        // if is_write is false, and file has not changed since last heartbeat,
-        // and less than 2 minutes since last heartbeat, and do nothing
-        const INTERVAL: TimeDelta = TimeDelta::minutes(2);
+    /// A server with fresh state, running wakatime-cli through `runner`.
+    fn new(
+        this: &Weak<Self>,
+        client: Client,
+        setting: Setting,
+        wakatime_path: String,
+        runner: Box<dyn CommandRunner>,
+    ) -> Self {
+        let (project_roots, _) = ProjectRoots::new(&setting.project_roots);
+        let (filter, _) = EntityFilter::new(&setting.exclude, &setting.include);
+        let max_concurrent_cli = setting.max_concurrent_cli();
+
+        WakatimeLanguageServer {
+            this: this.clone(),
+            client,
+            settings: ArcSwap::from_pointee(setting),
+            wakatime_path,
+            platform: ArcSwap::from_pointee(String::new()),
+            stats: Arc::new(Stats::default()),
+            history: History::default(),
+            sequence: AtomicU64::new(0),
+            batch: Arc::new(Batch::default()),
+            status_format_warned: AtomicBool::new(false),
+            claims_across_windows: AtomicBool::new(true),
+            configuration_registration: AtomicBool::new(false),
+            last_change: Mutex::new(None),
+            extension_version: None,
+            release_channel: None,
+            config_file: None,
+            config_file_error: None,
+            cursors: Mutex::new(HashMap::new()),
+            versions: Mutex::new(HashMap::new()),
+            languages: Mutex::new(HashMap::new()),
+            documents: Mutex::new(HashMap::new()),
+            workspace_folders: ArcSwap::from_pointee(Vec::new()),
+            project_roots: ArcSwap::from_pointee(project_roots),
+            detected_projects: DetectedProjects::default(),
+            filter: ArcSwap::from_pointee(filter),
+            initialized_at: OnceLock::new(),
+            branch_hint: ArcSwapOption::empty(),
+            symlinks: SymlinkCache::default(),
+            circuit: CircuitBreaker::default(),
+            tasks: std::sync::Mutex::new(Vec::new()),
+            cli_slots: ArcSwap::from_pointee(Semaphore::new(max_concurrent_cli)),
+            runner,
+            retry: RetryQueue::default(),
+            warnings_shown: std::sync::Mutex::new(HashMap::new()),
+            today_running: Mutex::new(()),
+            logs: LogBuffer::default(),
+            lsp_settings: ArcSwapOption::empty(),
+            current_file: Mutex::new(CurrentFile {
+                uri: String::new(),
+                timestamp: Local::now(),
+                write_timestamp: None,
+                last_was_write: false,
+                last_was_open: false,
+                changed_since: false,
+            }),
+        }
+    }
+
+    /// A server without a connected client, for running the heartbeat
+    /// pipeline outside an LSP session. What it would tell the client only
+    /// ends up in `logs`.
+    fn detached(
+        setting: Setting,
+        wakatime_path: String,
+        runner: Box<dyn CommandRunner>,
+    ) -> Arc<Self> {
+        let mut server = None;
+        let (_service, _socket) = LspService::new(|client| {
+            let ls =
+                Arc::new_cyclic(|this| Self::new(this, client, setting, wakatime_path, runner));
+            server = Some(ls.clone());
+            ls
+        });
+        server.expect("LspService::new runs the init closure")
+    }
+
+    /// Logs to the client and keeps the line for `wakatime/debugBundle`.
+    async fn log(&self, level: MessageType, message: impl Into<String>) {
+        let message = message.into();
+        self.logs.record(level, &message);
+        self.client.log_message(level, message).await;
+    }
+
+    async fn send(&self, event: Event) -> SendOutcome {
+        self.send_at(event, Local::now()).await
+    }
 
+    /// Sends `event` as having happened at `now`, unless throttled.
+    async fn send_at(&self, mut event: Event, now: DateTime<Local>) -> SendOutcome {
         let mut current_file = self.current_file.lock().await;
-        let now = Local::now();
 
         #[cfg(debug_assertions)]
-        self.client
-            .log_message(
+        self.log(
+            MessageType::LOG,
+            format!("Wakatime language server send called, event: {event:?}",),
+        )
+        .await;
+
+        let document = self
+            .documents
+            .lock()
+            .await
+            .get(&event.uri)
+            .filter(|document| document.unsaved)
+            .map(|document| document.text.clone());
+        event.uri = paths::normalize_windows_network_path(&event.uri);
+
+        let settings = self.settings.load_full();
+        if settings.resolve_symlinks {
+            event.uri = self.symlinks.canonicalize(&event.uri);
+        }
+
+        if !settings.include_git_internals && paths::is_git_internal(&event.uri) {
+            return SendOutcome::Skipped;
+        }
+
+        if self.filter.load().excludes(&event.uri) {
+            self.log(
                 MessageType::LOG,
-                format!("Wakatime language server send called, event: {event:?}",),
+                format!("Wakatime excluded by settings, skipping {}", event.uri),
             )
             .await;
-
-        if event.uri == current_file.uri
-            && now - current_file.timestamp < INTERVAL
-            && !event.is_write
-        {
-            return;
+            return SendOutcome::Skipped;
         }
 
-        let mut command = TokioCommand::new(self.wakatime_path.as_str());
+        // Closing the current file flushes changes throttled since its last
+        // heartbeat; otherwise there's nothing to send, only the throttle to
+        // reset so reopening the file isn't throttled.
+        if event.is_close {
+            if event.uri != current_file.uri {
+                return SendOutcome::Skipped;
+            }
+            let flush = current_file.changed_since;
+            current_file.forget();
+            if !flush {
+                return SendOutcome::Skipped;
+            }
+        }
 
-        command
-            .arg("--time")
-            .arg((now.timestamp() as f64).to_string())
-            .arg("--write")
-            .arg(event.is_write.to_string())
-            .arg("--entity")
-            .arg(event.uri.as_str());
+        if current_file.follows_open(&event, now) {
+            current_file.last_was_open = false;
+            self.history.record(
+                &event,
+                now.timestamp() as f64,
+                Outcome::Suppressed,
+                &settings,
+            );
+            return SendOutcome::Throttled;
+        }
 
-        if !self.platform.load().is_empty() {
-            command.arg("--plugin").arg(self.platform.load().as_str());
+        if !should_send(&current_file, now, &event.uri, event.is_write, &settings) {
+            current_file.record_throttled(&event);
+            self.history.record(
+                &event,
+                now.timestamp() as f64,
+                Outcome::Suppressed,
+                &settings,
+            );
+            return SendOutcome::Throttled;
         }
 
-        let settings = self.settings.load();
+        // Saving right after typing: turn the still queued change heartbeat
+        // into the write, or skip the save if that heartbeat already went out
+        // and nothing changed since.
+        if current_file.follows_change(&event, now) {
+            if self.batch.upgrade_to_write(&event.uri) {
+                current_file.record(&event, now);
+                return SendOutcome::Queued;
+            }
+            if !current_file.changed_since {
+                return SendOutcome::Throttled;
+            }
+        }
 
-        if let Some(ref key) = settings.api_key {
-            command.arg("--key").arg(key);
+        if !settings.schedule.allows(now.naive_local()) {
+            self.log(
+                MessageType::LOG,
+                format!(
+                    "Wakatime outside the configured schedule, skipping {}",
+                    event.uri
+                ),
+            )
+            .await;
+            return SendOutcome::Skipped;
         }
 
-        if let Some(ref api_url) = settings.api_url {
-            command.arg("--api-url").arg(api_url);
+        // Dropped heartbeats must not count toward the throttle, here or in
+        // other windows.
+        if settings.api_key.is_none()
+            && settings.missing_key_behavior == MissingKeyBehavior::Disable
+        {
+            return SendOutcome::Skipped;
         }
 
-        if let Some(ref language) = event.language {
-            command.arg("--language").arg(language);
+        // Another Zed window's server may have just sent this heartbeat. The
+        // shared file is checked without holding up this window's other
+        // events, which may have been sent meanwhile.
+        drop(current_file);
+        let interval = if event.is_write {
+            settings.write_interval()
         } else {
-            command.arg("--guess-language");
+            settings.interval()
+        };
+        let (uri, is_write) = (event.uri.clone(), event.is_write);
+        let claimed = !self.claims_across_windows.load(Ordering::Relaxed)
+            || tokio::task::spawn_blocking(move || active::claim(&uri, is_write, now, interval))
+                .await
+                .unwrap_or(true);
+        let mut current_file = self.current_file.lock().await;
+        if !claimed || !should_send(&current_file, now, &event.uri, event.is_write, &settings) {
+            self.history.record(
+                &event,
+                now.timestamp() as f64,
+                Outcome::Suppressed,
+                &settings,
+            );
+            return SendOutcome::Throttled;
         }
 
-        if let Some(lineno) = event.lineno {
-            command.arg("--lineno").arg(lineno.to_string());
+        event.detect_project(
+            &self.project_roots.load(),
+            &self.detected_projects,
+            &self.workspace_folders.load(),
+        );
+        if settings.resolve_symlinks {
+            if let Some(ref folder) = event.project_folder {
+                event.project_folder = Some(PathBuf::from(
+                    self.symlinks.canonicalize(&folder.to_string_lossy()),
+                ));
+            }
         }
 
-        if let Some(cursor_pos) = event.cursor_pos {
-            command.arg("--cursorpos").arg(cursor_pos.to_string());
+        if event.branch.is_none() {
+            event.branch = ci::detect_branch(
+                settings.branch.as_deref(),
+                self.branch_hint.load().as_deref().map(String::as_str),
+            );
         }
 
-        self.client
-            .log_message(
-                MessageType::LOG,
-                format!("Wakatime  command: {:?}", command.as_std()),
+        event.sequence = self.sequence.fetch_add(1, Ordering::Relaxed);
+        current_file.record(&event, now);
+        drop(current_file);
+
+        if settings.api_key.is_none() && settings.missing_key_behavior == MissingKeyBehavior::Prompt
+        {
+            self.warn_debounced(
+                "missing-key",
+                "WakaTime: no API key configured, set api_key in Zed's lsp.wakatime.settings \
+                 or ~/.wakatime.cfg"
+                    .to_string(),
             )
             .await;
+        }
 
-        if let Err(e) = command.output().await {
-            self.client
-                .log_message(
-                    MessageType::LOG,
-                    format!(
-                        "Wakatime language server send msg failed: {e:?}, command: {:?}",
-                        command.as_std()
-                    ),
-                )
-                .await;
+        if settings.api_key.is_none() && settings.missing_key_behavior == MissingKeyBehavior::Queue
+        {
+            queue::persist(&[(event, now.timestamp() as f64)]).ok();
+            return SendOutcome::Queued;
+        }
+
+        if settings.spawn_strategy == SpawnStrategy::Batched {
+            let is_write = event.is_write;
+            self.enqueue(event, now.timestamp() as f64);
+            // A save goes out right away, taking the queued edits with it.
+            if is_write {
+                self.spawn_batch(Duration::ZERO);
+            }
+            return SendOutcome::Queued;
+        }
+
+        if self.circuit.is_open() {
+            queue::persist(&[(event, now.timestamp() as f64)]).ok();
+            return SendOutcome::Queued;
+        }
+
+        let local_file = match document {
+            Some(text) => match LocalFile::create(&settings.temp_dir(), &event.uri, &text) {
+                Ok(file) => Some(file),
+                Err(e) => {
+                    self.log(
+                        MessageType::LOG,
+                        format!("Wakatime failed to write local copy of {}: {e}", event.uri),
+                    )
+                    .await;
+                    None
+                }
+            },
+            None => None,
+        };
+        event.local_file = local_file.as_ref().map(|file| file.path().to_path_buf());
+
+        let args = Heartbeat::new(&event, now.timestamp() as f64)
+            .to_args(&settings, self.platform.load().as_str());
+        // The API key and proxy credentials are in the arguments.
+        let command = diagnostics::redact(&format!("{} {args:?}", self.wakatime_path), &settings);
+        self.log(MessageType::LOG, format!("Wakatime  command: {command}"))
+            .await;
+
+        if settings.dry_run {
+            self.history
+                .record(&event, now.timestamp() as f64, Outcome::DryRun, &settings);
+            self.stats.record_dry_run(1);
+            return SendOutcome::DryRun;
+        }
+
+        // Run the cli off the notification handler so a slow network doesn't
+        // hold up the next document event.
+        let Some(server) = self.this.upgrade() else {
+            return SendOutcome::Skipped;
         };
+        let (exit_tx, exit_rx) = oneshot::channel();
+        self.spawn(async move {
+            let Ok(_slot) = server.cli_slots.load_full().acquire_owned().await else {
+                return;
+            };
+            // Removed once the cli is done with it.
+            let _local_file = local_file;
+
+            let started = Instant::now();
+            let result = server
+                .runner
+                .run(&server.wakatime_path, &args, None, settings.process_timeout())
+                .await;
+            server.stats.record_latency(started.elapsed());
+            let exit_code = result.as_ref().ok().and_then(|output| output.status.code());
+            let retry = retry::is_retryable(&result);
+
+            let success = match result {
+                Ok(ref output) => server.log_cli_output(output).await,
+                Err(e) => {
+                    server
+                        .log(
+                            MessageType::LOG,
+                            format!(
+                                "Wakatime language server send msg failed: {e:?}, command: {command}"
+                            ),
+                        )
+                        .await;
+                    false
+                }
+            };
+            server
+                .history
+                .record(&event, now.timestamp() as f64, Outcome::Sent { exit_code }, &settings);
+            server.record_cli_result(success).await;
+
+            if retry {
+                // The local copy is gone by the time the retry runs.
+                let mut event = event;
+                event.local_file = None;
+                server.retry_later(vec![(event, now.timestamp() as f64)]);
+            }
+            exit_tx.send(exit_code).ok();
+        });
 
-        current_file.uri = event.uri;
-        current_file.timestamp = now;
+        SendOutcome::Sent(exit_rx)
     }
-}
 
-#[tower_lsp::async_trait]
-impl LanguageServer for WakatimeLanguageServer {
-    async fn initialize(&self, params: InitializeParams) -> Result<InitializeResult> {
-        if let Some(ref client_info) = params.client_info {
-            let mut platform = String::new();
-            platform.push_str("Zed");
+    /// Logs what wakatime-cli printed and returns whether it succeeded. Only
+    /// the exit status decides that: some versions write informational
+    /// messages to stderr on success.
+    async fn log_cli_output(&self, output: &std::process::Output) -> bool {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let stderr = stderr.trim();
 
-            if let Some(ref version) = client_info.version {
-                platform.push('/');
-                platform.push_str(version.as_str());
+        if output.status.success() {
+            if !stderr.is_empty() {
+                self.log(MessageType::INFO, format!("Wakatime cli: {stderr}"))
+                    .await;
             }
+            return true;
+        }
 
-            platform.push(' ');
-            platform.push_str(format!("Zed-wakatime/{}", env!("CARGO_PKG_VERSION")).as_str());
+        self.log(
+            MessageType::WARNING,
+            format!("Wakatime cli exited with {}: {stderr}", output.status),
+        )
+        .await;
 
-            self.platform.store(Arc::new(platform));
+        let code = output.status.code();
+        let hint = match code {
+            Some(104) => " Check your api_key.",
+            Some(102) => " Heartbeats are saved and sent once the API is reachable.",
+            _ => "",
+        };
+        let snippet: String = stderr.chars().take(STDERR_SNIPPET_CHARS).collect();
+        self.warn_debounced(
+            &format!("cli-{code:?}"),
+            format!(
+                "WakaTime: wakatime-cli failed ({}).{hint} {snippet}",
+                code.map_or_else(
+                    || "terminated".to_string(),
+                    |code| format!("exit code {code}")
+                )
+            ),
+        )
+        .await;
+        false
+    }
+
+    /// Shows a warning unless the same kind was shown in the last
+    /// `WARNING_DEBOUNCE`.
+    async fn warn_debounced(&self, kind: &str, message: String) {
+        {
+            let mut shown = self.warnings_shown.lock().unwrap();
+            if shown
+                .get(kind)
+                .is_some_and(|at| at.elapsed() < WARNING_DEBOUNCE)
+            {
+                return;
+            }
+            shown.insert(kind.to_string(), Instant::now());
         }
 
-        Ok(InitializeResult {
-            server_info: Some(ServerInfo {
-                name: env!("CARGO_PKG_NAME").to_string(),
-                version: Some(env!("CARGO_PKG_VERSION").to_string()),
-            }),
-            capabilities: ServerCapabilities {
-                text_document_sync: Some(TextDocumentSyncCapability::Kind(
-                    TextDocumentSyncKind::INCREMENTAL,
-                )),
-                ..Default::default()
-            },
-        })
+        self.client
+            .show_message(MessageType::WARNING, message.trim_end())
+            .await;
     }
 
-    async fn initialized(&self, _params: InitializedParams) {
+    /// Tracks consecutive wakatime-cli failures, opening the circuit once they
+    /// reach `circuit_breaker_threshold` and scheduling it to close again.
+    async fn record_cli_result(&self, success: bool) {
+        if success {
+            self.circuit.record_success();
+            return;
+        }
+
+        let settings = self.settings.load_full();
+        let threshold = settings.circuit_breaker_threshold();
+        if !self.circuit.record_failure(threshold) {
+            return;
+        }
+
+        let timeout = settings.circuit_breaker_timeout();
+        let retry_after = TimeDelta::from_std(timeout)
+            .ok()
+            .and_then(|delay| Local::now().checked_add_signed(delay))
+            .map(|at| at.to_rfc3339());
+
+        self.log(
+            MessageType::WARNING,
+            format!(
+                "Wakatime cli failed {threshold} times in a row, queueing heartbeats for {}s",
+                timeout.as_secs()
+            ),
+        )
+        .await;
         self.client
-            .log_message(MessageType::INFO, "Wakatime language server initialized")
+            .send_notification::<WakatimeStatus>(serde_json::json!({
+                "status": "circuit_open",
+                "retry_after": retry_after,
+            }))
             .await;
+
+        if let Some(server) = self.this.upgrade() {
+            self.spawn(async move {
+                tokio::time::sleep(timeout).await;
+                server.close_circuit().await;
+            });
+        }
     }
 
-    async fn shutdown(&self) -> Result<()> {
-        Ok(())
+    /// Resumes sending and replays the heartbeats queued while the circuit was
+    /// open.
+    async fn close_circuit(&self) {
+        self.circuit.close();
+        self.client
+            .send_notification::<WakatimeStatus>(serde_json::json!({ "status": "circuit_closed" }))
+            .await;
+
+        for (event, timestamp) in queue::take_persisted() {
+            self.enqueue(event, timestamp);
+        }
     }
 
-    async fn did_open(&self, params: DidOpenTextDocumentParams) {
-        let event = Event {
-            uri: params.text_document.uri[url::Position::BeforeUsername..].to_string(),
-            is_write: false,
-            lineno: None,
-            language: Some(params.text_document.language_id.clone()),
-            cursor_pos: None,
+    /// Queues a heartbeat for the next batched invocation, scheduling a flush
+    /// at the end of the window if none is pending.
+    fn enqueue(&self, event: Event, timestamp: f64) {
+        if self.batch.push(event, timestamp) {
+            self.spawn_batch(self.settings.load().batch_window());
+        }
+    }
+
+    /// Sends the queued heartbeats after `delay`, persisting them on failure.
+    fn spawn_batch(&self, delay: Duration) {
+        let Some(server) = self.this.upgrade() else {
+            return;
         };
 
-        self.send(event).await;
+        self.spawn(async move {
+            tokio::time::sleep(delay).await;
+            let heartbeats = server.batch.take();
+            let timeout = server.settings.load().process_timeout();
+            if !server.send_batch(&heartbeats, timeout).await {
+                server.retry_later(heartbeats);
+            }
+        });
     }
 
-    async fn did_change(&self, params: DidChangeTextDocumentParams) {
-        let event = Event {
-            uri: params.text_document.uri[url::Position::BeforeUsername..].to_string(),
-            is_write: false,
-            lineno: params
-                .content_changes
-                .get(0)
-                .map_or_else(|| None, |c| c.range)
-                .map(|c| c.start.line as u64),
-            language: None,
-            cursor_pos: params
-                .content_changes
-                .get(0)
-                .map_or_else(|| None, |c| c.range)
-                .map(|c| c.start.character as u64),
-        };
+    /// Retries heartbeats wakatime-cli failed to send, backing off
+    /// exponentially. While the circuit is open they're persisted instead and
+    /// replayed when it closes.
+    fn retry_later(&self, heartbeats: Vec<(Event, f64)>) {
+        if self.circuit.is_open() {
+            queue::persist(&heartbeats).ok();
+            return;
+        }
 
-        self.send(event).await;
+        if !self.retry.push(heartbeats) {
+            return;
+        }
+
+        // Not tracked by `spawn`: shutdown flushes the queue itself instead of
+        // waiting out the delay.
+        let server = self.this.clone();
+        let delay = self.retry.delay();
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            let Some(server) = server.upgrade() else {
+                return;
+            };
+
+            let heartbeats = server.retry.take();
+            if heartbeats.is_empty() {
+                return;
+            }
+
+            let timeout = server.settings.load().process_timeout();
+            let sent = server.send_batch(&heartbeats, timeout).await;
+            server.retry.record_result(sent);
+            if !sent {
+                server.retry_later(heartbeats);
+            }
+        });
     }
 
-    async fn did_save(&self, params: DidSaveTextDocumentParams) {
-        let event = Event {
-            uri: params.text_document.uri[url::Position::BeforeUsername..].to_string(),
-            is_write: true,
-            lineno: None,
-            language: None,
-            cursor_pos: None,
-        };
+    /// Spawns a task that shutdown waits for, or aborts if it takes too long.
+    fn spawn(&self, task: impl Future<Output = ()> + Send + 'static) {
+        let mut tasks = self.tasks.lock().unwrap();
+        tasks.retain(|handle| !handle.is_finished());
+        tasks.push(tokio::spawn(task));
+    }
 
-        self.send(event).await;
+    /// Waits for every spawned task, however long wakatime-cli takes.
+    async fn wait_for_tasks(&self) {
+        let handles = std::mem::take(&mut *self.tasks.lock().unwrap());
+        for handle in handles {
+            handle.await.ok();
+        }
     }
-}
 
-#[tokio::main]
-async fn main() {
-    let matches = Command::new("wakatime_ls")
-        .version(env!("CARGO_PKG_VERSION"))
-        .author("bestgopher <84328409@qq.com>")
-        .about("A simple WakaTime language server tool")
-        .arg(
-            Arg::new("wakatime-cli")
-                .short('p')
-                .long("wakatime-cli")
-                .help("wakatime-cli path")
-                .required(true),
+    /// Gives spawned tasks `TASK_SHUTDOWN_TIMEOUT` to finish and aborts the
+    /// rest. Any wakatime-cli they were waiting on is killed on drop.
+    async fn join_tasks(&self) {
+        let handles = std::mem::take(&mut *self.tasks.lock().unwrap());
+        let deadline = tokio::time::Instant::now() + TASK_SHUTDOWN_TIMEOUT;
+
+        for mut handle in handles {
+            if tokio::time::timeout_at(deadline, &mut handle)
+                .await
+                .is_err()
+            {
+                handle.abort();
+            }
+        }
+    }
+
+    /// Sends every queued heartbeat, including those waiting to be retried,
+    /// persisting them for the next start when wakatime-cli fails or doesn't
+    /// finish within `FLUSH_TIMEOUT`.
+    async fn flush(&self) {
+        let mut heartbeats = self.retry.take();
+        heartbeats.extend(self.batch.take());
+        if heartbeats.is_empty() {
+            return;
+        }
+
+        if !self.send_batch(&heartbeats, FLUSH_TIMEOUT).await {
+            queue::persist(&heartbeats).ok();
+        }
+    }
+
+    /// Sends heartbeats in one wakatime-cli invocation, returning whether they
+    /// were sent or kept by the cli to send later. Nothing is sent while the
+    /// circuit is open.
+    async fn send_batch(&self, heartbeats: &[(Event, f64)], timeout: Duration) -> bool {
+        if self.circuit.is_open() {
+            return false;
+        }
+
+        let settings = self.settings.load_full();
+        let Some((args, stdin)) =
+            heartbeat::build_batch(heartbeats, &settings, self.platform.load().as_str())
+        else {
+            return true;
+        };
+
+        self.log(
+            MessageType::LOG,
+            format!(
+                "Wakatime sending {} batched heartbeats: {}",
+                heartbeats.len(),
+                diagnostics::redact(&format!("{args:?}"), &settings)
+            ),
         )
-        .get_matches();
+        .await;
 
-    let wakatime_cli = if let Some(s) = matches.get_one::<String>("wakatime-cli") {
-        s.to_string()
-    } else {
-        "wakatime-cli".to_string()
-    };
+        if settings.dry_run {
+            for (event, timestamp) in heartbeats {
+                self.history
+                    .record(event, *timestamp, Outcome::DryRun, &settings);
+            }
+            self.stats.record_dry_run(heartbeats.len() as u64);
+            return true;
+        }
 
-    let stdin = tokio::io::stdin();
-    let stdout = tokio::io::stdout();
+        let Ok(_slot) = self.cli_slots.load_full().acquire_owned().await else {
+            return false;
+        };
+        let started = Instant::now();
+        let result = self
+            .runner
+            .run(&self.wakatime_path, &args, stdin, timeout)
+            .await;
+        self.stats.record_latency(started.elapsed());
+        let exit_code = result.as_ref().ok().and_then(|output| output.status.code());
 
-    let (service, socket) = LspService::new(|client| {
-        Arc::new(WakatimeLanguageServer {
-            client,
-            settings: ArcSwap::from_pointee(Setting::default()),
-            wakatime_path: wakatime_cli,
-            platform: ArcSwap::from_pointee(String::new()),
-            current_file: Mutex::new(CurrentFile {
-                uri: String::new(),
-                timestamp: Local::now(),
-            }),
-        })
-    });
-    Server::new(stdin, stdout, socket).serve(service).await;
+        let success = match result {
+            Ok(ref output) => self.log_cli_output(output).await,
+            Err(ref e) => {
+                self.log(
+                    MessageType::LOG,
+                    format!("Wakatime language server batch send failed: {e:?}"),
+                )
+                .await;
+                false
+            }
+        };
+        for (event, timestamp) in heartbeats {
+            self.history
+                .record(event, *timestamp, Outcome::Sent { exit_code }, &settings);
+        }
+        self.record_cli_result(success).await;
+
+        !retry::is_retryable(&result)
+    }
+
+    /// Records a `did_change` and reports whether it ends an idle period, in
+    /// which case its heartbeat is suppressed and the throttle is reset so the
+    /// next change after typing resumes is sent right away.
+    async fn resume_from_idle(&self) -> bool {
+        let now = Local::now();
+        let settings = self.settings.load();
+        let idle_timeout = settings.idle_timeout();
+
+        let was_idle = {
+            let mut last_change = self.last_change.lock().await;
+            let was_idle = !idle_timeout.is_zero()
+                && last_change.is_some_and(|last| {
+                    (now - last)
+                        .to_std()
+                        .is_ok_and(|elapsed| elapsed > idle_timeout)
+                });
+            *last_change = Some(now);
+            was_idle
+        };
+
+        if was_idle {
+            self.current_file.lock().await.timestamp = now - settings.interval();
+        }
+
+        was_idle
+    }
+
+    /// Runs `wakatime-cli --today`. When the client sends `$/cancelRequest`,
+    /// tower-lsp drops this future and answers with `RequestCancelled`;
+    /// dropping it kills the cli process along with it.
+    async fn today(&self) -> Result<Option<serde_json::Value>> {
+        let text = self.fetch_today().await.map_err(internal_error)?;
+        Ok(Some(serde_json::Value::String(text)))
+    }
+
+    /// Today's coding time, rendered with `status_format`. Callers take turns
+    /// so there's only ever one `--today` process.
+    async fn fetch_today(&self) -> std::result::Result<String, String> {
+        let _running = self.today_running.lock().await;
+        let settings = self.settings.load();
+        if settings.api_key.is_none() {
+            return Err(
+                "no API key configured, set api_key in Zed's lsp.wakatime.settings or ~/.wakatime.cfg"
+                    .to_string(),
+            );
+        }
+
+        let mut args = vec![
+            "--today".to_string(),
+            "--output".to_string(),
+            "json".to_string(),
+        ];
+        heartbeat::push_api_args(&mut args, &settings);
+        if !self.platform.load().is_empty() {
+            args.push("--plugin".to_string());
+            args.push(self.platform.load().as_str().to_string());
+        }
+
+        let output = self
+            .runner
+            .run(&self.wakatime_path, &args, None, settings.process_timeout())
+            .await
+            .map_err(|e| format!("failed to run wakatime-cli: {e}"))?;
+
+        if !output.status.success() {
+            return Err(format!(
+                "wakatime-cli --today failed with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        let today = today::parse_today(&String::from_utf8_lossy(&output.stdout));
+        let text = self.render_today(&today, &settings).await;
+        if text.trim().is_empty() {
+            return Err("wakatime-cli --today printed nothing".to_string());
+        }
+
+        Ok(text)
+    }
+
+    /// With `today_refresh_seconds` set, fetches today's time on that interval
+    /// and sends it as a `today` status when it changes.
+    fn spawn_today_refresh(&self) {
+        let this = self.this.clone();
+
+        tokio::spawn(async move {
+            let mut last = None;
+            loop {
+                let Some(interval) = this
+                    .upgrade()
+                    .and_then(|server| server.settings.load().today_refresh())
+                else {
+                    if this.strong_count() == 0 {
+                        return;
+                    }
+                    tokio::time::sleep(Duration::from_secs(60)).await;
+                    continue;
+                };
+                tokio::time::sleep(interval).await;
+
+                let Some(server) = this.upgrade() else {
+                    return;
+                };
+                let Ok(text) = server.fetch_today().await else {
+                    continue;
+                };
+                if last.as_ref() != Some(&text) {
+                    server
+                        .client
+                        .send_notification::<WakatimeStatus>(serde_json::json!({
+                            "status": "today",
+                            "text": text,
+                        }))
+                        .await;
+                    last = Some(text);
+                }
+            }
+        });
+    }
+
+    /// Drops in-memory caches, and with `include_offline_queue` the heartbeats
+    /// waiting to be sent, then tells the user what was cleared.
+    async fn clear_cache(&self, params: ClearCacheParams) -> Result<Option<serde_json::Value>> {
+        self.project_roots.load().clear_cache();
+        self.detected_projects.clear_cache();
+        self.symlinks.clear();
+        self.languages.lock().await.clear();
+        self.cursors.lock().await.clear();
+        self.branch_hint.store(None);
+        self.stats.reset();
+        self.history.clear();
+
+        let mut cleared = vec![
+            "project roots".to_string(),
+            "detected projects".to_string(),
+            "resolved symlinks".to_string(),
+            "document languages".to_string(),
+            "cursor lines".to_string(),
+            "editor branch".to_string(),
+            "stats".to_string(),
+            "recent heartbeats".to_string(),
+        ];
+
+        let mut binaries = vec![PathBuf::from(&self.wakatime_path)];
+        binaries.extend(std::env::current_exe());
+        let removed = downloaded_version_dirs(&binaries)
+            .into_iter()
+            .filter(|dir| std::fs::remove_dir_all(dir).is_ok())
+            .count();
+        if removed > 0 {
+            cleared.push(format!("{removed} downloaded binary versions not in use"));
+        }
+
+        if params.include_offline_queue {
+            let dropped =
+                self.batch.take().len() + self.retry.take().len() + queue::take_persisted().len();
+            cleared.push(format!("{dropped} queued heartbeats"));
+        }
+
+        self.client
+            .show_message(
+                MessageType::INFO,
+                format!("WakaTime cleared {}", cleared.join(", ")),
+            )
+            .await;
+
+        Ok(Some(serde_json::json!({ "cleared": cleared })))
+    }
+
+    async fn versions(&self) -> Result<Option<serde_json::Value>> {
+        let ls_path = std::env::current_exe()
+            .ok()
+            .map(|path| path.to_string_lossy().to_string());
+        let ls_version = async {
+            match ls_path {
+                Some(ref path) => version::binary_version(self.runner.as_ref(), path).await,
+                None => "unknown".to_string(),
+            }
+        };
+        let (wakatime_cli, wakatime_ls) = tokio::join!(
+            version::binary_version(self.runner.as_ref(), &self.wakatime_path),
+            ls_version
+        );
+        let extension = self.extension_version.as_deref().unwrap_or("unknown");
+
+        self.client
+            .show_message(
+                MessageType::INFO,
+                format!(
+                    "WakaTime versions: wakatime-ls {wakatime_ls}, wakatime-cli {wakatime_cli}, extension {extension}"
+                ),
+            )
+            .await;
+
+        Ok(Some(serde_json::json!({
+            "wakatime_ls": wakatime_ls,
+            "wakatime_cli": wakatime_cli,
+            "extension": extension,
+        })))
+    }
+
+    /// Formats today's total with `status_format`, falling back to the cli's
+    /// own text when unset or invalid.
+    async fn render_today(&self, today: &today::Today, settings: &Setting) -> String {
+        let (Some(template), Some(time)) = (settings.status_format.as_deref(), today.time) else {
+            return today.text.clone();
+        };
+
+        if !today::validate_format(template) {
+            if !self.status_format_warned.swap(true, Ordering::Relaxed) {
+                self.client
+                    .show_message(
+                        MessageType::WARNING,
+                        format!(
+                            "Wakatime status_format {template:?} is invalid, using the default"
+                        ),
+                    )
+                    .await;
+            }
+            return today.text.clone();
+        }
+
+        let uri = self.current_file.lock().await.uri.clone();
+        let project = project::detect_project(Path::new(&uri)).map(|p| p.name);
+
+        today::format_today(template, time, project.as_deref())
+    }
+
+    /// Stores new settings and rebuilds the state derived from them.
+    async fn apply_settings(&self, setting: Setting) {
+        let (project_roots, invalid) = ProjectRoots::new(&setting.project_roots);
+        for pattern in invalid {
+            self.log(
+                MessageType::WARNING,
+                format!("Wakatime ignoring invalid project_roots pattern {pattern:?}"),
+            )
+            .await;
+        }
+
+        let (filter, invalid) = EntityFilter::new(&setting.exclude, &setting.include);
+        for pattern in invalid {
+            self.log(
+                MessageType::WARNING,
+                format!("Wakatime ignoring invalid exclude/include pattern {pattern:?}"),
+            )
+            .await;
+        }
+
+        for entry in setting.schedule.invalid_entries() {
+            self.log(
+                MessageType::WARNING,
+                format!("Wakatime ignoring invalid schedule entry {entry:?}"),
+            )
+            .await;
+        }
+
+        if let Some(ca_bundle) = setting.ca_bundle().filter(|path| !path.is_file()) {
+            self.client
+                .show_message(
+                    MessageType::WARNING,
+                    format!(
+                        "WakaTime CA bundle {} doesn't exist, ignoring it",
+                        ca_bundle.display()
+                    ),
+                )
+                .await;
+        }
+
+        if setting.hide_file_names != Hide::All(false) {
+            self.log(
+                MessageType::INFO,
+                "Wakatime hide_file_names is on: wakatime-cli gets the real path to detect the \
+                 project and language but obfuscates it before sending, project names are \
+                 sent as detected unless hide_project_names is set too, and documents that \
+                 aren't files are sent as their URI scheme only",
+            )
+            .await;
+        }
+
+        let proxy = proxy::Proxy::resolve(&setting);
+        let key_added = setting.api_key.is_some() && self.settings.load().api_key.is_none();
+        if setting.max_concurrent_cli() != self.settings.load().max_concurrent_cli() {
+            // Processes already running keep their slots in the old semaphore.
+            self.cli_slots
+                .store(Arc::new(Semaphore::new(setting.max_concurrent_cli())));
+        }
+        self.project_roots.store(Arc::new(project_roots));
+        self.filter.store(Arc::new(filter));
+        self.settings.store(Arc::new(setting));
+
+        // Heartbeats held back by `missing_key_behavior: queue` go out once a
+        // key shows up.
+        if key_added {
+            for (event, timestamp) in queue::take_persisted() {
+                self.enqueue(event, timestamp);
+            }
+        }
+
+        let settings = self.settings.load();
+        self.log(
+            MessageType::INFO,
+            format!(
+                "Wakatime settings applied, api key: {}, api url: {}, proxy: {}",
+                if settings.api_key.is_some() {
+                    "set"
+                } else {
+                    "missing"
+                },
+                settings.api_url.as_deref().unwrap_or("default"),
+                proxy.describe()
+            ),
+        )
+        .await;
+    }
+
+    /// Handles the `$/wakatime/heartbeatHistory` request with the last 20
+    /// heartbeats, including throttled ones.
+    async fn heartbeat_history(self: &Arc<Self>) -> Result<serde_json::Value> {
+        Ok(self.history.recent(20))
+    }
+
+    /// Handles the `wakatime/debugBundle` request with a redacted report of
+    /// versions, configuration, stats, recent logs and a connectivity check.
+    async fn debug_bundle(self: &Arc<Self>) -> Result<serde_json::Value> {
+        let settings = self.settings.load_full();
+        let lsp_settings = self.lsp_settings.load_full();
+        let platform = self.platform.load_full();
+
+        Ok(diagnostics::Bundle {
+            runner: self.runner.as_ref(),
+            wakatime_cli: &self.wakatime_path,
+            settings: &settings,
+            sources: diagnostics::configuration_sources(
+                &settings,
+                lsp_settings.as_deref(),
+                self.config_file.as_ref(),
+            ),
+            platform: &platform,
+            extension_version: self.extension_version.as_deref(),
+            stats: self.stats.to_json(),
+            logs: self.logs.redacted(&settings),
+        }
+        .into_json()
+        .await)
+    }
+
+    /// Handles the `wakatime/branch` notification, letting the editor supply
+    /// the branch it knows about.
+    async fn set_branch_hint(self: &Arc<Self>, hint: BranchHint) {
+        self.branch_hint.store(
+            hint.branch
+                .filter(|branch| !branch.is_empty())
+                .map(Arc::new),
+        );
+    }
+
+    /// Handles the `wakatime/cursorMoved` notification, sent by editors for
+    /// navigation without edits so reading code counts too. It's throttled
+    /// like any other non-write heartbeat.
+    async fn cursor_moved(self: &Arc<Self>, params: CursorMoved) {
+        let entity = paths::uri_to_entity(&params.text_document.uri);
+        let uri = entity.entity;
+
+        let cursor = {
+            let mut cursors = self.cursors.lock().await;
+            if let Some(position) = params.position {
+                cursors.insert(
+                    uri.clone(),
+                    (position.line as u64, position.character as u64),
+                );
+            }
+            cursors.get(&uri).copied()
+        };
+        let language = self.languages.lock().await.get(&uri).cloned();
+        let lines_in_file = self
+            .documents
+            .lock()
+            .await
+            .get(&uri)
+            .map(Document::line_count);
+
+        let event = Event {
+            uri,
+            is_app: entity.is_app,
+            is_write: false,
+            lineno: cursor.map(|(lineno, _)| lineno),
+            language,
+            cursor_pos: cursor.map(|(_, cursor_pos)| cursor_pos),
+            lines_in_file,
+            ..Default::default()
+        };
+
+        if self.resume_from_idle().await {
+            return;
+        }
+
+        self.send(event).await;
+    }
+
+    /// Applies settings received over LSP, layered on the config file. They
+    /// may come wrapped in a `wakatime` section.
+    async fn apply_lsp_settings(&self, value: Option<serde_json::Value>) {
+        let value = value
+            .map(|value| match value {
+                serde_json::Value::Object(mut object)
+                    if object.get("wakatime").is_some_and(|v| v.is_object()) =>
+                {
+                    object.remove("wakatime").unwrap_or_default()
+                }
+                value => value,
+            })
+            .filter(|value| !value.is_null());
+        self.lsp_settings.store(value.clone().map(Arc::new));
+        let value = match self.config_file {
+            Some(ref file) => {
+                let keys = file.provided_keys(value.as_ref());
+                if !keys.is_empty() {
+                    self.log(
+                        MessageType::INFO,
+                        format!(
+                            "Wakatime using {} from {}",
+                            keys.join(", "),
+                            file.path.display()
+                        ),
+                    )
+                    .await;
+                }
+                Some(file.merge(value))
+            }
+            None => value,
+        };
+        let Some(value) = value else {
+            return;
+        };
+
+        let (setting, invalid) = Setting::from_value_lenient(value);
+        if !invalid.is_empty() {
+            self.log(
+                MessageType::WARNING,
+                format!("Wakatime ignoring invalid settings: {}", invalid.join(", ")),
+            )
+            .await;
+        }
+        self.apply_settings(Setting::resolve(setting)).await;
+    }
+
+    /// Pulls the `wakatime` section via `workspace/configuration`, retrying a
+    /// few times in case the client isn't ready yet. On final failure the
+    /// env/cfg-file settings resolved at startup stay in effect.
+    async fn pull_configuration(&self) {
+        let values = self
+            .with_configuration_retries(|| {
+                self.client.configuration(vec![ConfigurationItem {
+                    scope_uri: None,
+                    section: Some("wakatime".to_string()),
+                }])
+            })
+            .await;
+
+        match values {
+            Some(values) => self.apply_lsp_settings(values.into_iter().next()).await,
+            None => {
+                self.log(
+                    MessageType::WARNING,
+                    "Wakatime could not pull configuration, using env and ~/.wakatime.cfg",
+                )
+                .await;
+            }
+        }
+    }
+
+    /// Runs a `workspace/configuration` request up to
+    /// `CONFIGURATION_ATTEMPTS` times, logging each failed attempt.
+    async fn with_configuration_retries<F, Fut>(
+        &self,
+        mut request: F,
+    ) -> Option<Vec<serde_json::Value>>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<Vec<serde_json::Value>>>,
+    {
+        for attempt in 1..=CONFIGURATION_ATTEMPTS {
+            match request().await {
+                Ok(values) => return Some(values),
+                Err(e) => {
+                    self.log(
+                        MessageType::LOG,
+                        format!(
+                            "Wakatime configuration pull attempt \
+                             {attempt}/{CONFIGURATION_ATTEMPTS} failed: {e}"
+                        ),
+                    )
+                    .await;
+                }
+            }
+
+            if attempt < CONFIGURATION_ATTEMPTS {
+                tokio::time::sleep(CONFIGURATION_RETRY_DELAY * attempt).await;
+            }
+        }
+
+        None
+    }
+}
+
+#[tower_lsp::async_trait]
+impl LanguageServer for WakatimeLanguageServer {
+    async fn initialize(&self, params: InitializeParams) -> Result<InitializeResult> {
+        let dynamic_registration = params
+            .capabilities
+            .workspace
+            .as_ref()
+            .and_then(|workspace| workspace.did_change_configuration.as_ref())
+            .and_then(|capability| capability.dynamic_registration)
+            .unwrap_or(false);
+        self.configuration_registration
+            .store(dynamic_registration, Ordering::Relaxed);
+
+        if params.initialization_options.is_some() {
+            self.apply_lsp_settings(params.initialization_options.clone())
+                .await;
+        }
+
+        if let Some(ref client_info) = params.client_info {
+            // Forks and Zed Preview report their own name. Spaces would split
+            // the user agent, so they become dashes.
+            let name = client_info.name.split_whitespace().collect::<Vec<_>>();
+            let mut platform = if name.is_empty() {
+                "Zed".to_string()
+            } else {
+                name.join("-")
+            };
+
+            // Only non-stable channels are marked, so stable and unknown keep
+            // the plain editor name.
+            if let Some(channel) = self.release_channel.as_deref().and_then(channel_suffix) {
+                if !platform.ends_with(channel) {
+                    platform.push('-');
+                    platform.push_str(channel);
+                }
+            }
+
+            if let Some(ref version) = client_info.version {
+                platform.push('/');
+                platform.push_str(version.as_str());
+            }
+
+            platform.push(' ');
+            platform.push_str(format!("Zed-wakatime/{}", env!("CARGO_PKG_VERSION")).as_str());
+
+            self.platform.store(Arc::new(platform));
+        }
+
+        #[allow(deprecated)]
+        let workspace_folders: Vec<PathBuf> = match params.workspace_folders {
+            Some(ref folders) => folders
+                .iter()
+                .filter_map(|folder| folder.uri.to_file_path().ok())
+                .collect(),
+            None => params
+                .root_uri
+                .iter()
+                .filter_map(|uri| uri.to_file_path().ok())
+                .collect(),
+        };
+        self.workspace_folders.store(Arc::new(workspace_folders));
+
+        Ok(InitializeResult {
+            server_info: Some(ServerInfo {
+                name: env!("CARGO_PKG_NAME").to_string(),
+                version: Some(env!("CARGO_PKG_VERSION").to_string()),
+            }),
+            capabilities: ServerCapabilities {
+                text_document_sync: Some(TextDocumentSyncCapability::Kind(
+                    TextDocumentSyncKind::INCREMENTAL,
+                )),
+                execute_command_provider: Some(ExecuteCommandOptions {
+                    commands: WakatimeCommand::advertised(),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        })
+    }
+
+    async fn initialized(&self, _params: InitializedParams) {
+        self.initialized_at.get_or_init(Instant::now);
+
+        self.log(MessageType::INFO, "Wakatime language server initialized")
+            .await;
+
+        // Clients that support it only push settings changes for registered
+        // servers.
+        if self.configuration_registration.load(Ordering::Relaxed) {
+            let registration = Registration {
+                id: "wakatime-did-change-configuration".to_string(),
+                method: "workspace/didChangeConfiguration".to_string(),
+                register_options: None,
+            };
+            if let Err(e) = self.client.register_capability(vec![registration]).await {
+                self.log(
+                    MessageType::LOG,
+                    format!("Wakatime failed to register for configuration changes: {e}"),
+                )
+                .await;
+            }
+        }
+
+        if let Some(ref error) = self.config_file_error {
+            self.client
+                .show_message(
+                    MessageType::WARNING,
+                    format!("WakaTime ignoring config file: {error}"),
+                )
+                .await;
+        }
+
+        // A bare name is looked up on PATH when run; a configured path is
+        // only checked here, as the extension can't see outside its work dir.
+        let wakatime_path = Path::new(&self.wakatime_path);
+        if wakatime_path.components().count() > 1 && !wakatime_path.is_file() {
+            self.client
+                .show_message(
+                    MessageType::WARNING,
+                    format!("WakaTime: wakatime-cli not found at {}", self.wakatime_path),
+                )
+                .await;
+        }
+
+        self.pull_configuration().await;
+
+        let settings = self.settings.load_full();
+        if settings.api_key.is_none()
+            && settings.missing_key_behavior == MissingKeyBehavior::Prompt
+            && settings.first_run_check.unwrap_or(true)
+            && !setup::first_run_complete()
+        {
+            self.spawn(setup::run_first_run_wizard(self.client.clone()));
+        }
+
+        for (event, timestamp) in queue::take_persisted() {
+            self.enqueue(event, timestamp);
+        }
+
+        self.spawn_today_refresh();
+    }
+
+    async fn shutdown(&self) -> Result<()> {
+        self.flush().await;
+        self.join_tasks().await;
+        Ok(())
+    }
+
+    async fn execute_command(
+        &self,
+        params: ExecuteCommandParams,
+    ) -> Result<Option<serde_json::Value>> {
+        match WakatimeCommand::from_name(&params.command) {
+            Some(WakatimeCommand::ClearCache) => {
+                let params = params
+                    .arguments
+                    .into_iter()
+                    .next()
+                    .and_then(|value| serde_json::from_value(value).ok())
+                    .unwrap_or_default();
+                self.clear_cache(params).await
+            }
+            Some(WakatimeCommand::DebugStats) => Ok(Some(self.stats.to_json())),
+            Some(WakatimeCommand::RecentHeartbeats) => Ok(Some(self.history.recent(HISTORY_SIZE))),
+            Some(WakatimeCommand::Today) => self.today().await,
+            Some(WakatimeCommand::Version) => self.versions().await,
+            None => Err(tower_lsp::jsonrpc::Error::method_not_found()),
+        }
+    }
+
+    /// Zed pushes changed settings here; a notification without them means
+    /// they have to be pulled.
+    async fn did_change_configuration(&self, params: DidChangeConfigurationParams) {
+        if params.settings.is_null() {
+            self.pull_configuration().await;
+        } else {
+            self.apply_lsp_settings(Some(params.settings)).await;
+        }
+    }
+
+    async fn did_open(&self, params: DidOpenTextDocumentParams) {
+        let entity = paths::uri_to_entity(&params.text_document.uri);
+        let language = match entity.language {
+            Some(language) => language.to_string(),
+            None => language::resolve(&entity.entity, &params.text_document.language_id),
+        };
+
+        let document = Document {
+            unsaved: !entity.is_app && !Path::new(&entity.entity).is_file(),
+            text: params.text_document.text,
+        };
+
+        let event = Event {
+            uri: entity.entity,
+            is_app: entity.is_app,
+            is_write: false,
+            lineno: None,
+            language: Some(language.clone()),
+            cursor_pos: None,
+            lines_in_file: Some(document.line_count()),
+            is_open: true,
+            ..Default::default()
+        };
+
+        self.languages
+            .lock()
+            .await
+            .insert(event.uri.clone(), language);
+        self.versions
+            .lock()
+            .await
+            .insert(event.uri.clone(), params.text_document.version);
+        self.documents
+            .lock()
+            .await
+            .insert(event.uri.clone(), document);
+
+        // Zed re-opens every restored tab right after startup; those opens only
+        // seed document state until the grace period is over.
+        let grace = self.settings.load().restore_grace();
+        if self
+            .initialized_at
+            .get()
+            .is_some_and(|initialized_at| initialized_at.elapsed() < grace)
+        {
+            self.stats.record_restore_open_suppressed();
+            return;
+        }
+
+        self.send(event).await;
+    }
+
+    async fn did_change(&self, params: DidChangeTextDocumentParams) {
+        let entity = paths::uri_to_entity(&params.text_document.uri);
+        let uri = entity.entity;
+
+        // Drop notifications processed out of order so positions never move
+        // backwards.
+        {
+            let mut versions = self.versions.lock().await;
+            let version = params.text_document.version;
+            if versions.get(&uri).is_some_and(|latest| version < *latest) {
+                return;
+            }
+            versions.insert(uri.clone(), version);
+        }
+
+        // The cursor is past the last ranged change in the batch. Full-document
+        // changes carry no range, so without one keep the last known position;
+        // a change without any content is navigation, sent all the same.
+        let cursor = {
+            let mut cursors = self.cursors.lock().await;
+            match params
+                .content_changes
+                .iter()
+                .rev()
+                .find_map(document::cursor_after)
+            {
+                Some(cursor) => {
+                    cursors.insert(uri.clone(), cursor);
+                    Some(cursor)
+                }
+                None => cursors.get(&uri).copied(),
+            }
+        };
+
+        // Changes in a batch apply one after another, each to the text the
+        // previous one left.
+        let lines_in_file = self.documents.lock().await.get_mut(&uri).map(|document| {
+            for change in &params.content_changes {
+                document.apply(change);
+            }
+            document.line_count()
+        });
+
+        let language = self.languages.lock().await.get(&uri).cloned();
+
+        let event = Event {
+            uri,
+            is_app: entity.is_app,
+            is_write: false,
+            lineno: cursor.map(|(lineno, _)| lineno),
+            language,
+            cursor_pos: cursor.map(|(_, cursor_pos)| cursor_pos),
+            lines_in_file,
+            ..Default::default()
+        };
+
+        if self.resume_from_idle().await {
+            return;
+        }
+
+        self.send(event).await;
+    }
+
+    async fn did_save(&self, params: DidSaveTextDocumentParams) {
+        let entity = paths::uri_to_entity(&params.text_document.uri);
+        let uri = entity.entity;
+        let cursor = self.cursors.lock().await.get(&uri).copied();
+        self.detected_projects.invalidate(Path::new(&uri));
+
+        let language = self.languages.lock().await.get(&uri).cloned();
+        // A document saved for the first time is a file from now on.
+        let lines_in_file = self.documents.lock().await.get_mut(&uri).map(|document| {
+            if let Some(text) = params.text {
+                document.text = text;
+            }
+            document.unsaved = document.unsaved && !Path::new(&uri).is_file();
+            document.line_count()
+        });
+
+        let event = Event {
+            uri,
+            is_app: entity.is_app,
+            is_write: true,
+            lineno: cursor.map(|(lineno, _)| lineno),
+            language,
+            cursor_pos: cursor.map(|(_, cursor_pos)| cursor_pos),
+            lines_in_file,
+            ..Default::default()
+        };
+
+        self.send(event).await;
+    }
+
+    async fn did_close(&self, params: DidCloseTextDocumentParams) {
+        let entity = paths::uri_to_entity(&params.text_document.uri);
+        let uri = entity.entity;
+        let cursor = self.cursors.lock().await.remove(&uri);
+        let language = self.languages.lock().await.remove(&uri);
+        self.versions.lock().await.remove(&uri);
+        let lines_in_file = self
+            .documents
+            .lock()
+            .await
+            .get(&uri)
+            .map(Document::line_count);
+
+        let event = Event {
+            uri: uri.clone(),
+            is_app: entity.is_app,
+            is_write: false,
+            lineno: cursor.map(|(lineno, _)| lineno),
+            language,
+            cursor_pos: cursor.map(|(_, cursor_pos)| cursor_pos),
+            lines_in_file,
+            is_close: true,
+            ..Default::default()
+        };
+
+        // Still needed for the final heartbeat's local copy.
+        self.send(event).await;
+        self.documents.lock().await.remove(&uri);
+    }
+}
+
+/// Written by the extension into each version directory it installs.
+const INSTALL_STAMP: &str = ".wakatime-install";
+
+/// Version directories the extension downloaded next to `binaries`, the
+/// binaries in use, which are kept. Only directories holding the extension's
+/// install stamp count, so a user-managed install named like one, or the
+/// directories around it, are never touched.
+fn downloaded_version_dirs(binaries: &[PathBuf]) -> Vec<PathBuf> {
+    let is_download = |dir: &Path| {
+        let versioned = dir
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| {
+                name.starts_with("wakatime-cli-v") || name.starts_with("wakatime-ls-v")
+            });
+        versioned && dir.join(INSTALL_STAMP).is_file()
+    };
+
+    // Compared resolved, so a symlinked cache can't hide a directory in use.
+    let resolve = |dir: &Path| dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+    let version_dirs: Vec<&Path> = binaries
+        .iter()
+        .filter_map(|binary| binary.parent())
+        .collect();
+    let in_use: Vec<PathBuf> = version_dirs.iter().map(|dir| resolve(dir)).collect();
+
+    let mut dirs = Vec::new();
+    for version_dir in version_dirs.iter().filter(|dir| is_download(dir)) {
+        let Some(Ok(entries)) = version_dir.parent().map(std::fs::read_dir) else {
+            continue;
+        };
+
+        for path in entries.flatten().map(|entry| entry.path()) {
+            if is_download(&path) && !in_use.contains(&resolve(&path)) && !dirs.contains(&path) {
+                dirs.push(path);
+            }
+        }
+    }
+
+    dirs.sort();
+    dirs
+}
+
+/// The user agent suffix for a Zed release channel, `None` for stable.
+fn channel_suffix(channel: &str) -> Option<&'static str> {
+    match channel.to_ascii_lowercase().as_str() {
+        "preview" => Some("Preview"),
+        "dev" => Some("Dev"),
+        "nightly" => Some("Nightly"),
+        _ => None,
+    }
+}
+
+/// Resolves on SIGINT/SIGTERM, or Ctrl-C/console close on Windows.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+            }
+            Err(_) => {
+                tokio::signal::ctrl_c().await.ok();
+            }
+        }
+    }
+
+    #[cfg(windows)]
+    {
+        match tokio::signal::windows::ctrl_close() {
+            Ok(mut close) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = close.recv() => {}
+                }
+            }
+            Err(_) => {
+                tokio::signal::ctrl_c().await.ok();
+            }
+        }
+    }
+}
+
+fn internal_error(message: String) -> tower_lsp::jsonrpc::Error {
+    let mut error = tower_lsp::jsonrpc::Error::internal_error();
+    error.message = message.into();
+    error
+}
+
+fn cli() -> Command {
+    Command::new("wakatime_ls")
+        .version(env!("CARGO_PKG_VERSION"))
+        .author("bestgopher <84328409@qq.com>")
+        .about("A simple WakaTime language server tool")
+        .arg(
+            Arg::new("wakatime-cli")
+                .short('p')
+                .long("wakatime-cli")
+                .help("wakatime-cli path")
+                .global(true),
+        )
+        .arg(
+            Arg::new("config")
+                .long("config")
+                .help("wakatime-ls config file [default: ~/.config/wakatime-ls/config.toml]")
+                .global(true),
+        )
+        .arg(
+            Arg::new("print-config")
+                .long("print-config")
+                .help("print the resolved settings and wakatime-cli path, then exit")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("restart-on-crash")
+                .long("restart-on-crash")
+                .help("restart the server if it fails right after starting")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("extension-version")
+                .long("extension-version")
+                .help("version of the Zed extension launching the server")
+                .hide(true),
+        )
+        .arg(
+            Arg::new("release-channel")
+                .long("release-channel")
+                .help("release channel of the Zed instance launching the server")
+                .hide(true),
+        )
+        .subcommand(
+            Command::new("send")
+                .about("Send a single heartbeat through wakatime-cli and print the outcome")
+                .arg(
+                    Arg::new("entity")
+                        .long("entity")
+                        .help("file path of the heartbeat")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("write")
+                        .long("write")
+                        .help("mark the heartbeat as a write")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(Arg::new("language").long("language").help("language name"))
+                .arg(Arg::new("project").long("project").help("project name"))
+                .arg(Arg::new("key").long("key").help("WakaTime API key"))
+                .arg(Arg::new("api-url").long("api-url").help("WakaTime API url")),
+        )
+        .subcommand(
+            Command::new("doctor")
+                .about("Check the wakatime-cli, config file and API connection")
+                .arg(
+                    Arg::new("bundle")
+                        .long("bundle")
+                        .help("print a redacted JSON report to attach to an issue instead")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("completions")
+                .about("Print a shell completion script to stdout")
+                .arg(
+                    Arg::new("shell")
+                        .help("shell to generate completions for")
+                        .required(true)
+                        .value_parser(clap::value_parser!(Shell)),
+                ),
+        )
+}
+
+/// Sends one heartbeat through the same pipeline as the language server:
+/// filters, the cross-window throttle, redacted logging and the runner. Prints
+/// the log and the outcome, exiting with wakatime-cli's status.
+async fn send_once(
+    wakatime_cli: &str,
+    config_file: Option<&ConfigFile>,
+    matches: &ArgMatches,
+    runner: Box<dyn CommandRunner>,
+) -> ExitCode {
+    let base = config_file.map(|file| file.setting().0).unwrap_or_default();
+    let mut settings = Setting::resolve(Setting {
+        api_key: matches
+            .get_one::<String>("key")
+            .cloned()
+            .or(base.api_key.clone()),
+        api_url: matches
+            .get_one::<String>("api-url")
+            .cloned()
+            .or(base.api_url.clone()),
+        ..base
+    });
+    // A batch would only go out after the window; one heartbeat is sent
+    // right away so its outcome can be printed.
+    settings.spawn_strategy = SpawnStrategy::PerHeartbeat;
+
+    let event = Event {
+        uri: matches
+            .get_one::<String>("entity")
+            .cloned()
+            .unwrap_or_default(),
+        is_write: matches.get_flag("write"),
+        language: matches.get_one::<String>("language").cloned(),
+        project: matches.get_one::<String>("project").cloned(),
+        ..Default::default()
+    };
+
+    let server =
+        WakatimeLanguageServer::detached(settings.clone(), wakatime_cli.to_string(), runner);
+    server.platform.store(Arc::new(format!(
+        "wakatime-ls/{}",
+        env!("CARGO_PKG_VERSION")
+    )));
+    server
+        .workspace_folders
+        .store(Arc::new(std::env::current_dir().into_iter().collect()));
+
+    // Throttling against open editors would make the outcome depend on
+    // whatever another window sent last.
+    server.claims_across_windows.store(false, Ordering::Relaxed);
+
+    // Not sent is a success: the heartbeat went where the settings say.
+    let exit_code = match server.send(event).await {
+        SendOutcome::Sent(exit_code) => Ok(exit_code.await.ok().flatten()),
+        SendOutcome::Skipped => {
+            Err("heartbeat not sent: excluded, outside the schedule or without an API key")
+        }
+        SendOutcome::Throttled => {
+            Err("heartbeat throttled: one was sent for this file within the interval")
+        }
+        SendOutcome::Queued => Err("heartbeat queued to be sent by the language server"),
+        SendOutcome::DryRun => Err("dry run: wakatime-cli not run"),
+    };
+    server.wait_for_tasks().await;
+
+    // A failed run waits in memory for a retry this process won't make; the
+    // next language server start sends it instead.
+    let retry = server.retry.take();
+    if !retry.is_empty() {
+        queue::persist(&retry).ok();
+    }
+
+    for line in server.logs.redacted(&settings) {
+        eprintln!("{line}");
+    }
+
+    let exit_code = match exit_code {
+        Ok(exit_code) => exit_code,
+        Err(message) => {
+            println!("{message}");
+            return ExitCode::SUCCESS;
+        }
+    };
+    if !retry.is_empty() {
+        println!("heartbeat queued to be retried by the language server");
+    }
+
+    match exit_code {
+        Some(0) => {
+            println!("wakatime-cli exited with code 0");
+            ExitCode::SUCCESS
+        }
+        Some(code) => {
+            println!("wakatime-cli exited with code {code}");
+            ExitCode::from(code.clamp(1, 255) as u8)
+        }
+        None => {
+            println!("wakatime-cli failed to run or was terminated");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Checks the setup from a terminal, or with `--bundle` prints the same
+/// report as `wakatime/debugBundle`, minus what only a running server knows.
+async fn doctor(
+    wakatime_cli: &str,
+    config_file: Option<&ConfigFile>,
+    config_file_error: Option<&str>,
+    bundle: bool,
+) -> ExitCode {
+    let settings = Setting::resolve(config_file.map(|file| file.setting().0).unwrap_or_default());
+
+    if bundle {
+        let platform = format!("wakatime-ls/{}", env!("CARGO_PKG_VERSION"));
+        let logs = config_file_error
+            .map(|e| diagnostics::redact(&format!("ignoring config file: {e}"), &settings))
+            .into_iter()
+            .collect();
+        let report = diagnostics::Bundle {
+            runner: &TokioRunner,
+            wakatime_cli,
+            settings: &settings,
+            sources: diagnostics::configuration_sources(&settings, None, config_file),
+            platform: &platform,
+            extension_version: None,
+            stats: Stats::default().to_json(),
+            logs,
+        }
+        .into_json()
+        .await;
+        println!("{report:#}");
+        return ExitCode::SUCCESS;
+    }
+
+    println!("wakatime-ls {}", env!("CARGO_PKG_VERSION"));
+    println!(
+        "wakatime-cli {} ({wakatime_cli})",
+        version::binary_version(&TokioRunner, wakatime_cli).await
+    );
+    match (config_file, config_file_error) {
+        (_, Some(e)) => println!("config file: {e}"),
+        (Some(file), None) => println!("config file: {}", file.path.display()),
+        (None, None) => println!("config file: none"),
+    }
+    match diagnostics::configuration_sources(&settings, None, config_file).get("api_key") {
+        Some(source) => println!("api key: set, from {}", source.as_str().unwrap_or_default()),
+        None => println!(
+            "api key: missing, heartbeats are handled per missing_key_behavior: {}",
+            serde_json::to_value(settings.missing_key_behavior).unwrap_or_default()
+        ),
+    }
+
+    println!("proxy: {}", proxy::Proxy::resolve(&settings).describe());
+
+    let connectivity = diagnostics::check_connectivity(&TokioRunner, wakatime_cli, &settings).await;
+    if connectivity["ok"].as_bool() == Some(true) {
+        println!("connectivity: ok");
+        return ExitCode::SUCCESS;
+    }
+
+    let reason = connectivity["error"]
+        .as_str()
+        .or_else(|| connectivity["stderr"].as_str())
+        .unwrap_or_default();
+    println!("connectivity: failed {reason}");
+    ExitCode::FAILURE
+}
+
+fn main() -> ExitCode {
+    let matches = cli().get_matches();
+
+    if matches.get_flag("restart-on-crash") {
+        supervisor::run();
+    }
+
+    match tokio::runtime::Runtime::new() {
+        Ok(runtime) => runtime.block_on(run(matches)),
+        Err(e) => {
+            eprintln!("wakatime-ls: failed to start the async runtime: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(matches: ArgMatches) -> ExitCode {
+    let wakatime_cli = if let Some(s) = matches.get_one::<String>("wakatime-cli") {
+        s.to_string()
+    } else {
+        "wakatime-cli".to_string()
+    };
+
+    if let Some(("completions", sub_matches)) = matches.subcommand() {
+        if let Some(&shell) = sub_matches.get_one::<Shell>("shell") {
+            clap_complete::generate(shell, &mut cli(), "wakatime-ls", &mut std::io::stdout());
+        }
+        return ExitCode::SUCCESS;
+    }
+
+    let config_path = matches
+        .get_one::<String>("config")
+        .map(PathBuf::from)
+        .or_else(config_file::default_path);
+    let (config_file, config_file_error) = match config_path.as_deref().map(ConfigFile::load) {
+        Some(Ok(file)) => (file, None),
+        Some(Err(e)) => (None, Some(e)),
+        None => (None, None),
+    };
+
+    if let Some(("send", sub_matches)) = matches.subcommand() {
+        let wakatime_cli = sub_matches
+            .get_one::<String>("wakatime-cli")
+            .cloned()
+            .unwrap_or(wakatime_cli);
+        if let Some(ref e) = config_file_error {
+            eprintln!("ignoring config file: {e}");
+        }
+        return send_once(
+            &wakatime_cli,
+            config_file.as_ref(),
+            sub_matches,
+            Box::new(TokioRunner),
+        )
+        .await;
+    }
+
+    if matches.get_flag("print-config") {
+        let settings = Setting::resolve(
+            config_file
+                .as_ref()
+                .map(|file| file.setting().0)
+                .unwrap_or_default(),
+        );
+        let config = serde_json::json!({
+            "wakatime_cli": diagnostics::redact(&wakatime_cli, &settings),
+            "config_file": config_file
+                .as_ref()
+                .map(|file| diagnostics::redact(&file.path.display().to_string(), &settings)),
+            "config_file_error": config_file_error,
+            "configuration": diagnostics::configuration(&settings),
+            "sources": diagnostics::configuration_sources(&settings, None, config_file.as_ref()),
+        });
+        println!("{config:#}");
+        return ExitCode::SUCCESS;
+    }
+
+    if let Some(("doctor", sub_matches)) = matches.subcommand() {
+        let wakatime_cli = sub_matches
+            .get_one::<String>("wakatime-cli")
+            .cloned()
+            .unwrap_or(wakatime_cli);
+        return doctor(
+            &wakatime_cli,
+            config_file.as_ref(),
+            config_file_error.as_deref(),
+            sub_matches.get_flag("bundle"),
+        )
+        .await;
+    }
+
+    if std::io::stdin().is_terminal() {
+        eprintln!(
+            "wakatime-ls is a language server; run it via Zed or pipe LSP JSON-RPC to its stdin.\n\
+             To send a single heartbeat from a terminal, use `wakatime-ls send --help`."
+        );
+        return ExitCode::FAILURE;
+    }
+
+    let extension_version = matches.get_one::<String>("extension-version").cloned();
+    let release_channel = matches.get_one::<String>("release-channel").cloned();
+
+    paths::init_network_drives();
+
+    let stdin = tokio::io::stdin();
+    let stdout = tokio::io::stdout();
+
+    let initial_setting = Setting::resolve(
+        config_file
+            .as_ref()
+            .map(|file| file.setting().0)
+            .unwrap_or_default(),
+    );
+
+    let mut server = None;
+    let (service, socket) = LspService::build(|client| {
+        let ls = Arc::new_cyclic(|this| WakatimeLanguageServer {
+            extension_version: extension_version.clone(),
+            release_channel: release_channel.clone(),
+            config_file,
+            config_file_error,
+            ..WakatimeLanguageServer::new(
+                this,
+                client,
+                initial_setting,
+                wakatime_cli,
+                Box::new(TokioRunner),
+            )
+        });
+        server = Some(ls.clone());
+        ls
+    })
+    .custom_method("wakatime/branch", WakatimeLanguageServer::set_branch_hint)
+    .custom_method("wakatime/cursorMoved", WakatimeLanguageServer::cursor_moved)
+    .custom_method(
+        "$/wakatime/heartbeatHistory",
+        WakatimeLanguageServer::heartbeat_history,
+    )
+    .custom_method("wakatime/debugBundle", WakatimeLanguageServer::debug_bundle)
+    .finish();
+    let server = server.expect("LspService::new runs the init closure");
+
+    // stdin closing ends `serve`; either way queued heartbeats get the same
+    // flush as an LSP shutdown.
+    tokio::select! {
+        _ = Server::new(stdin, stdout, socket).serve(service) => {}
+        _ = shutdown_signal() => {}
+    }
+    server.flush().await;
+    server.join_tasks().await;
+
+    ExitCode::SUCCESS
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Once;
+
+    use crate::runner::tests::RecordingRunner;
+
+    use super::*;
+
+    /// Points `WAKATIME_HOME` at a scratch directory, so the shared state
+    /// files written while sending never touch the real `~/.wakatime`.
+    fn isolate_home() {
+        static HOME: Once = Once::new();
+        HOME.call_once(|| {
+            let home =
+                std::env::temp_dir().join(format!("wakatime-ls-test-{}", std::process::id()));
+            std::fs::create_dir_all(&home).unwrap();
+            std::env::set_var("WAKATIME_HOME", home);
+        });
+    }
+
+    /// A server without a connected client, running wakatime-cli through
+    /// `runner`.
+    fn server(setting: Setting, runner: &RecordingRunner) -> Arc<WakatimeLanguageServer> {
+        isolate_home();
+        WakatimeLanguageServer::detached(
+            setting,
+            "wakatime-cli".to_string(),
+            Box::new(runner.clone()),
+        )
+    }
+
+    fn setting_with_key() -> Setting {
+        Setting {
+            api_key: Some("waka_0123-secret".to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn file_event(uri: &str, is_write: bool) -> Event {
+        Event {
+            uri: uri.to_string(),
+            is_write,
+            language: Some("rust".to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn test_uri(name: &str) -> Url {
+        Url::from_file_path(std::env::temp_dir().join("wakatime-ls-test").join(name)).unwrap()
+    }
+
+    /// Inserts `text` at `line`:`character`.
+    fn change(
+        uri: &Url,
+        version: i32,
+        line: u32,
+        character: u32,
+        text: &str,
+    ) -> DidChangeTextDocumentParams {
+        let position = Position::new(line, character);
+        DidChangeTextDocumentParams {
+            text_document: VersionedTextDocumentIdentifier::new(uri.clone(), version),
+            content_changes: vec![TextDocumentContentChangeEvent {
+                range: Some(Range::new(position, position)),
+                range_length: None,
+                text: text.to_string(),
+            }],
+        }
+    }
+
+    fn full_text(text: &str) -> TextDocumentContentChangeEvent {
+        TextDocumentContentChangeEvent {
+            range: None,
+            range_length: None,
+            text: text.to_string(),
+        }
+    }
+
+    fn save(uri: &Url) -> DidSaveTextDocumentParams {
+        DidSaveTextDocumentParams {
+            text_document: TextDocumentIdentifier::new(uri.clone()),
+            text: None,
+        }
+    }
+
+    fn open(uri: &Url, text: &str) -> DidOpenTextDocumentParams {
+        DidOpenTextDocumentParams {
+            text_document: TextDocumentItem::new(
+                uri.clone(),
+                "rust".to_string(),
+                1,
+                text.to_string(),
+            ),
+        }
+    }
+
+    fn write_invocations(runner: &RecordingRunner) -> Vec<Vec<String>> {
+        runner
+            .invocations()
+            .into_iter()
+            .map(|invocation| invocation.args)
+            .filter(|args| args.contains(&"--write".to_string()))
+            .collect()
+    }
+
+    fn has_arg(args: &[String], flag: &str, value: &str) -> bool {
+        args.windows(2)
+            .any(|pair| pair[0] == flag && pair[1] == value)
+    }
+
+    /// The current file, its last heartbeat and last write `elapsed` before
+    /// `now`.
+    fn current_file(uri: &str, now: DateTime<Local>, elapsed: TimeDelta) -> CurrentFile {
+        CurrentFile {
+            uri: uri.to_string(),
+            timestamp: now - elapsed,
+            write_timestamp: Some(now - elapsed),
+            last_was_write: false,
+            last_was_open: false,
+            changed_since: false,
+        }
+    }
+
+    #[test]
+    fn write_and_non_write_intervals_apply_separately() {
+        let settings = Setting {
+            interval_seconds: Some(120),
+            write_interval_seconds: Some(30),
+            ..Default::default()
+        };
+        let now = Local::now();
+
+        // (is_write, seconds since the last heartbeat, throttled)
+        for (is_write, elapsed, throttled) in [
+            (false, 60, true),
+            (false, 150, false),
+            (true, 20, true),
+            (true, 60, false),
+        ] {
+            let file = current_file("file:///a.rs", now, TimeDelta::seconds(elapsed));
+            assert_eq!(
+                !should_send(&file, now, "file:///a.rs", is_write, &settings),
+                throttled,
+                "is_write: {is_write}, {elapsed}s after the last heartbeat"
+            );
+        }
+    }
+
+    #[test]
+    fn first_write_for_a_file_is_not_throttled_by_non_writes() {
+        let settings = Setting {
+            interval_seconds: Some(120),
+            write_interval_seconds: Some(30),
+            ..Default::default()
+        };
+        let now = Local::now();
+        let file = CurrentFile {
+            write_timestamp: None,
+            ..current_file("file:///a.rs", now, TimeDelta::seconds(5))
+        };
+
+        assert!(should_send(&file, now, "file:///a.rs", true, &settings));
+        assert!(!should_send(&file, now, "file:///a.rs", false, &settings));
+        assert!(should_send(&file, now, "file:///b.rs", false, &settings));
+    }
+
+    #[test]
+    fn same_file_within_the_interval_is_not_sent() {
+        let now = Local::now();
+        let prev = current_file("file:///a.rs", now, TimeDelta::seconds(119));
+        assert!(!should_send(
+            &prev,
+            now,
+            "file:///a.rs",
+            false,
+            &Setting::default()
+        ));
+    }
+
+    #[test]
+    fn same_file_after_the_interval_is_sent() {
+        let now = Local::now();
+        for elapsed in [120, 121, 3600] {
+            let prev = current_file("file:///a.rs", now, TimeDelta::seconds(elapsed));
+            assert!(
+                should_send(&prev, now, "file:///a.rs", false, &Setting::default()),
+                "{elapsed}s"
+            );
+        }
+    }
+
+    #[test]
+    fn different_file_is_always_sent() {
+        let now = Local::now();
+        let prev = current_file("file:///a.rs", now, TimeDelta::zero());
+        assert!(should_send(
+            &prev,
+            now,
+            "file:///b.rs",
+            false,
+            &Setting::default()
+        ));
+        assert!(should_send(
+            &prev,
+            now,
+            "file:///b.rs",
+            true,
+            &Setting::default()
+        ));
+    }
+
+    #[test]
+    fn write_is_always_sent_by_default() {
+        let now = Local::now();
+        let prev = current_file("file:///a.rs", now, TimeDelta::zero());
+        assert!(should_send(
+            &prev,
+            now,
+            "file:///a.rs",
+            true,
+            &Setting::default()
+        ));
+    }
+
+    #[tokio::test]
+    async fn every_advertised_command_is_handled() {
+        let runner = RecordingRunner::default();
+        let server = server(setting_with_key(), &runner);
+
+        let result = server
+            .initialize(InitializeParams::default())
+            .await
+            .unwrap();
+        let commands = result
+            .capabilities
+            .execute_command_provider
+            .unwrap()
+            .commands;
+        assert_eq!(commands.len(), WakatimeCommand::ALL.len());
+
+        for command in commands.into_iter().chain(["wakatime.unknown".to_string()]) {
+            let handled = server
+                .execute_command(ExecuteCommandParams {
+                    command: command.clone(),
+                    ..Default::default()
+                })
+                .await;
+            let unknown =
+                handled.is_err_and(|err| err.code == tower_lsp::jsonrpc::ErrorCode::MethodNotFound);
+            assert_eq!(unknown, command == "wakatime.unknown", "{command}");
+        }
+    }
+
+    #[test]
+    fn cli_definition_is_valid() {
+        cli().debug_assert();
+    }
+
+    #[test]
+    fn completions_cover_the_flags_and_subcommands() {
+        for shell in [Shell::Bash, Shell::Zsh, Shell::Fish, Shell::PowerShell] {
+            let mut script = Vec::new();
+            clap_complete::generate(shell, &mut cli(), "wakatime-ls", &mut script);
+            let script = String::from_utf8(script).unwrap();
+
+            for word in [
+                "--wakatime-cli",
+                "--config",
+                "--print-config",
+                "send",
+                "--entity",
+                "doctor",
+                "--bundle",
+                "completions",
+            ] {
+                assert!(script.contains(word), "{shell}: {word} missing");
+            }
+        }
+    }
+
+    #[test]
+    fn completions_subcommand_takes_a_shell() {
+        let matches = cli()
+            .try_get_matches_from(["wakatime-ls", "completions", "zsh"])
+            .unwrap();
+        let (name, sub_matches) = matches.subcommand().unwrap();
+        assert_eq!(name, "completions");
+        assert_eq!(sub_matches.get_one::<Shell>("shell"), Some(&Shell::Zsh));
+
+        assert!(cli()
+            .try_get_matches_from(["wakatime-ls", "completions", "tcsh"])
+            .is_err());
+    }
+
+    async fn platform_for_client(name: &str, version: Option<&str>) -> String {
+        let server = server(setting_with_key(), &RecordingRunner::default());
+        server
+            .initialize(InitializeParams {
+                client_info: Some(ClientInfo {
+                    name: name.to_string(),
+                    version: version.map(str::to_string),
+                }),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        server.platform.load().to_string()
+    }
+
+    #[tokio::test]
+    async fn platform_names_the_client_editor() {
+        let plugin = format!("Zed-wakatime/{}", env!("CARGO_PKG_VERSION"));
+
+        assert_eq!(
+            platform_for_client("Zed", Some("0.160.0")).await,
+            format!("Zed/0.160.0 {plugin}")
+        );
+        assert_eq!(
+            platform_for_client("Zed Preview", Some("0.161.0")).await,
+            format!("Zed-Preview/0.161.0 {plugin}")
+        );
+        assert_eq!(
+            platform_for_client("Forked Editor", None).await,
+            format!("Forked-Editor {plugin}")
+        );
+        assert_eq!(
+            platform_for_client("  ", Some("1.0")).await,
+            format!("Zed/1.0 {plugin}")
+        );
+    }
+
+    #[test]
+    fn only_non_stable_channels_are_marked() {
+        assert_eq!(channel_suffix("preview"), Some("Preview"));
+        assert_eq!(channel_suffix("Nightly"), Some("Nightly"));
+        assert_eq!(channel_suffix("dev"), Some("Dev"));
+        assert_eq!(channel_suffix("stable"), None);
+        assert_eq!(channel_suffix(""), None);
+    }
+
+    async fn clear(server: &WakatimeLanguageServer, include_offline_queue: bool) -> Vec<String> {
+        let result = server
+            .clear_cache(ClearCacheParams {
+                include_offline_queue,
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        serde_json::from_value(result["cleared"].clone()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn clear_cache_resets_each_in_memory_cache() {
+        let server = server(setting_with_key(), &RecordingRunner::default());
+        let uri = test_uri("cleared.rs").to_string();
+        server
+            .languages
+            .lock()
+            .await
+            .insert(uri.clone(), "rust".to_string());
+        server.cursors.lock().await.insert(uri.clone(), (12, 340));
+        server.branch_hint.store(Some(Arc::new("main".to_string())));
+        server.stats.record_latency(Duration::from_millis(20));
+        server.history.record(
+            &file_event(&uri, false),
+            1.0,
+            Outcome::Suppressed,
+            &Setting::default(),
+        );
+
+        let cleared = clear(&server, false).await;
+
+        assert!(server.languages.lock().await.is_empty());
+        assert!(server.cursors.lock().await.is_empty());
+        assert!(server.branch_hint.load().is_none());
+        assert_eq!(server.stats.to_json()["heartbeats_sent"], 0);
+        assert_eq!(server.stats.to_json()["latency_ms"]["samples"], 0);
+        assert_eq!(server.history.recent(HISTORY_SIZE), serde_json::json!([]));
+        for item in [
+            "project roots",
+            "detected projects",
+            "resolved symlinks",
+            "document languages",
+            "cursor lines",
+            "editor branch",
+            "stats",
+            "recent heartbeats",
+        ] {
+            assert!(cleared.iter().any(|entry| entry == item), "{item}");
+        }
+    }
+
+    #[tokio::test]
+    async fn clear_cache_keeps_queued_heartbeats_unless_included() {
+        let server = server(setting_with_key(), &RecordingRunner::default());
+        let uri = test_uri("queued.rs").to_string();
+        server.batch.push(file_event(&uri, false), 1.0);
+
+        let cleared = clear(&server, false).await;
+
+        assert_eq!(server.batch.take().len(), 1);
+        assert!(!cleared.iter().any(|entry| entry.contains("queued")));
+    }
+
+    #[tokio::test]
+    async fn clear_cache_drops_queued_and_persisted_heartbeats_when_included() {
+        let server = server(setting_with_key(), &RecordingRunner::default());
+        let uri = test_uri("persisted.rs").to_string();
+        server.batch.push(file_event(&uri, false), 1.0);
+        queue::persist(&[(file_event(&uri, true), 2.0)]).unwrap();
+
+        server.retry.push([(file_event(&uri, false), 3.0)]);
+
+        let cleared = clear(&server, true).await;
+
+        assert!(server.batch.take().is_empty());
+        assert!(server.retry.take().is_empty());
+        assert!(queue::take_persisted().is_empty());
+        assert!(cleared.iter().any(|entry| entry == "3 queued heartbeats"));
+    }
+
+    /// Creates version directories under `cache`, stamped as the extension's
+    /// downloads when `stamped`.
+    fn version_dirs(cache: &Path, names: &[&str], stamped: bool) {
+        for name in names {
+            std::fs::create_dir_all(cache.join(name)).unwrap();
+            if stamped {
+                std::fs::write(cache.join(name).join(INSTALL_STAMP), "").unwrap();
+            }
+        }
+    }
+
+    fn dir_names(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = std::fs::read_dir(dir)
+            .unwrap()
+            .flatten()
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[tokio::test]
+    async fn clear_cache_deletes_downloaded_versions_not_in_use() {
+        let cache = test_support::temp_dir("clear-cache-binaries");
+        version_dirs(
+            &cache,
+            &[
+                "wakatime-cli-v1.90.0-amd64",
+                "wakatime-cli-v1.89.0-amd64",
+                "wakatime-ls-v0.1.6-x86_64-musl",
+            ],
+            true,
+        );
+        version_dirs(&cache, &["wakatime-cli-v1.80.0-manual", "unrelated"], false);
+        let cli = cache
+            .join("wakatime-cli-v1.90.0-amd64")
+            .join("wakatime-cli-linux-amd64");
+        std::fs::write(&cli, "").unwrap();
+
+        isolate_home();
+        let server = WakatimeLanguageServer::detached(
+            setting_with_key(),
+            cli.to_string_lossy().to_string(),
+            Box::new(RecordingRunner::default()),
+        );
+        let cleared = clear(&server, false).await;
+
+        assert_eq!(
+            dir_names(&cache),
+            [
+                "unrelated",
+                "wakatime-cli-v1.80.0-manual",
+                "wakatime-cli-v1.90.0-amd64"
+            ]
+        );
+        assert!(cli.is_file());
+        assert!(cleared
+            .iter()
+            .any(|entry| entry.starts_with("2 downloaded binary versions")));
+    }
+
+    #[test]
+    fn user_managed_install_and_its_neighbours_are_kept() {
+        let opt = test_support::temp_dir("user-managed-cli");
+        version_dirs(&opt, &["wakatime-cli-v1.90"], false);
+        version_dirs(&opt, &["wakatime-cli-v1.89.0-amd64"], true);
+
+        assert!(
+            downloaded_version_dirs(&[opt.join("wakatime-cli-v1.90").join("wakatime-cli")])
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn directories_of_every_binary_in_use_are_kept() {
+        let cache = test_support::temp_dir("binaries-in-use");
+        version_dirs(
+            &cache,
+            &[
+                "wakatime-cli-v1.90.0-amd64",
+                "wakatime-ls-v0.1.8-x86_64",
+                "wakatime-ls-v0.1.7-x86_64",
+            ],
+            true,
+        );
+
+        assert_eq!(
+            downloaded_version_dirs(&[
+                cache
+                    .join("wakatime-cli-v1.90.0-amd64")
+                    .join("wakatime-cli"),
+                cache.join("wakatime-ls-v0.1.8-x86_64").join("wakatime-ls"),
+            ]),
+            [cache.join("wakatime-ls-v0.1.7-x86_64")]
+        );
+    }
+
+    #[test]
+    fn binaries_outside_version_dirs_have_no_downloads() {
+        let cache = test_support::temp_dir("no-version-dirs");
+        std::fs::create_dir_all(cache.join("bin")).unwrap();
+        std::fs::create_dir_all(cache.join("wakatime-cli-v1.90.0-amd64")).unwrap();
+
+        assert!(downloaded_version_dirs(&[
+            PathBuf::from("wakatime-cli"),
+            cache.join("bin").join("wakatime-cli"),
+        ])
+        .is_empty());
+    }
+
+    #[tokio::test]
+    async fn save_right_after_a_sent_change_is_not_sent_again() {
+        let runner = RecordingRunner::default();
+        let server = server(setting_with_key(), &runner);
+        let uri = test_uri("collapse-sent.rs");
+
+        server.did_change(change(&uri, 1, 0, 0, "a")).await;
+        server.did_save(save(&uri)).await;
+        server.join_tasks().await;
+
+        let invocations = runner.invocations();
+        assert_eq!(invocations.len(), 1);
+        assert!(!invocations[0].args.contains(&"--write".to_string()));
+    }
+
+    #[tokio::test]
+    async fn save_right_after_a_queued_change_upgrades_it() {
+        let runner = RecordingRunner::default();
+        let server = server(
+            Setting {
+                spawn_strategy: SpawnStrategy::Batched,
+                batch_window_seconds: Some(60),
+                ..setting_with_key()
+            },
+            &runner,
+        );
+        let uri = test_uri("collapse-queued.rs");
+
+        server.did_change(change(&uri, 1, 0, 0, "a")).await;
+        server.did_save(save(&uri)).await;
+
+        let queued = server.batch.take();
+        assert_eq!(queued.len(), 1);
+        assert!(queued[0].0.is_write);
+        assert!(runner.invocations().is_empty());
+    }
+
+    fn output_with_stderr(code: i32, stderr: &str) -> std::process::Output {
+        std::process::Output {
+            stderr: stderr.as_bytes().to_vec(),
+            ..crate::runner::tests::output(code, "")
+        }
+    }
+
+    #[tokio::test]
+    async fn stderr_on_success_is_not_a_failure() {
+        let server = server(setting_with_key(), &RecordingRunner::default());
+        let output = output_with_stderr(0, "Note: using cached config\n");
+
+        assert!(server.log_cli_output(&output).await);
+
+        let logs = server.logs.redacted(&setting_with_key());
+        assert!(logs
+            .iter()
+            .any(|line| line.ends_with("Wakatime cli: Note: using cached config")));
+        assert!(!logs.iter().any(|line| line.contains("exited with")));
+        assert!(server.warnings_shown.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn non_zero_exit_is_a_failure_even_without_stderr() {
+        let server = server(setting_with_key(), &RecordingRunner::default());
+
+        assert!(!server.log_cli_output(&output_with_stderr(102, "")).await);
+        assert!(server
+            .warnings_shown
+            .lock()
+            .unwrap()
+            .contains_key("cli-Some(102)"));
+    }
+
+    #[tokio::test]
+    async fn heartbeat_with_stderr_on_success_is_recorded_as_sent() {
+        let runner = RecordingRunner::responding(|_| Ok(output_with_stderr(0, "info: synced")));
+        let server = server(setting_with_key(), &runner);
+        let uri = test_uri("stderr.rs").to_string();
+
+        server.send(file_event(&uri, true)).await;
+        server.join_tasks().await;
+
+        let history = server.history.recent(HISTORY_SIZE);
+        let entry = history
+            .as_array()
+            .and_then(|entries| entries.last())
+            .unwrap();
+        assert_eq!(entry["cli_exit_code"], 0);
+        assert!(!server.circuit.is_open());
+        assert!(server.warnings_shown.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn local_copy_is_removed_after_a_failed_cli_run() {
+        let temp_dir = crate::test_support::temp_dir("local-copy-failure");
+        // Whether each `--local-file` existed while wakatime-cli ran.
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let runner = RecordingRunner::responding({
+            let seen = seen.clone();
+            move |invocation| {
+                let local_file = invocation
+                    .args
+                    .windows(2)
+                    .find(|pair| pair[0] == "--local-file")
+                    .map(|pair| PathBuf::from(&pair[1]));
+                if let Some(path) = local_file {
+                    let exists = path.is_file();
+                    seen.lock().unwrap().push((path, exists));
+                }
+                Ok(crate::runner::tests::output(104, ""))
+            }
+        });
+        let server = server(
+            Setting {
+                temp_dir: Some(temp_dir.to_string_lossy().to_string()),
+                ..setting_with_key()
+            },
+            &runner,
+        );
+        let uri = test_uri("not-on-disk.rs").to_string();
+        server.documents.lock().await.insert(
+            uri.clone(),
+            Document {
+                text: "fn main() {}".to_string(),
+                unsaved: true,
+            },
+        );
+
+        server.send(file_event(&uri, true)).await;
+        server.join_tasks().await;
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 1);
+        let (path, existed) = &seen[0];
+        assert!(path.starts_with(&temp_dir), "{}", path.display());
+        assert!(existed);
+        assert!(!path.exists());
+        assert_eq!(std::fs::read_dir(&temp_dir).unwrap().count(), 0);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn hex_view_is_sent_as_the_binary_file() {
+        let runner = RecordingRunner::default();
+        let server = server(setting_with_key(), &runner);
+
+        server
+            .did_open(DidOpenTextDocumentParams {
+                text_document: TextDocumentItem::new(
+                    Url::parse("hex:///home/user/firmware.bin").unwrap(),
+                    String::new(),
+                    1,
+                    String::new(),
+                ),
+            })
+            .await;
+        server.join_tasks().await;
+
+        let invocations = runner.invocations();
+        assert_eq!(invocations.len(), 1);
+        let args = &invocations[0].args;
+        assert!(
+            has_arg(args, "--entity", "/home/user/firmware.bin"),
+            "{args:?}"
+        );
+        assert!(has_arg(args, "--language", "Binary"), "{args:?}");
+        assert!(!args.contains(&"--entity-type".to_string()), "{args:?}");
+    }
+
+    #[tokio::test]
+    async fn heartbeat_history_returns_the_last_twenty_records() {
+        let server = server(setting_with_key(), &RecordingRunner::default());
+        for index in 0..25 {
+            let event = Event {
+                project: Some("api".to_string()),
+                branch: Some("main".to_string()),
+                ..file_event(
+                    &format!("/home/user/api/src/file{index}.rs"),
+                    index % 2 == 0,
+                )
+            };
+            let outcome = if index % 3 == 0 {
+                Outcome::Suppressed
+            } else {
+                Outcome::Sent { exit_code: Some(0) }
+            };
+            server.history.record(
+                &event,
+                1_700_000_000.0 + index as f64,
+                outcome,
+                &Setting::default(),
+            );
+        }
+
+        let history = server.heartbeat_history().await.unwrap();
+
+        let records = history.as_array().unwrap();
+        assert_eq!(records.len(), 20);
+        assert_eq!(records[0]["entity"], "file5.rs");
+        assert_eq!(records[19]["entity"], "file24.rs");
+        for record in records {
+            for field in [
+                "timestamp",
+                "entity",
+                "project",
+                "branch",
+                "language",
+                "is_write",
+                "category",
+                "suppressed",
+                "cli_exit_code",
+            ] {
+                assert!(record.get(field).is_some(), "{field} missing: {record}");
+            }
+            assert_eq!(record["project"], "api");
+            assert_eq!(record["branch"], "main");
+            assert_eq!(record["category"], "coding");
+        }
+
+        // file6.rs was throttled, file7.rs sent.
+        assert_eq!(records[1]["suppressed"], true);
+        assert_eq!(records[1]["cli_exit_code"], serde_json::Value::Null);
+        assert_eq!(records[1]["is_write"], true);
+        assert_eq!(records[2]["suppressed"], false);
+        assert_eq!(records[2]["cli_exit_code"], 0);
+        assert_eq!(records[2]["is_write"], false);
+        assert_eq!(records[2]["timestamp"], 1_700_000_007.0);
+    }
+
+    #[tokio::test]
+    async fn send_passes_the_key_to_wakatime_cli() {
+        let runner = RecordingRunner::default();
+        let server = server(setting_with_key(), &runner);
+
+        server
+            .send(file_event("/tmp/wakatime-ls-test/key.rs", true))
+            .await;
+        server.join_tasks().await;
+
+        let invocations = runner.invocations();
+        assert_eq!(invocations.len(), 1);
+        assert_eq!(invocations[0].program, "wakatime-cli");
+        let args = &invocations[0].args;
+        assert!(has_arg(args, "--key", "waka_0123-secret"), "{args:?}");
+        assert!(has_arg(args, "--entity", "/tmp/wakatime-ls-test/key.rs"));
+        assert!(has_arg(args, "--language", "Rust"));
+        assert!(args.contains(&"--write".to_string()));
+    }
+
+    #[tokio::test]
+    async fn logged_command_never_contains_the_key() {
+        let runner = RecordingRunner::default();
+        let server = server(setting_with_key(), &runner);
+
+        server
+            .send(file_event("/tmp/wakatime-ls-test/redact.rs", false))
+            .await;
+        server.join_tasks().await;
+
+        // Masking on export is skipped with default settings, so these are
+        // the lines as they were logged.
+        let lines = server.logs.redacted(&Setting::default());
+        let command = lines
+            .iter()
+            .find(|line| line.contains("command:"))
+            .expect("the command is logged");
+        assert!(command.contains("--key"), "{command}");
+        assert!(command.contains("<redacted>"), "{command}");
+        assert!(lines.iter().all(|line| !line.contains("waka_0123-secret")));
+    }
+
+    #[tokio::test]
+    async fn send_subcommand_goes_through_the_server_pipeline() {
+        isolate_home();
+        let runner = RecordingRunner::default();
+        let matches = cli()
+            .try_get_matches_from([
+                "wakatime-ls",
+                "send",
+                "--entity",
+                "/tmp/wakatime-ls-test/send_once.rs",
+                "--write",
+                "--language",
+                "rust",
+                "--key",
+                "waka_0123-secret",
+            ])
+            .unwrap();
+        let (_, sub_matches) = matches.subcommand().unwrap();
+
+        let _ = send_once("wakatime-cli", None, sub_matches, Box::new(runner.clone())).await;
+
+        let invocations = runner.invocations();
+        assert_eq!(invocations.len(), 1);
+        let args = &invocations[0].args;
+        assert!(has_arg(
+            args,
+            "--entity",
+            "/tmp/wakatime-ls-test/send_once.rs"
+        ));
+        assert!(has_arg(args, "--key", "waka_0123-secret"));
+        assert!(has_arg(
+            args,
+            "--plugin",
+            &format!("wakatime-ls/{}", env!("CARGO_PKG_VERSION"))
+        ));
+        assert!(args.contains(&"--write".to_string()));
+    }
+
+    #[tokio::test]
+    async fn send_subcommand_skips_excluded_entities() {
+        isolate_home();
+        let runner = RecordingRunner::default();
+        let config = std::env::temp_dir().join(format!(
+            "wakatime-ls-test-send-exclude-{}.toml",
+            std::process::id()
+        ));
+        std::fs::write(&config, "exclude = [\"*.log\"]\n").unwrap();
+        let config_file = ConfigFile::load(&config).unwrap().unwrap();
+        let matches = cli()
+            .try_get_matches_from([
+                "wakatime-ls",
+                "send",
+                "--entity",
+                "/tmp/wakatime-ls-test/build.log",
+                "--key",
+                "waka_0123-secret",
+            ])
+            .unwrap();
+        let (_, sub_matches) = matches.subcommand().unwrap();
+
+        let _ = send_once(
+            "wakatime-cli",
+            Some(&config_file),
+            sub_matches,
+            Box::new(runner.clone()),
+        )
+        .await;
+
+        assert!(runner.invocations().is_empty());
+    }
+
+    fn send_matches(entity: &str) -> ArgMatches {
+        let matches = cli()
+            .try_get_matches_from([
+                "wakatime-ls",
+                "send",
+                "--entity",
+                entity,
+                "--write",
+                "--key",
+                "waka_0123-secret",
+            ])
+            .unwrap();
+        matches.subcommand().unwrap().1.clone()
+    }
+
+    #[tokio::test]
+    async fn send_subcommand_persists_a_heartbeat_left_to_retry() {
+        isolate_home();
+        let entity = "/tmp/wakatime-ls-test/send_once_retry.rs";
+        let runner = RecordingRunner::responding(|_| Ok(runner::tests::output(1, "offline")));
+
+        let code = send_once(
+            "wakatime-cli",
+            None,
+            &send_matches(entity),
+            Box::new(runner.clone()),
+        )
+        .await;
+
+        assert_eq!(code, ExitCode::from(1));
+        assert_eq!(runner.invocations().len(), 1);
+        // Other tests share the queue file; put back what isn't ours.
+        let (ours, others): (Vec<_>, Vec<_>) = queue::take_persisted()
+            .into_iter()
+            .partition(|(event, _)| event.uri == entity);
+        queue::persist(&others).ok();
+        assert_eq!(ours.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn send_subcommand_ignores_other_windows() {
+        isolate_home();
+        let entity = "/tmp/wakatime-ls-test/send_once_other_window.rs";
+        let active = serde_json::json!({
+            "uri": entity,
+            "timestamp": Local::now().to_rfc3339(),
+            "pid": std::process::id() + 1,
+            "is_write": true,
+        });
+        let path = active::active_path().unwrap();
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, active.to_string()).unwrap();
+        let runner = RecordingRunner::default();
+
+        let code = send_once(
+            "wakatime-cli",
+            None,
+            &send_matches(entity),
+            Box::new(runner.clone()),
+        )
+        .await;
+
+        assert_eq!(code, ExitCode::SUCCESS);
+        assert_eq!(runner.invocations().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn configuration_pull_retries_until_the_client_answers() {
+        let server = server(Setting::default(), &RecordingRunner::default());
+        let attempts = std::sync::atomic::AtomicU32::new(0);
+        let settings = serde_json::json!({ "api_url": "https://wakatime.example" });
+
+        let values = server
+            .with_configuration_retries(|| {
+                let attempt = attempts.fetch_add(1, Ordering::SeqCst) + 1;
+                let settings = settings.clone();
+                async move {
+                    if attempt < 3 {
+                        Err(tower_lsp::jsonrpc::Error::internal_error())
+                    } else {
+                        Ok(vec![settings])
+                    }
+                }
+            })
+            .await;
+
+        assert_eq!(values, Some(vec![settings]));
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+        let failures = server
+            .logs
+            .redacted(&Setting::default())
+            .into_iter()
+            .filter(|line| line.contains("configuration pull attempt"))
+            .count();
+        assert_eq!(failures, 2);
+    }
+
+    #[tokio::test]
+    async fn failed_configuration_pull_keeps_resolved_settings() {
+        let setting = Setting {
+            api_url: Some("https://wakatime.example".to_string()),
+            ..Default::default()
+        };
+        let server = server(setting, &RecordingRunner::default());
+
+        // The detached client isn't initialized, so every pull fails.
+        server.pull_configuration().await;
+
+        assert_eq!(
+            server.settings.load().api_url.as_deref(),
+            Some("https://wakatime.example")
+        );
+        let logs = server.logs.redacted(&Setting::default());
+        assert!(logs
+            .iter()
+            .any(|line| line.contains("could not pull configuration")));
+    }
+
+    #[tokio::test]
+    async fn version_command_reports_both_binaries() {
+        let runner = RecordingRunner::responding(|invocation| {
+            assert_eq!(invocation.args, ["--version"]);
+            Ok(runner::tests::output(
+                0,
+                if invocation.program == "wakatime-cli" {
+                    "v1.90.0\n"
+                } else {
+                    "wakatime_ls 0.4.0\n"
+                },
+            ))
+        });
+        let server = server(Setting::default(), &runner);
+
+        let versions = server.versions().await.unwrap().unwrap();
+
+        assert_eq!(versions["wakatime_cli"], "1.90.0");
+        assert_eq!(versions["wakatime_ls"], "0.4.0");
+        assert_eq!(versions["extension"], "unknown");
+        assert_eq!(runner.invocations().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn version_command_reports_missing_binaries_as_unknown() {
+        let runner = RecordingRunner::responding(|invocation| {
+            if invocation.program == "wakatime-cli" {
+                Err(std::io::Error::from(std::io::ErrorKind::NotFound))
+            } else {
+                Ok(runner::tests::output(1, ""))
+            }
+        });
+        let server = server(Setting::default(), &runner);
+
+        let versions = server.versions().await.unwrap().unwrap();
+
+        assert_eq!(versions["wakatime_cli"], "unknown");
+        assert_eq!(versions["wakatime_ls"], "unknown");
+    }
+
+    #[tokio::test]
+    async fn save_carries_the_last_edit_position() {
+        let runner = RecordingRunner::default();
+        let server = server(setting_with_key(), &runner);
+        let uri = test_uri("cursor.rs");
+
+        server.did_change(change(&uri, 1, 2, 4, "a")).await;
+        // Throttled, but it moves the cursor to 9:7.
+        server.did_change(change(&uri, 2, 9, 0, "fn main")).await;
+        server.did_save(save(&uri)).await;
+        server.join_tasks().await;
+
+        assert_eq!(runner.invocations().len(), 2);
+        let writes = write_invocations(&runner);
+        assert_eq!(writes.len(), 1);
+        assert!(has_arg(&writes[0], "--lineno", "10"), "{:?}", writes[0]);
+        assert!(has_arg(&writes[0], "--cursorpos", "8"), "{:?}", writes[0]);
+    }
+
+    #[tokio::test]
+    async fn full_document_change_keeps_the_last_position() {
+        let server = server(setting_with_key(), &RecordingRunner::default());
+        let uri = test_uri("full_sync.rs");
+        let entity = paths::uri_to_entity(&uri).entity;
+
+        server.did_change(change(&uri, 1, 4, 2, "x")).await;
+        assert_eq!(server.cursors.lock().await.get(&entity), Some(&(4, 3)));
+
+        let mut full = change(&uri, 2, 0, 0, "");
+        full.content_changes = vec![full_text("fn main() {}\n")];
+        server.did_change(full).await;
+        assert_eq!(server.cursors.lock().await.get(&entity), Some(&(4, 3)));
+
+        // A ranged change after a full one in the same notification wins.
+        let mut mixed = change(&uri, 3, 1, 5, "ab");
+        mixed
+            .content_changes
+            .insert(0, full_text("fn main() {}\nlet x;\n"));
+        server.did_change(mixed).await;
+        assert_eq!(server.cursors.lock().await.get(&entity), Some(&(1, 7)));
+
+        server.join_tasks().await;
+    }
+
+    /// Deletes lines `start..end`.
+    fn delete_lines(uri: &Url, version: i32, start: u32, end: u32) -> DidChangeTextDocumentParams {
+        DidChangeTextDocumentParams {
+            text_document: VersionedTextDocumentIdentifier::new(uri.clone(), version),
+            content_changes: vec![TextDocumentContentChangeEvent {
+                range: Some(Range::new(Position::new(start, 0), Position::new(end, 0))),
+                range_length: None,
+                text: String::new(),
+            }],
+        }
+    }
+
+    #[tokio::test]
+    async fn save_counts_lines_left_after_a_deletion() {
+        let runner = RecordingRunner::default();
+        let server = server(setting_with_key(), &runner);
+        let uri = test_uri("deleted_lines.rs");
+
+        server.did_open(open(&uri, "a\nb\nc\nd\n")).await;
+        server.did_change(delete_lines(&uri, 2, 1, 3)).await;
+        server.did_save(save(&uri)).await;
+        server.join_tasks().await;
+
+        let writes = write_invocations(&runner);
+        assert_eq!(writes.len(), 1);
+        assert!(
+            has_arg(&writes[0], "--lines-in-file", "2"),
+            "{:?}",
+            writes[0]
+        );
+        assert!(has_arg(&writes[0], "--lineno", "2"), "{:?}", writes[0]);
+        assert!(has_arg(&writes[0], "--cursorpos", "1"), "{:?}", writes[0]);
+    }
+
+    #[tokio::test]
+    async fn multi_cursor_edit_ends_at_the_last_change() {
+        let server = server(setting_with_key(), &RecordingRunner::default());
+        let uri = test_uri("multi_cursor.rs");
+        let entity = paths::uri_to_entity(&uri).entity;
+
+        server
+            .did_open(open(&uri, "let a;\nlet b;\nlet c;\n"))
+            .await;
+        let mut edit = change(&uri, 2, 0, 4, "x");
+        edit.content_changes
+            .extend(change(&uri, 2, 2, 4, "yz").content_changes);
+        server.did_change(edit).await;
+
+        assert_eq!(server.cursors.lock().await.get(&entity), Some(&(2, 6)));
+        assert_eq!(
+            server.documents.lock().await[&entity].text,
+            "let xa;\nlet b;\nlet yzc;\n"
+        );
+        server.join_tasks().await;
+    }
+
+    #[tokio::test]
+    async fn closing_forgets_the_document() {
+        let server = server(setting_with_key(), &RecordingRunner::default());
+        let uri = test_uri("closed.rs");
+        let entity = paths::uri_to_entity(&uri).entity;
+
+        server.did_open(open(&uri, "fn main() {}\n")).await;
+        server.did_change(change(&uri, 2, 0, 0, "// ")).await;
+        server
+            .did_close(DidCloseTextDocumentParams {
+                text_document: TextDocumentIdentifier::new(uri.clone()),
+            })
+            .await;
+        server.join_tasks().await;
+
+        assert!(!server.documents.lock().await.contains_key(&entity));
+        assert!(!server.cursors.lock().await.contains_key(&entity));
+        assert!(!server.versions.lock().await.contains_key(&entity));
+    }
+
+    #[tokio::test]
+    async fn shutdown_aborts_tasks_still_running_at_the_timeout() {
+        let server = server(setting_with_key(), &RecordingRunner::default());
+        let finished = Arc::new(AtomicBool::new(false));
+        let slow_finished = finished.clone();
+        server.spawn(async move {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            slow_finished.store(true, Ordering::SeqCst);
+        });
+        server.spawn(async {});
+
+        let started = Instant::now();
+        server.shutdown().await.unwrap();
+
+        assert!(started.elapsed() < TASK_SHUTDOWN_TIMEOUT + Duration::from_secs(1));
+        assert!(server.tasks.lock().unwrap().is_empty());
+        assert!(!finished.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn change_right_after_open_is_folded_into_the_open() {
+        let runner = RecordingRunner::default();
+        let server = server(
+            Setting {
+                interval_seconds: Some(0),
+                ..setting_with_key()
+            },
+            &runner,
+        );
+        let uri = test_uri("open-then-change.rs");
+
+        server.did_open(open(&uri, "fn main() {}\n")).await;
+        server.did_change(change(&uri, 2, 0, 0, "// ")).await;
+        server.join_tasks().await;
+
+        assert_eq!(runner.invocations().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn edit_after_the_open_window_is_its_own_heartbeat() {
+        let runner = RecordingRunner::default();
+        let server = server(
+            Setting {
+                interval_seconds: Some(0),
+                ..setting_with_key()
+            },
+            &runner,
+        );
+        let uri = test_uri("open-then-edit.rs");
+
+        server.did_open(open(&uri, "fn main() {}\n")).await;
+        tokio::time::sleep(OPEN_COLLAPSE_WINDOW + Duration::from_millis(100)).await;
+        server.did_change(change(&uri, 2, 0, 0, "// ")).await;
+        server.join_tasks().await;
+
+        assert_eq!(runner.invocations().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn only_writes_and_changes_past_the_interval_are_sent() {
+        let runner = RecordingRunner::default();
+        let server = server(setting_with_key(), &runner);
+        let uri = test_uri("timeline.rs").to_string();
+        let start = Local::now() - TimeDelta::hours(1);
+        let open = Event {
+            is_open: true,
+            ..file_event(&uri, false)
+        };
+
+        for (seconds, event, sent) in [
+            (0, open, true),
+            (10, file_event(&uri, false), false),
+            (60, file_event(&uri, false), false),
+            (90, file_event(&uri, true), true),
+            (100, file_event(&uri, true), true),
+            // Non-writes are throttled from the last heartbeat, writes too.
+            (219, file_event(&uri, false), false),
+            (221, file_event(&uri, false), true),
+            (300, file_event(&uri, false), false),
+            (342, file_event(&uri, false), true),
+        ] {
+            let before = runner.invocations().len();
+            let is_write = event.is_write;
+            server
+                .send_at(event, start + TimeDelta::seconds(seconds))
+                .await;
+            server.wait_for_tasks().await;
+
+            let sent_now = runner.invocations().len() > before;
+            assert_eq!(sent_now, sent, "at {seconds}s, write: {is_write}");
+        }
+        assert_eq!(write_invocations(&runner).len(), 2);
+    }
 }
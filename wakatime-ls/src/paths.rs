@@ -0,0 +1,257 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::Path,
+    sync::{Mutex, OnceLock},
+};
+
+use url::Url;
+
+/// URI schemes naming a file on disk, with the language to report when the
+/// editor's language id doesn't describe the view, as in the hex editor.
+const FILE_SCHEMES: &[(&str, Option<&str>)] = &[
+    ("file", None),
+    ("hex", Some("Binary")),
+    ("binary", Some("Binary")),
+];
+
+/// A document as wakatime-cli sees it.
+pub struct UriEntity {
+    /// The file's path, or the URI itself for documents that aren't files.
+    pub entity: String,
+    /// The language its scheme implies, if any.
+    pub language: Option<&'static str>,
+    /// Not a file, like an untitled buffer; sent with `--entity-type app`.
+    pub is_app: bool,
+}
+
+/// Turns a document URI into the entity sent to wakatime-cli. File URIs are
+/// decoded into a filesystem path; other schemes, or file URIs naming another
+/// host, are passed on as they are.
+pub fn uri_to_entity(uri: &Url) -> UriEntity {
+    let file_scheme = FILE_SCHEMES
+        .iter()
+        .find(|(scheme, _)| *scheme == uri.scheme());
+
+    match file_scheme.and_then(|(_, language)| Some((to_file_path(uri)?, *language))) {
+        Some((entity, language)) => UriEntity {
+            entity,
+            language,
+            is_app: false,
+        },
+        None => UriEntity {
+            entity: uri.to_string(),
+            language: None,
+            is_app: true,
+        },
+    }
+}
+
+/// Decodes the path of a file-like URI. Drive paths lose the slash before the
+/// drive letter (`file:///C:/src` is `C:/src`) even off Windows, where
+/// `Url::to_file_path` keeps it.
+fn to_file_path(uri: &Url) -> Option<String> {
+    let mut file_uri = Url::parse("file:///").ok()?;
+    file_uri.set_host(uri.host_str()).ok()?;
+    file_uri.set_path(uri.path());
+
+    let path = file_uri.to_file_path().ok()?.to_string_lossy().to_string();
+    match path.as_bytes() {
+        [b'/', drive, b':', ..] if drive.is_ascii_alphabetic() => Some(path[1..].to_string()),
+        _ => Some(path),
+    }
+}
+
+/// Files git opens in the editor for messages and interactive rebases.
+const GIT_MESSAGE_FILES: &[&str] = &[
+    "COMMIT_EDITMSG",
+    "MERGE_MSG",
+    "SQUASH_MSG",
+    "TAG_EDITMSG",
+    "EDIT_DESCRIPTION",
+    "git-rebase-todo",
+    "addp-hunk-edit.diff",
+];
+
+/// Whether the entity is one of git's own files, like a commit message being
+/// written, rather than part of the project.
+pub fn is_git_internal(entity: &str) -> bool {
+    let path = Path::new(entity);
+    path.components().any(|c| c.as_os_str() == ".git")
+        || path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| GIT_MESSAGE_FILES.contains(&name))
+}
+
+/// Drive letter -> UNC root of mapped network drives, looked up once.
+static NETWORK_DRIVES: OnceLock<HashMap<char, String>> = OnceLock::new();
+
+/// Caches the mapped network drives; called at startup so heartbeats don't
+/// query the network provider.
+pub fn init_network_drives() {
+    NETWORK_DRIVES.get_or_init(mapped_network_drives);
+}
+
+/// Rewrites a path on a mapped network drive (`Z:\project\file.rs`) to its UNC
+/// form (`\\server\share\project\file.rs`) so both spellings yield the same
+/// entity. Other paths, and every path off Windows, are returned unchanged.
+pub fn normalize_windows_network_path(path: &str) -> String {
+    let mut chars = path.chars();
+    let (Some(letter), Some(':')) = (chars.next(), chars.next()) else {
+        return path.to_string();
+    };
+
+    let drives = NETWORK_DRIVES.get_or_init(mapped_network_drives);
+    match drives.get(&letter.to_ascii_uppercase()) {
+        Some(unc) => format!("{}{}", unc.trim_end_matches('\\'), &path[2..]),
+        None => path.to_string(),
+    }
+}
+
+#[cfg(windows)]
+fn mapped_network_drives() -> HashMap<char, String> {
+    use winapi::um::{fileapi::GetDriveTypeW, winbase::DRIVE_REMOTE, winnetwk::WNetGetConnectionW};
+
+    let wide = |s: String| -> Vec<u16> { s.encode_utf16().chain(Some(0)).collect() };
+    let mut drives = HashMap::new();
+
+    for letter in 'A'..='Z' {
+        let root = wide(format!("{letter}:\\"));
+        if unsafe { GetDriveTypeW(root.as_ptr()) } != DRIVE_REMOTE {
+            continue;
+        }
+
+        let local = wide(format!("{letter}:"));
+        let mut remote = vec![0u16; 1024];
+        let mut len = remote.len() as u32;
+        if unsafe { WNetGetConnectionW(local.as_ptr(), remote.as_mut_ptr(), &mut len) } != 0 {
+            continue;
+        }
+
+        let end = remote.iter().position(|&c| c == 0).unwrap_or(remote.len());
+        drives.insert(letter, String::from_utf16_lossy(&remote[..end]));
+    }
+
+    drives
+}
+
+#[cfg(not(windows))]
+fn mapped_network_drives() -> HashMap<char, String> {
+    HashMap::new()
+}
+
+/// Canonicalized entity paths, so symlinked checkouts resolve to one entity
+/// without hitting the filesystem on every keystroke.
+#[derive(Default)]
+pub struct SymlinkCache {
+    resolved: Mutex<HashMap<String, String>>,
+}
+
+impl SymlinkCache {
+    /// Resolves symlinks in `path`; paths that can't be canonicalized
+    /// (deleted files, virtual buffers) are returned unchanged and retried
+    /// next time.
+    pub fn canonicalize(&self, path: &str) -> String {
+        if let Some(resolved) = self.resolved.lock().unwrap().get(path) {
+            return resolved.clone();
+        }
+
+        let Ok(resolved) = fs::canonicalize(Path::new(path)) else {
+            return path.to_string();
+        };
+        let resolved = strip_verbatim_prefix(&resolved.to_string_lossy()).to_string();
+
+        self.resolved
+            .lock()
+            .unwrap()
+            .insert(path.to_string(), resolved.clone());
+
+        resolved
+    }
+
+    pub fn clear(&self) {
+        self.resolved.lock().unwrap().clear();
+    }
+}
+
+/// `fs::canonicalize` on Windows returns `\\?\C:\...`; drop the verbatim prefix
+/// for drive paths so entities keep their usual form.
+fn strip_verbatim_prefix(path: &str) -> &str {
+    match path.strip_prefix(r"\\?\") {
+        Some(rest) if rest.as_bytes().get(1) == Some(&b':') => rest,
+        _ => path,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entity(uri: &str) -> UriEntity {
+        uri_to_entity(&Url::parse(uri).unwrap())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn hex_and_binary_views_are_the_underlying_file() {
+        for uri in [
+            "hex:///home/user/firmware.bin",
+            "binary:///home/user/firmware.bin",
+        ] {
+            let entity = entity(uri);
+            assert_eq!(entity.entity, "/home/user/firmware.bin", "{uri}");
+            assert_eq!(entity.language, Some("Binary"), "{uri}");
+            assert!(!entity.is_app, "{uri}");
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn file_uri_keeps_the_editor_language() {
+        let entity = entity("file:///home/user/src/main%20file.rs");
+        assert_eq!(entity.entity, "/home/user/src/main file.rs");
+        assert_eq!(entity.language, None);
+        assert!(!entity.is_app);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn drive_paths_lose_the_leading_slash() {
+        assert_eq!(
+            entity("hex:///C:/fw/firmware.bin").entity,
+            "C:/fw/firmware.bin"
+        );
+    }
+
+    #[test]
+    fn file_uri_with_a_drive_is_a_decoded_drive_path() {
+        let entity = entity("file:///C:/foo bar/baz.rs");
+        let expected = if cfg!(windows) {
+            r"C:\foo bar\baz.rs"
+        } else {
+            "C:/foo bar/baz.rs"
+        };
+        assert_eq!(entity.entity, expected);
+        assert_eq!(entity.language, None);
+        assert!(!entity.is_app);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn percent_encoded_non_ascii_is_decoded() {
+        let encoded = entity("file:///home/user/%C3%A4.rs");
+        assert_eq!(encoded.entity, "/home/user/ä.rs");
+        assert!(!encoded.is_app);
+
+        assert_eq!(entity("file:///home/user/ä.rs").entity, "/home/user/ä.rs");
+    }
+
+    #[test]
+    fn other_schemes_are_apps() {
+        let entity = entity("untitled:Untitled-1");
+        assert_eq!(entity.entity, "untitled:Untitled-1");
+        assert_eq!(entity.language, None);
+        assert!(entity.is_app);
+    }
+}
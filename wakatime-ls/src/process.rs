@@ -0,0 +1,214 @@
+use std::{
+    io,
+    process::{ExitStatus, Output, Stdio},
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
+    process::{Child, Command},
+};
+
+/// How long a timed out process gets to exit after being asked to before it
+/// is killed.
+const TERMINATE_GRACE: Duration = Duration::from_secs(2);
+
+/// Runs `command` like `Command::output`, writing `stdin` to it, and
+/// terminates it gracefully if it doesn't finish within `timeout`. The process
+/// is killed if the returned future is dropped, e.g. on a cancelled request.
+pub async fn output(
+    command: &mut Command,
+    stdin: Option<&str>,
+    timeout: Duration,
+) -> io::Result<Output> {
+    command
+        .stdin(if stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    let mut child = command.spawn()?;
+
+    if let (Some(input), Some(mut pipe)) = (stdin, child.stdin.take()) {
+        pipe.write_all(input.as_bytes()).await?;
+        pipe.write_all(b"\n").await?;
+    }
+
+    let stdout = read_to_end(child.stdout.take());
+    let stderr = read_to_end(child.stderr.take());
+    let (status, stdout, stderr) = tokio::join!(
+        terminate_gracefully(&mut child, timeout, TERMINATE_GRACE),
+        stdout,
+        stderr
+    );
+
+    Ok(Output {
+        status: status?,
+        stdout: stdout?,
+        stderr: stderr?,
+    })
+}
+
+async fn read_to_end(pipe: Option<impl AsyncRead + Unpin>) -> io::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    if let Some(mut pipe) = pipe {
+        pipe.read_to_end(&mut buf).await?;
+    }
+    Ok(buf)
+}
+
+/// Waits for `child` up to `soft_timeout`, then asks it to exit (SIGTERM, or
+/// `taskkill` on Windows) so it can flush what it buffered, and kills it if
+/// it's still running after `hard_timeout`. A process that had to be
+/// terminated is reported as `TimedOut`.
+pub async fn terminate_gracefully(
+    child: &mut Child,
+    soft_timeout: Duration,
+    hard_timeout: Duration,
+) -> io::Result<ExitStatus> {
+    if let Ok(status) = tokio::time::timeout(soft_timeout, child.wait()).await {
+        return status;
+    }
+
+    request_exit(child).await;
+    if tokio::time::timeout(hard_timeout, child.wait())
+        .await
+        .is_err()
+    {
+        child.kill().await?;
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::TimedOut,
+        format!("process didn't exit within {soft_timeout:?}"),
+    ))
+}
+
+#[cfg(unix)]
+async fn request_exit(child: &Child) {
+    if let Some(pid) = child.id() {
+        // SAFETY: `pid` belongs to a child that hasn't been reaped yet.
+        unsafe {
+            libc::kill(pid as libc::pid_t, libc::SIGTERM);
+        }
+    }
+}
+
+#[cfg(windows)]
+async fn request_exit(child: &Child) {
+    if let Some(pid) = child.id() {
+        Command::new("taskkill")
+            .args(["/F", "/T", "/PID", &pid.to_string()])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .await
+            .ok();
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+
+    const SOFT: Duration = Duration::from_millis(300);
+    const HARD: Duration = Duration::from_millis(700);
+
+    fn spawn_shell(script: &str, stdout: Stdio) -> Child {
+        Command::new("sh")
+            .args(["-c", script])
+            .stdin(Stdio::null())
+            .stdout(stdout)
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn process_finishing_in_time_keeps_its_status() {
+        let mut child = spawn_shell("exit 3", Stdio::null());
+
+        let status = terminate_gracefully(&mut child, SOFT, HARD).await.unwrap();
+
+        assert_eq!(status.code(), Some(3));
+    }
+
+    #[tokio::test]
+    async fn slow_process_is_asked_to_exit_after_the_soft_timeout() {
+        let mut child = spawn_shell(
+            "trap 'echo terminated; exit 0' TERM; while :; do sleep 0.1; done",
+            Stdio::piped(),
+        );
+        let started = Instant::now();
+
+        let err = terminate_gracefully(&mut child, SOFT, HARD)
+            .await
+            .unwrap_err();
+        let elapsed = started.elapsed();
+
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(elapsed >= SOFT, "terminated after {elapsed:?}");
+        assert!(elapsed < SOFT + HARD, "killed after {elapsed:?}");
+        let stdout = read_to_end(child.stdout.take()).await.unwrap();
+        assert_eq!(String::from_utf8_lossy(&stdout).trim(), "terminated");
+    }
+
+    #[tokio::test]
+    async fn process_ignoring_the_request_is_killed_after_the_hard_timeout() {
+        let mut child = spawn_shell("trap '' TERM; while :; do sleep 0.1; done", Stdio::null());
+        let started = Instant::now();
+
+        let err = terminate_gracefully(&mut child, SOFT, HARD)
+            .await
+            .unwrap_err();
+        let elapsed = started.elapsed();
+
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(elapsed >= SOFT + HARD, "killed after {elapsed:?}");
+        let status = child.try_wait().unwrap().expect("process still running");
+        assert!(!status.success());
+    }
+
+    /// Whether `pid` is still running, as opposed to gone or a zombie
+    /// waiting to be reaped.
+    #[cfg(target_os = "linux")]
+    fn is_running(pid: &str) -> bool {
+        std::fs::read_to_string(format!("/proc/{pid}/stat")).is_ok_and(|stat| {
+            stat.rsplit(')')
+                .next()
+                .is_some_and(|state| !state.trim_start().starts_with('Z'))
+        })
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn dropping_the_output_future_kills_the_process() {
+        let pid_file = crate::test_support::temp_dir("process-cancel").join("pid");
+        let script = format!(
+            "echo $$ > '{}'; while :; do sleep 0.1; done",
+            pid_file.display()
+        );
+        let mut command = Command::new("sh");
+        command.args(["-c", &script]);
+
+        // Like a request future tower-lsp drops on `$/cancelRequest`.
+        let cancelled =
+            tokio::time::timeout(SOFT, output(&mut command, None, Duration::from_secs(60))).await;
+        assert!(cancelled.is_err());
+
+        let pid = std::fs::read_to_string(&pid_file).unwrap();
+        let pid = pid.trim();
+        let deadline = Instant::now() + HARD;
+        while is_running(pid) && Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(!is_running(pid), "process {pid} still running");
+    }
+}
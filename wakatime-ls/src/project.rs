@@ -0,0 +1,812 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use globset::{GlobBuilder, GlobMatcher};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DetectedProject {
+    pub name: String,
+    pub alternate: Option<String>,
+    pub folder: PathBuf,
+}
+
+/// Extracts a project name from a marker file.
+enum Detector {
+    Content(fn(&str) -> Option<String>),
+    /// Also given the file name, for markers matched by extension.
+    FileNameAndContent(fn(&str, &str) -> Option<String>),
+}
+
+/// Files marking a project root, checked in order in each directory while
+/// walking up from the entity. `*.ext` matches any file with that extension.
+/// Markers without a detector name the project after their directory.
+fn project_file_markers() -> &'static [(&'static str, Option<Detector>)] {
+    &[
+        ("pom.xml", Some(Detector::Content(try_detect_from_pom_xml))),
+        (
+            "package.json",
+            Some(Detector::Content(try_detect_from_package_json)),
+        ),
+        (
+            "Project.toml",
+            Some(Detector::Content(try_detect_from_julia_project_toml)),
+        ),
+        (
+            "JuliaProject.toml",
+            Some(Detector::Content(try_detect_from_julia_project_toml)),
+        ),
+        ("Manifest.toml", None),
+        (
+            "*.cabal",
+            Some(Detector::FileNameAndContent(try_detect_from_cabal)),
+        ),
+        (
+            "package.yaml",
+            Some(Detector::Content(try_detect_from_stack_yaml)),
+        ),
+        (
+            "stack.yaml",
+            Some(Detector::Content(try_detect_from_stack_yaml)),
+        ),
+        (
+            "build.zig.zon",
+            Some(Detector::Content(try_detect_from_zig_zon)),
+        ),
+        ("build.zig", None),
+    ]
+}
+
+pub fn detect_project(entity: &Path) -> Option<DetectedProject> {
+    for dir in entity.ancestors().skip(1) {
+        for (marker, detector) in project_file_markers() {
+            let Some(path) = find_marker(dir, marker) else {
+                continue;
+            };
+            let name = match detector {
+                Some(Detector::Content(detector)) => fs::read_to_string(&path)
+                    .ok()
+                    .and_then(|content| detector(&content)),
+                Some(Detector::FileNameAndContent(detector)) => {
+                    let file_name = path
+                        .file_name()
+                        .map(|name| name.to_string_lossy())
+                        .unwrap_or_default();
+                    fs::read_to_string(&path)
+                        .ok()
+                        .and_then(|content| detector(&file_name, &content))
+                }
+                None => dir_name(dir),
+            };
+
+            if let Some(name) = name {
+                return Some(DetectedProject {
+                    name,
+                    alternate: find_workspace_root(dir),
+                    folder: dir.to_path_buf(),
+                });
+            }
+        }
+    }
+
+    None
+}
+
+/// Resolves a marker to an existing file in `dir`, picking the first by name
+/// for `*.ext` markers.
+fn find_marker(dir: &Path, marker: &str) -> Option<PathBuf> {
+    let Some(extension) = marker.strip_prefix("*.") else {
+        let path = dir.join(marker);
+        return path.is_file().then_some(path);
+    };
+
+    fs::read_dir(dir)
+        .ok()?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == extension) && path.is_file())
+        .min()
+}
+
+fn dir_name(dir: &Path) -> Option<String> {
+    dir.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+}
+
+fn try_detect_from_package_json(content: &str) -> Option<String> {
+    let value: serde_json::Value = serde_json::from_str(content).ok()?;
+    value
+        .get("name")?
+        .as_str()
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+}
+
+/// Elements of a Maven POM whose children carry an `<artifactId>` other than
+/// the project's own.
+const POM_NESTED_ELEMENTS: &[&str] = &[
+    "parent",
+    "dependencies",
+    "dependencyManagement",
+    "build",
+    "reporting",
+    "profiles",
+];
+
+/// Reads the `<artifactId>` of the root `<project>` element of a Maven
+/// `pom.xml`, skipping those of its parent, dependencies and plugins.
+fn try_detect_from_pom_xml(content: &str) -> Option<String> {
+    let mut project = strip_xml_sections(content, "<!--", "-->");
+    project = project[project.find("<project")?..].to_string();
+    for element in POM_NESTED_ELEMENTS {
+        project = strip_xml_sections(&project, &format!("<{element}>"), &format!("</{element}>"));
+    }
+
+    let start = project.find("<artifactId>")? + "<artifactId>".len();
+    let end = start + project[start..].find("</artifactId>")?;
+    let artifact_id = project[start..end].trim();
+    (!artifact_id.is_empty()).then(|| artifact_id.to_string())
+}
+
+/// Removes everything from each `open` to the matching `close`, which must
+/// not nest.
+fn strip_xml_sections(content: &str, open: &str, close: &str) -> String {
+    let mut stripped = String::with_capacity(content.len());
+    let mut rest = content;
+
+    while let Some(start) = rest.find(open) {
+        stripped.push_str(&rest[..start]);
+        rest = match rest[start..].find(close) {
+            Some(end) => &rest[start + end + close.len()..],
+            None => "",
+        };
+    }
+
+    stripped.push_str(rest);
+    stripped
+}
+
+/// Reads the top-level `name = "MyPackage"` of a Julia `Project.toml`.
+fn try_detect_from_julia_project_toml(content: &str) -> Option<String> {
+    for line in content.lines() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            break;
+        }
+
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        if key.trim() == "name" {
+            let name = value.trim().trim_matches('"');
+            return (!name.is_empty()).then(|| name.to_string());
+        }
+    }
+
+    None
+}
+
+/// Reads the `name:` field of a `.cabal` file, falling back to the file stem,
+/// which cabal expects to match the package name anyway.
+fn try_detect_from_cabal(file_name: &str, content: &str) -> Option<String> {
+    top_level_yaml_like_field(content, "name").or_else(|| {
+        file_name
+            .strip_suffix(".cabal")
+            .filter(|stem| !stem.is_empty())
+            .map(str::to_string)
+    })
+}
+
+/// Reads the top-level `name:` of a `stack.yaml` or hpack `package.yaml`.
+fn try_detect_from_stack_yaml(content: &str) -> Option<String> {
+    top_level_yaml_like_field(content, "name")
+}
+
+/// Reads the package name of a Zig `build.zig.zon`: `.name = "my_project"`,
+/// or the enum literal `.name = .my_project` (`.@"my-project"`) of newer Zig.
+fn try_detect_from_zig_zon(content: &str) -> Option<String> {
+    let mut rest = content;
+
+    while let Some(start) = rest.find(".name") {
+        rest = &rest[start + ".name".len()..];
+        let Some(value) = rest.trim_start().strip_prefix('=') else {
+            continue;
+        };
+        let value = value.trim_start();
+
+        let name = if let Some(quoted) = value
+            .strip_prefix(".@\"")
+            .or_else(|| value.strip_prefix('"'))
+        {
+            quoted.split('"').next()
+        } else if let Some(literal) = value.strip_prefix('.') {
+            literal
+                .split(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .next()
+        } else {
+            None
+        };
+
+        if let Some(name) = name.filter(|name| !name.is_empty()) {
+            return Some(name.to_string());
+        }
+    }
+
+    None
+}
+
+/// Finds an unindented `key: value` line, as used by YAML and cabal files.
+/// Cabal field names are case-insensitive.
+fn top_level_yaml_like_field(content: &str, key: &str) -> Option<String> {
+    content.lines().find_map(|line| {
+        if line.starts_with(char::is_whitespace) {
+            return None;
+        }
+        let (field, value) = line.split_once(':')?;
+        if !field.trim().eq_ignore_ascii_case(key) {
+            return None;
+        }
+
+        let value = value.split_whitespace().next()?;
+        let value = value.trim_matches(|c| c == '"' || c == '\'');
+        (!value.is_empty()).then(|| value.to_string())
+    })
+}
+
+/// Walks up from a package directory looking for an npm/yarn workspace root,
+/// i.e. a `package.json` declaring `workspaces`, or a Maven aggregator `pom.xml`
+/// listing `<modules>`, and returns its name.
+fn find_workspace_root(package_dir: &Path) -> Option<String> {
+    for dir in package_dir.ancestors().skip(1) {
+        if let Ok(content) = fs::read_to_string(dir.join("pom.xml")) {
+            if content.contains("<modules>") {
+                return try_detect_from_pom_xml(&content);
+            }
+        }
+
+        let Ok(content) = fs::read_to_string(dir.join("package.json")) else {
+            continue;
+        };
+        let Ok(value) = serde_json::from_str::<serde_json::Value>(&content) else {
+            continue;
+        };
+
+        if value.get("workspaces").is_some() {
+            return value
+                .get("name")
+                .and_then(|name| name.as_str())
+                .filter(|name| !name.is_empty())
+                .map(str::to_string);
+        }
+    }
+
+    None
+}
+
+/// Directories configured through `project_roots` that are treated as
+/// independent projects, e.g. `packages/*` in a monorepo.
+#[derive(Default)]
+pub struct ProjectRoots {
+    matchers: Vec<GlobMatcher>,
+    cache: Mutex<HashMap<PathBuf, Option<PathBuf>>>,
+}
+
+impl ProjectRoots {
+    /// Compiles the patterns, returning the ones that failed alongside.
+    pub fn new(patterns: &[String]) -> (Self, Vec<String>) {
+        let mut matchers = Vec::new();
+        let mut invalid = Vec::new();
+
+        for pattern in patterns {
+            let pattern = pattern.trim_end_matches('/');
+            match GlobBuilder::new(pattern).literal_separator(true).build() {
+                Ok(glob) => matchers.push(glob.compile_matcher()),
+                Err(_) => invalid.push(pattern.to_string()),
+            }
+        }
+
+        (
+            Self {
+                matchers,
+                cache: Mutex::default(),
+            },
+            invalid,
+        )
+    }
+
+    /// Finds the deepest configured root containing the entity. Patterns are
+    /// matched relative to the workspace folder the entity belongs to.
+    pub fn find(&self, entity: &Path, workspace_folders: &[PathBuf]) -> Option<PathBuf> {
+        if self.matchers.is_empty() {
+            return None;
+        }
+
+        let dir = entity.parent()?;
+        if let Some(root) = self.cache.lock().unwrap().get(dir) {
+            return root.clone();
+        }
+
+        let root = workspace_folders
+            .iter()
+            .filter(|folder| dir.starts_with(folder))
+            .max_by_key(|folder| folder.components().count())
+            .and_then(|folder| {
+                dir.ancestors()
+                    .take_while(|ancestor| *ancestor != folder.as_path())
+                    .find(|ancestor| {
+                        ancestor.strip_prefix(folder).is_ok_and(|relative| {
+                            self.matchers.iter().any(|m| m.is_match(relative))
+                        })
+                    })
+                    .map(Path::to_path_buf)
+            });
+
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(dir.to_path_buf(), root.clone());
+
+        root
+    }
+
+    pub fn clear_cache(&self) {
+        self.cache.lock().unwrap().clear();
+    }
+}
+
+/// How long a detected project is reused before its marker files are read
+/// again.
+const DETECTED_TTL: Duration = Duration::from_secs(30);
+
+/// Projects detected from marker files, by entity, so rapid edits don't walk
+/// and read the directories above the entity for every heartbeat.
+#[derive(Default)]
+pub struct DetectedProjects {
+    cache: Mutex<HashMap<PathBuf, (Instant, Option<DetectedProject>)>>,
+}
+
+impl DetectedProjects {
+    /// `detect_project` for `entity`, reused for `DETECTED_TTL` after `now`.
+    pub fn detect(&self, entity: &Path, now: Instant) -> Option<DetectedProject> {
+        if let Some((detected_at, detected)) = self.cache.lock().unwrap().get(entity) {
+            if now.saturating_duration_since(*detected_at) < DETECTED_TTL {
+                return detected.clone();
+            }
+        }
+
+        let detected = detect_project(entity);
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(entity.to_path_buf(), (now, detected.clone()));
+        detected
+    }
+
+    /// Forgets the projects of `saved` and of every entity next to or below
+    /// it, as saving a marker file may rename their project.
+    pub fn invalidate(&self, saved: &Path) {
+        let dir = saved.parent().unwrap_or(saved);
+        self.cache
+            .lock()
+            .unwrap()
+            .retain(|entity, _| !entity.starts_with(dir));
+    }
+
+    pub fn clear_cache(&self) {
+        self.cache.lock().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_dir;
+
+    /// `root/package.json` declaring workspaces, with one package below it.
+    fn workspace(name: &str) -> PathBuf {
+        let root = temp_dir(name);
+        fs::write(
+            root.join("package.json"),
+            r#"{ "name": "my-monorepo", "workspaces": ["packages/*"] }"#,
+        )
+        .unwrap();
+        let package = root.join("packages").join("my-package");
+        fs::create_dir_all(package.join("src")).unwrap();
+        fs::write(package.join("package.json"), r#"{ "name": "my-package" }"#).unwrap();
+        root
+    }
+
+    #[test]
+    fn workspace_package_has_root_as_alternate_project() {
+        let root = workspace("workspace-package");
+        let package = root.join("packages").join("my-package");
+
+        let detected = detect_project(&package.join("src").join("index.js")).unwrap();
+
+        assert_eq!(detected.name, "my-package");
+        assert_eq!(detected.alternate.as_deref(), Some("my-monorepo"));
+        assert_eq!(detected.folder, package);
+    }
+
+    #[test]
+    fn workspace_root_file_has_no_alternate_project() {
+        let root = workspace("workspace-root");
+
+        let detected = detect_project(&root.join("scripts").join("release.js")).unwrap();
+
+        assert_eq!(detected.name, "my-monorepo");
+        assert_eq!(detected.alternate, None);
+    }
+
+    #[test]
+    fn package_outside_a_workspace_has_no_alternate_project() {
+        let root = temp_dir("plain-package");
+        fs::write(root.join("package.json"), r#"{ "name": "my-monorepo" }"#).unwrap();
+        let package = root.join("packages").join("my-package");
+        fs::create_dir_all(&package).unwrap();
+        fs::write(package.join("package.json"), r#"{ "name": "my-package" }"#).unwrap();
+
+        let detected = detect_project(&package.join("index.js")).unwrap();
+
+        assert_eq!(detected.name, "my-package");
+        assert_eq!(detected.alternate, None);
+    }
+
+    const JULIA_PROJECT: &str = r#"name = "MyPackage"
+uuid = "7876af07-990d-54b4-ab0e-23690620f79a"
+authors = ["Jane Doe <jane@example.com>"]
+version = "0.3.1"
+
+[deps]
+DataFrames = "a93c6f00-e57d-5684-b7b6-d8193f3e46c0"
+LinearAlgebra = "37e2e46d-f89d-539d-b4ee-838fcccc9c8e"
+
+[compat]
+DataFrames = "1.6"
+julia = "1.9"
+"#;
+
+    #[test]
+    fn julia_project_name_is_read_before_the_sections() {
+        assert_eq!(
+            try_detect_from_julia_project_toml(JULIA_PROJECT).as_deref(),
+            Some("MyPackage")
+        );
+    }
+
+    #[test]
+    fn julia_environment_without_a_name_has_no_project() {
+        let environment = r#"[deps]
+name = "NotTheProject"
+Plots = "91a5bcdd-55d7-5caf-9e0b-520d859cae80"
+
+[compat]
+julia = "1.10"
+"#;
+        assert_eq!(try_detect_from_julia_project_toml(environment), None);
+    }
+
+    #[test]
+    fn julia_project_alias_names_the_project() {
+        let root = temp_dir("julia-alias");
+        fs::write(root.join("JuliaProject.toml"), JULIA_PROJECT).unwrap();
+
+        let detected = detect_project(&root.join("src").join("MyPackage.jl")).unwrap();
+
+        assert_eq!(detected.name, "MyPackage");
+        assert_eq!(detected.folder, root);
+    }
+
+    #[test]
+    fn julia_manifest_names_the_project_after_its_directory() {
+        let root = temp_dir("julia-manifest");
+        let environment = root.join("analysis");
+        fs::create_dir_all(&environment).unwrap();
+        fs::write(
+            environment.join("Project.toml"),
+            "[deps]\nCSV = \"336ed68f-0bac-5ca0-87d4-7b16caf5d00b\"\n",
+        )
+        .unwrap();
+        fs::write(
+            environment.join("Manifest.toml"),
+            "julia_version = \"1.10.0\"\nmanifest_format = \"2.0\"\n",
+        )
+        .unwrap();
+
+        let detected = detect_project(&environment.join("notebook.jl")).unwrap();
+
+        assert_eq!(detected.name, "analysis");
+        assert_eq!(detected.folder, environment);
+    }
+
+    const CABAL: &str = "cabal-version:      3.0
+name:               my-project
+version:            0.1.0.0
+synopsis:           A small example
+license:            BSD-3-Clause
+build-type:         Simple
+
+library
+    exposed-modules:  MyLib
+    build-depends:    base ^>=4.17.0.0
+    hs-source-dirs:   src
+    default-language: Haskell2010
+
+executable my-project
+    main-is:          Main.hs
+    build-depends:
+        base ^>=4.17.0.0,
+        my-project
+";
+
+    #[test]
+    fn cabal_name_field_names_the_project() {
+        assert_eq!(
+            try_detect_from_cabal("other-name.cabal", CABAL).as_deref(),
+            Some("my-project")
+        );
+        assert_eq!(
+            try_detect_from_cabal("x.cabal", "Name: Mixed-Case\n").as_deref(),
+            Some("Mixed-Case")
+        );
+    }
+
+    #[test]
+    fn cabal_without_a_name_falls_back_to_the_file_stem() {
+        let content = "cabal-version: 3.0\nlibrary\n    name: not-top-level\n";
+        assert_eq!(
+            try_detect_from_cabal("my-project.cabal", content).as_deref(),
+            Some("my-project")
+        );
+        assert_eq!(try_detect_from_cabal(".cabal", content), None);
+    }
+
+    #[test]
+    fn stack_and_hpack_yaml_names_the_project() {
+        let package_yaml = "name:                my-project
+version:             0.1.0.0
+github:              \"githubuser/my-project\"
+
+dependencies:
+- base >= 4.7 && < 5
+- name: ignored
+";
+        let stack_yaml = "resolver: lts-22.7
+packages:
+- .
+";
+        assert_eq!(
+            try_detect_from_stack_yaml(package_yaml).as_deref(),
+            Some("my-project")
+        );
+        assert_eq!(try_detect_from_stack_yaml(stack_yaml), None);
+    }
+
+    #[test]
+    fn cabal_file_is_found_by_extension() {
+        let root = temp_dir("cabal");
+        fs::write(root.join("my-project.cabal"), CABAL).unwrap();
+        fs::write(root.join("stack.yaml"), "resolver: lts-22.7\n").unwrap();
+
+        let detected = detect_project(&root.join("src").join("MyLib.hs")).unwrap();
+
+        assert_eq!(detected.name, "my-project");
+        assert_eq!(detected.folder, root);
+    }
+
+    const ZIG_ZON: &str = r#".{
+    // This is the default name used by packages depending on this one.
+    .name = "my_project",
+    .version = "0.1.0",
+    .minimum_zig_version = "0.12.0",
+    .dependencies = .{
+        .known_folders = .{
+            .url = "https://github.com/ziglibs/known-folders/archive/0ad514d.tar.gz",
+            .hash = "12209cde192558f8b3dc098ac2330fc2a14fdd211c5433afd33085af75caa9183147",
+        },
+    },
+    .paths = .{ "build.zig", "build.zig.zon", "src" },
+}
+"#;
+
+    #[test]
+    fn zig_zon_string_name_names_the_project() {
+        assert_eq!(
+            try_detect_from_zig_zon(ZIG_ZON).as_deref(),
+            Some("my_project")
+        );
+        assert_eq!(
+            try_detect_from_zig_zon(".{ .name=\"zig-clap\", .version = \"0.9.1\" }").as_deref(),
+            Some("zig-clap")
+        );
+    }
+
+    #[test]
+    fn zig_zon_enum_literal_names_the_project() {
+        assert_eq!(
+            try_detect_from_zig_zon(".{\n    .name = .my_project,\n    .fingerprint = 0x1,\n}")
+                .as_deref(),
+            Some("my_project")
+        );
+        assert_eq!(
+            try_detect_from_zig_zon(".{\n    .name = .@\"my-project\",\n}").as_deref(),
+            Some("my-project")
+        );
+    }
+
+    #[test]
+    fn zig_zon_ignores_fields_that_only_start_with_name() {
+        let content = ".{\n    .name_suffix = \"ignored\",\n    .name = \"kept\",\n}";
+        assert_eq!(try_detect_from_zig_zon(content).as_deref(), Some("kept"));
+        assert_eq!(try_detect_from_zig_zon(".{ .version = \"0.1.0\" }"), None);
+        assert_eq!(try_detect_from_zig_zon(".{ .name = \"\" }"), None);
+    }
+
+    #[test]
+    fn zig_zon_is_preferred_over_build_zig() {
+        let root = temp_dir("zig-zon");
+        fs::write(root.join("build.zig.zon"), ZIG_ZON).unwrap();
+        fs::write(root.join("build.zig"), "const std = @import(\"std\");\n").unwrap();
+
+        let detected = detect_project(&root.join("src").join("main.zig")).unwrap();
+
+        assert_eq!(detected.name, "my_project");
+        assert_eq!(detected.folder, root);
+    }
+
+    #[test]
+    fn build_zig_alone_names_the_project_after_its_directory() {
+        let root = temp_dir("zig-build");
+        fs::write(root.join("build.zig"), "const std = @import(\"std\");\n").unwrap();
+
+        let detected = detect_project(&root.join("src").join("main.zig")).unwrap();
+
+        assert_eq!(detected.name, dir_name(&root).unwrap());
+        assert_eq!(detected.folder, root);
+    }
+
+    #[test]
+    fn detected_project_is_reused_within_the_ttl() {
+        let root = temp_dir("detected-ttl");
+        fs::write(root.join("package.json"), r#"{ "name": "before" }"#).unwrap();
+        let entity = root.join("src").join("index.js");
+        let projects = DetectedProjects::default();
+        let now = Instant::now();
+
+        assert_eq!(projects.detect(&entity, now).unwrap().name, "before");
+        fs::write(root.join("package.json"), r#"{ "name": "after" }"#).unwrap();
+
+        let within = now + DETECTED_TTL - Duration::from_secs(1);
+        assert_eq!(projects.detect(&entity, within).unwrap().name, "before");
+        let after = now + DETECTED_TTL;
+        assert_eq!(projects.detect(&entity, after).unwrap().name, "after");
+    }
+
+    #[test]
+    fn saving_a_marker_refreshes_the_projects_below_it() {
+        let root = temp_dir("detected-save");
+        fs::write(root.join("package.json"), r#"{ "name": "before" }"#).unwrap();
+        let entity = root.join("src").join("index.js");
+        let projects = DetectedProjects::default();
+        let now = Instant::now();
+
+        assert_eq!(projects.detect(&entity, now).unwrap().name, "before");
+        fs::write(root.join("package.json"), r#"{ "name": "after" }"#).unwrap();
+
+        projects.invalidate(&root.join("src").join("other.js"));
+        assert_eq!(projects.detect(&entity, now).unwrap().name, "after");
+
+        fs::write(root.join("package.json"), r#"{ "name": "again" }"#).unwrap();
+        projects.invalidate(&root.join("package.json"));
+        assert_eq!(projects.detect(&entity, now).unwrap().name, "again");
+    }
+
+    #[test]
+    fn saving_elsewhere_keeps_detected_projects() {
+        let root = temp_dir("detected-elsewhere");
+        fs::create_dir_all(root.join("app")).unwrap();
+        fs::write(
+            root.join("app").join("package.json"),
+            r#"{ "name": "app" }"#,
+        )
+        .unwrap();
+        let entity = root.join("app").join("index.js");
+        let projects = DetectedProjects::default();
+        let now = Instant::now();
+
+        assert_eq!(projects.detect(&entity, now).unwrap().name, "app");
+        fs::write(
+            root.join("app").join("package.json"),
+            r#"{ "name": "renamed" }"#,
+        )
+        .unwrap();
+
+        projects.invalidate(&root.join("docs").join("README.md"));
+        assert_eq!(projects.detect(&entity, now).unwrap().name, "app");
+    }
+
+    const POM: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!-- <artifactId>commented-out</artifactId> -->
+<project xmlns="http://maven.apache.org/POM/4.0.0"
+         xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance"
+         xsi:schemaLocation="http://maven.apache.org/POM/4.0.0 https://maven.apache.org/xsd/maven-4.0.0.xsd">
+  <modelVersion>4.0.0</modelVersion>
+
+  <parent>
+    <groupId>org.springframework.boot</groupId>
+    <artifactId>spring-boot-starter-parent</artifactId>
+    <version>3.2.1</version>
+    <relativePath/>
+  </parent>
+
+  <dependencies>
+    <dependency>
+      <groupId>org.springframework.boot</groupId>
+      <artifactId>spring-boot-starter-web</artifactId>
+    </dependency>
+  </dependencies>
+
+  <groupId>com.example</groupId>
+  <artifactId>orders-service</artifactId>
+  <version>0.0.1-SNAPSHOT</version>
+
+  <build>
+    <plugins>
+      <plugin>
+        <groupId>org.springframework.boot</groupId>
+        <artifactId>spring-boot-maven-plugin</artifactId>
+      </plugin>
+    </plugins>
+  </build>
+</project>
+"#;
+
+    #[test]
+    fn pom_artifact_id_of_the_project_names_it() {
+        assert_eq!(
+            try_detect_from_pom_xml(POM).as_deref(),
+            Some("orders-service")
+        );
+    }
+
+    #[test]
+    fn pom_without_its_own_artifact_id_has_no_project() {
+        let content = "<project>\n  <parent>\n    <artifactId>parent</artifactId>\n  </parent>\n  <artifactId> </artifactId>\n</project>\n";
+        assert_eq!(try_detect_from_pom_xml(content), None);
+        assert_eq!(try_detect_from_pom_xml("<settings/>"), None);
+    }
+
+    #[test]
+    fn maven_module_has_the_aggregator_as_alternate_project() {
+        let root = temp_dir("maven-modules");
+        fs::write(
+            root.join("pom.xml"),
+            "<project>\n  <groupId>com.example</groupId>\n  <artifactId>shop-parent</artifactId>\n  <packaging>pom</packaging>\n  <modules>\n    <module>orders</module>\n  </modules>\n</project>\n",
+        )
+        .unwrap();
+        let module = root.join("orders");
+        fs::create_dir_all(&module).unwrap();
+        fs::write(module.join("pom.xml"), POM).unwrap();
+
+        let detected = detect_project(
+            &module
+                .join("src")
+                .join("main")
+                .join("java")
+                .join("App.java"),
+        )
+        .unwrap();
+
+        assert_eq!(detected.name, "orders-service");
+        assert_eq!(detected.alternate.as_deref(), Some("shop-parent"));
+        assert_eq!(detected.folder, module);
+
+        let root_detected = detect_project(&root.join("README.md")).unwrap();
+        assert_eq!(root_detected.name, "shop-parent");
+        assert_eq!(root_detected.alternate, None);
+    }
+}
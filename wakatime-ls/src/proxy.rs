@@ -0,0 +1,227 @@
+use std::env;
+
+use crate::settings::Setting;
+
+const DEFAULT_API_HOST: &str = "api.wakatime.com";
+
+/// Variables checked in order when `proxy` isn't set, in both the usual
+/// upper and lower case spellings.
+const PROXY_VARS: &[&str] = &[
+    "HTTPS_PROXY",
+    "https_proxy",
+    "ALL_PROXY",
+    "all_proxy",
+    "HTTP_PROXY",
+    "http_proxy",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProxySource {
+    Setting,
+    Environment(&'static str),
+    /// A proxy variable is set but `NO_PROXY` excludes the API host.
+    NoProxy,
+    None,
+}
+
+/// The proxy handed to wakatime-cli as `--proxy`, and where it came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Proxy {
+    pub url: Option<String>,
+    pub source: ProxySource,
+}
+
+impl Proxy {
+    /// Uses the `proxy` setting, else the standard proxy variables of the
+    /// server's own environment, which the cli may not inherit.
+    pub fn resolve(settings: &Setting) -> Proxy {
+        Self::resolve_with(settings, |name| env::var(name).ok())
+    }
+
+    /// `resolve`, reading the environment through `env`. Empty variables
+    /// count as unset.
+    fn resolve_with(settings: &Setting, env: impl Fn(&str) -> Option<String>) -> Proxy {
+        let non_empty = |name: &str| env(name).filter(|value| !value.is_empty());
+
+        if let Some(url) = settings.proxy.clone().filter(|url| !url.is_empty()) {
+            return Proxy {
+                url: Some(url),
+                source: ProxySource::Setting,
+            };
+        }
+
+        let Some((var, url)) = PROXY_VARS
+            .iter()
+            .find_map(|var| non_empty(var).map(|url| (*var, url)))
+        else {
+            return Proxy {
+                url: None,
+                source: ProxySource::None,
+            };
+        };
+
+        let host = api_host(settings.api_url.as_deref());
+        let no_proxy = non_empty("NO_PROXY")
+            .or_else(|| non_empty("no_proxy"))
+            .unwrap_or_default();
+        if is_excluded(&host, &no_proxy) {
+            return Proxy {
+                url: None,
+                source: ProxySource::NoProxy,
+            };
+        }
+
+        Proxy {
+            url: Some(url),
+            source: ProxySource::Environment(var),
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self.source {
+            ProxySource::Setting => "proxy setting".to_string(),
+            ProxySource::Environment(var) => format!("${var}"),
+            ProxySource::NoProxy => "none, api host matches NO_PROXY".to_string(),
+            ProxySource::None => "none".to_string(),
+        }
+    }
+}
+
+fn api_host(api_url: Option<&str>) -> String {
+    api_url
+        .and_then(|url| url::Url::parse(url).ok())
+        .and_then(|url| url.host_str().map(str::to_lowercase))
+        .unwrap_or_else(|| DEFAULT_API_HOST.to_string())
+}
+
+/// Matches `host` against a `NO_PROXY` list: `*`, exact hosts, and domain
+/// suffixes with or without a leading dot. Ports in entries are ignored.
+fn is_excluded(host: &str, no_proxy: &str) -> bool {
+    no_proxy
+        .split(',')
+        .map(|entry| entry.trim().to_lowercase())
+        .filter(|entry| !entry.is_empty())
+        .any(|entry| {
+            if entry == "*" {
+                return true;
+            }
+            let entry = entry.split(':').next().unwrap_or_default();
+            let domain = entry.trim_start_matches('.');
+            host == domain || host.ends_with(&format!(".{domain}"))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolve(settings: &Setting, vars: &[(&str, &str)]) -> Proxy {
+        Proxy::resolve_with(settings, |name| {
+            vars.iter()
+                .find(|(var, _)| *var == name)
+                .map(|(_, value)| value.to_string())
+        })
+    }
+
+    #[test]
+    fn setting_wins_over_the_environment() {
+        let settings = Setting {
+            proxy: Some("http://setting:3128".to_string()),
+            ..Default::default()
+        };
+        let proxy = resolve(&settings, &[("HTTPS_PROXY", "http://env:3128")]);
+        assert_eq!(proxy.url.as_deref(), Some("http://setting:3128"));
+        assert_eq!(proxy.source, ProxySource::Setting);
+    }
+
+    #[test]
+    fn variables_are_checked_in_order() {
+        let vars = [
+            ("http_proxy", "http://http:3128"),
+            ("ALL_PROXY", "http://all:3128"),
+            ("https_proxy", "http://https:3128"),
+        ];
+        let proxy = resolve(&Setting::default(), &vars);
+        assert_eq!(proxy.url.as_deref(), Some("http://https:3128"));
+        assert_eq!(proxy.source, ProxySource::Environment("https_proxy"));
+        assert_eq!(proxy.describe(), "$https_proxy");
+
+        let proxy = resolve(&Setting::default(), &vars[..2]);
+        assert_eq!(proxy.source, ProxySource::Environment("ALL_PROXY"));
+    }
+
+    #[test]
+    fn empty_values_are_skipped() {
+        let settings = Setting {
+            proxy: Some(String::new()),
+            ..Default::default()
+        };
+        let proxy = resolve(
+            &settings,
+            &[
+                ("HTTPS_PROXY", ""),
+                ("HTTP_PROXY", "http://http:3128"),
+                ("NO_PROXY", ""),
+            ],
+        );
+        assert_eq!(proxy.url.as_deref(), Some("http://http:3128"));
+        assert_eq!(proxy.source, ProxySource::Environment("HTTP_PROXY"));
+
+        let proxy = resolve(&Setting::default(), &[("HTTPS_PROXY", "")]);
+        assert_eq!(proxy.url, None);
+        assert_eq!(proxy.source, ProxySource::None);
+        assert_eq!(proxy.describe(), "none");
+    }
+
+    #[test]
+    fn no_proxy_excludes_the_api_host() {
+        let proxy = resolve(
+            &Setting::default(),
+            &[
+                ("HTTPS_PROXY", "http://env:3128"),
+                ("no_proxy", "localhost,.wakatime.com"),
+            ],
+        );
+        assert_eq!(proxy.url, None);
+        assert_eq!(proxy.source, ProxySource::NoProxy);
+    }
+
+    #[test]
+    fn no_proxy_entries() {
+        let host = "api.wakatime.com";
+        assert!(is_excluded(host, "*"));
+        assert!(is_excluded(host, "api.wakatime.com"));
+        assert!(is_excluded(host, "API.WakaTime.com"));
+        assert!(is_excluded(host, "wakatime.com"));
+        assert!(is_excluded(host, ".wakatime.com"));
+        assert!(is_excluded(host, "localhost, wakatime.com:443"));
+        assert!(!is_excluded(host, ""));
+        assert!(!is_excluded(host, "localhost,example.com"));
+        assert!(!is_excluded(host, "time.com"));
+        assert!(!is_excluded(host, "api.wakatime.com.evil"));
+    }
+
+    #[test]
+    fn custom_api_url_host_is_checked_against_no_proxy() {
+        assert_eq!(api_host(None), DEFAULT_API_HOST);
+        assert_eq!(api_host(Some("not a url")), DEFAULT_API_HOST);
+        assert_eq!(
+            api_host(Some("https://Waka.Corp.example:8443/api/v1")),
+            "waka.corp.example"
+        );
+
+        let settings = Setting {
+            api_url: Some("https://waka.corp.example/api/v1".to_string()),
+            ..Default::default()
+        };
+        let vars = [
+            ("HTTPS_PROXY", "http://env:3128"),
+            ("NO_PROXY", "corp.example"),
+        ];
+        assert_eq!(resolve(&settings, &vars).source, ProxySource::NoProxy);
+        assert_eq!(
+            resolve(&Setting::default(), &vars).source,
+            ProxySource::Environment("HTTPS_PROXY")
+        );
+    }
+}
@@ -0,0 +1,155 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Read, Seek, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{file_lock::lock, heartbeat::Event, settings::wakatime_home};
+
+/// Most heartbeats kept on disk; the oldest are dropped beyond this so a long
+/// stretch without a key or a connection doesn't grow the file unbounded.
+const MAX_QUEUED: usize = 1000;
+
+/// Heartbeats that couldn't be sent before exiting, replayed on next start.
+pub fn queue_path() -> Option<PathBuf> {
+    wakatime_home().map(|home| home.join(".wakatime").join("zed-wakatime-queue.json"))
+}
+
+pub fn persist(heartbeats: &[(Event, f64)]) -> io::Result<()> {
+    let path = queue_path().ok_or_else(|| io::Error::other("no home directory"))?;
+    persist_to(&path, heartbeats)
+}
+
+/// Appends heartbeats to the queue at `path`. Every Zed window's server
+/// shares the file, so it's read and rewritten under an exclusive lock.
+pub fn persist_to(path: &Path, heartbeats: &[(Event, f64)]) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)?;
+    lock(&file)?;
+
+    let mut queued = read(&mut file);
+    queued.extend_from_slice(heartbeats);
+    let excess = queued.len().saturating_sub(MAX_QUEUED);
+    queued.drain(..excess);
+
+    file.set_len(0)?;
+    file.rewind()?;
+    file.write_all(&serde_json::to_vec(&queued)?)
+}
+
+/// Returns the persisted heartbeats and removes them from disk.
+pub fn take_persisted() -> Vec<(Event, f64)> {
+    queue_path()
+        .map(|path| take_from(&path))
+        .unwrap_or_default()
+}
+
+/// Empties the queue at `path`, returning what it held. The file is
+/// truncated rather than deleted, so a server waiting for the lock doesn't
+/// write to a file that's gone.
+pub fn take_from(path: &Path) -> Vec<(Event, f64)> {
+    let Ok(mut file) = OpenOptions::new().read(true).write(true).open(path) else {
+        return Vec::new();
+    };
+    if lock(&file).is_err() {
+        return Vec::new();
+    }
+
+    let queued = read(&mut file);
+    file.set_len(0).ok();
+    queued
+}
+
+fn read(file: &mut fs::File) -> Vec<(Event, f64)> {
+    let mut content = Vec::new();
+    file.read_to_end(&mut content).ok();
+    serde_json::from_slice(&content).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, thread};
+
+    use super::*;
+    use crate::test_support::temp_dir;
+
+    fn heartbeats(uri: &str, count: usize) -> Vec<(Event, f64)> {
+        (0..count)
+            .map(|index| {
+                let event = Event {
+                    uri: uri.to_string(),
+                    ..Default::default()
+                };
+                (event, index as f64)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn persisted_heartbeats_are_taken_once() {
+        let path = temp_dir("queue-take").join("queue.json");
+
+        persist_to(&path, &heartbeats("/a.rs", 2)).unwrap();
+        persist_to(&path, &heartbeats("/b.rs", 1)).unwrap();
+
+        let taken: Vec<_> = take_from(&path)
+            .into_iter()
+            .map(|(event, _)| event.uri)
+            .collect();
+        assert_eq!(taken, ["/a.rs", "/a.rs", "/b.rs"]);
+        assert!(take_from(&path).is_empty());
+        assert!(take_from(&path.with_extension("missing")).is_empty());
+    }
+
+    #[test]
+    fn queue_keeps_the_newest_heartbeats() {
+        let path = temp_dir("queue-cap").join("queue.json");
+
+        persist_to(&path, &heartbeats("/a.rs", MAX_QUEUED - 1)).unwrap();
+        persist_to(&path, &heartbeats("/b.rs", 3)).unwrap();
+
+        let taken = take_from(&path);
+        assert_eq!(taken.len(), MAX_QUEUED);
+        assert_eq!(taken[0].1, 2.0);
+        assert!(taken[MAX_QUEUED - 3..]
+            .iter()
+            .all(|(event, _)| event.uri == "/b.rs"));
+    }
+
+    #[test]
+    fn concurrent_writers_lose_no_heartbeats() {
+        let path = Arc::new(temp_dir("queue-concurrent").join("queue.json"));
+
+        let writers: Vec<_> = (0..8)
+            .map(|writer| {
+                let path = Arc::clone(&path);
+                thread::spawn(move || {
+                    for _ in 0..10 {
+                        persist_to(&path, &heartbeats(&format!("/{writer}.rs"), 2)).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let taken = take_from(&path);
+        assert_eq!(taken.len(), 8 * 10 * 2);
+        for writer in 0..8 {
+            let uri = format!("/{writer}.rs");
+            assert_eq!(
+                taken.iter().filter(|(event, _)| event.uri == uri).count(),
+                20
+            );
+        }
+    }
+}
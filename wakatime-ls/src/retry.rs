@@ -0,0 +1,76 @@
+use std::{
+    collections::VecDeque,
+    io,
+    process::Output,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use crate::heartbeat::Event;
+
+/// Most heartbeats kept for retrying; older ones are dropped beyond this so a
+/// long offline stretch doesn't grow memory.
+const MAX_PENDING: usize = 100;
+
+/// Delay before the first retry, doubled after each failed one up to
+/// `MAX_DELAY`.
+const BASE_DELAY: Duration = Duration::from_secs(15);
+const MAX_DELAY: Duration = Duration::from_secs(10 * 60);
+
+/// Heartbeats whose wakatime-cli run failed without the cli keeping them in
+/// its own offline queue.
+#[derive(Default)]
+pub struct RetryQueue {
+    pending: Mutex<VecDeque<(Event, f64)>>,
+    scheduled: AtomicBool,
+    failures: AtomicU32,
+}
+
+impl RetryQueue {
+    /// Queues heartbeats for retrying, returning true when no retry is
+    /// scheduled yet and the caller should schedule one.
+    pub fn push(&self, heartbeats: impl IntoIterator<Item = (Event, f64)>) -> bool {
+        let mut pending = self.pending.lock().unwrap();
+        pending.extend(heartbeats);
+        let excess = pending.len().saturating_sub(MAX_PENDING);
+        pending.drain(..excess);
+        drop(pending);
+
+        !self.scheduled.swap(true, Ordering::AcqRel)
+    }
+
+    pub fn take(&self) -> Vec<(Event, f64)> {
+        self.scheduled.store(false, Ordering::Release);
+        self.pending.lock().unwrap().drain(..).collect()
+    }
+
+    /// How long to wait before the next retry.
+    pub fn delay(&self) -> Duration {
+        let failures = self.failures.load(Ordering::Acquire).min(16);
+        BASE_DELAY.saturating_mul(1 << failures).min(MAX_DELAY)
+    }
+
+    pub fn record_result(&self, success: bool) {
+        if success {
+            self.failures.store(0, Ordering::Release);
+        } else {
+            self.failures.fetch_add(1, Ordering::AcqRel);
+        }
+    }
+}
+
+/// Whether a wakatime-cli run lost its heartbeats and retrying could help. The
+/// cli keeps heartbeats it couldn't send over the network in its own offline
+/// queue (exit codes 102 and 112), and a bad config or API key (103, 104)
+/// fails the same way until the user fixes it.
+pub fn is_retryable(result: &io::Result<Output>) -> bool {
+    match result {
+        Ok(output) => {
+            !output.status.success() && !matches!(output.status.code(), Some(102 | 103 | 104 | 112))
+        }
+        Err(_) => true,
+    }
+}
@@ -0,0 +1,154 @@
+use std::{future::Future, io, pin::Pin, process::Output, time::Duration};
+
+use tokio::process::Command;
+
+use crate::process;
+
+/// Runs wakatime-cli for the server. Heartbeats, batches and `--today` all go
+/// through one, so its invocations can be recorded without a real binary.
+pub trait CommandRunner: Send + Sync {
+    fn run<'a>(
+        &'a self,
+        program: &'a str,
+        args: &'a [String],
+        stdin: Option<String>,
+        timeout: Duration,
+    ) -> Pin<Box<dyn Future<Output = io::Result<Output>> + Send + 'a>>;
+}
+
+/// Spawns the process with tokio, killing it on timeout or when dropped.
+pub struct TokioRunner;
+
+impl CommandRunner for TokioRunner {
+    fn run<'a>(
+        &'a self,
+        program: &'a str,
+        args: &'a [String],
+        stdin: Option<String>,
+        timeout: Duration,
+    ) -> Pin<Box<dyn Future<Output = io::Result<Output>> + Send + 'a>> {
+        Box::pin(async move {
+            let mut command = Command::new(program);
+            command.args(args);
+            process::output(&mut command, stdin.as_deref(), timeout).await
+        })
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::{
+        process::ExitStatus,
+        sync::{Arc, Mutex},
+    };
+
+    use super::*;
+
+    /// One recorded wakatime-cli run.
+    #[derive(Debug, Clone)]
+    pub struct Invocation {
+        pub program: String,
+        pub args: Vec<String>,
+        pub stdin: Option<String>,
+    }
+
+    type Respond = dyn Fn(&Invocation) -> io::Result<Output> + Send + Sync;
+
+    /// Records every invocation instead of running it, answering with
+    /// whatever the test's `respond` returns. Clones share the record.
+    #[derive(Clone)]
+    pub struct RecordingRunner {
+        invocations: Arc<Mutex<Vec<Invocation>>>,
+        respond: Arc<Respond>,
+    }
+
+    impl Default for RecordingRunner {
+        /// Every run exits successfully without output.
+        fn default() -> Self {
+            Self::responding(|_| Ok(output(0, "")))
+        }
+    }
+
+    impl RecordingRunner {
+        pub fn responding(
+            respond: impl Fn(&Invocation) -> io::Result<Output> + Send + Sync + 'static,
+        ) -> Self {
+            RecordingRunner {
+                invocations: Arc::default(),
+                respond: Arc::new(respond),
+            }
+        }
+
+        pub fn invocations(&self) -> Vec<Invocation> {
+            self.invocations.lock().unwrap().clone()
+        }
+    }
+
+    impl CommandRunner for RecordingRunner {
+        fn run<'a>(
+            &'a self,
+            program: &'a str,
+            args: &'a [String],
+            stdin: Option<String>,
+            _timeout: Duration,
+        ) -> Pin<Box<dyn Future<Output = io::Result<Output>> + Send + 'a>> {
+            let invocation = Invocation {
+                program: program.to_string(),
+                args: args.to_vec(),
+                stdin,
+            };
+            let result = (self.respond)(&invocation);
+            self.invocations.lock().unwrap().push(invocation);
+            Box::pin(async move { result })
+        }
+    }
+
+    /// A finished process with `code` and `stdout`.
+    pub fn output(code: i32, stdout: &str) -> Output {
+        Output {
+            status: exit_status(code),
+            stdout: stdout.as_bytes().to_vec(),
+            stderr: Vec::new(),
+        }
+    }
+
+    #[cfg(unix)]
+    fn exit_status(code: i32) -> ExitStatus {
+        use std::os::unix::process::ExitStatusExt;
+
+        ExitStatus::from_raw(code << 8)
+    }
+
+    #[cfg(windows)]
+    fn exit_status(code: i32) -> ExitStatus {
+        use std::os::windows::process::ExitStatusExt;
+
+        ExitStatus::from_raw(code as u32)
+    }
+
+    #[tokio::test]
+    async fn records_invocations_and_answers_with_respond() {
+        let runner = RecordingRunner::responding(|invocation| {
+            Ok(output(invocation.args.len() as i32, "today"))
+        });
+        let args = vec!["--today".to_string(), "--key".to_string()];
+
+        let result = runner
+            .run(
+                "wakatime-cli",
+                &args,
+                Some("[]".to_string()),
+                Duration::ZERO,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(result.status.code(), Some(2));
+        assert_eq!(result.stdout, b"today");
+        let invocations = runner.invocations();
+        assert_eq!(invocations.len(), 1);
+        assert_eq!(invocations[0].program, "wakatime-cli");
+        assert_eq!(invocations[0].args, args);
+        assert_eq!(invocations[0].stdin.as_deref(), Some("[]"));
+    }
+}
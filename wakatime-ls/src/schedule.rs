@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+
+use chrono::{Datelike, NaiveDateTime, NaiveTime, Weekday};
+use serde::{Deserialize, Serialize};
+
+/// Working hours per weekday in local time, e.g.
+/// `{ "mon": ["09:00-12:00", "13:00-17:30"], "sat": [] }`. Days that aren't
+/// listed aren't tracked at all. A range ending before it starts runs past
+/// midnight into the next day.
+#[derive(Deserialize, Serialize, Default, Debug, Clone)]
+pub struct Schedule(HashMap<String, Vec<String>>);
+
+impl Schedule {
+    /// Whether a heartbeat at the given local wall-clock time is inside the
+    /// schedule. Comparing wall-clock times keeps DST transitions from
+    /// shifting the windows.
+    pub fn allows(&self, at: NaiveDateTime) -> bool {
+        if self.0.is_empty() {
+            return true;
+        }
+
+        let time = at.time();
+        let today = at.weekday();
+
+        self.ranges(today).any(|(start, end)| {
+            if start < end {
+                start <= time && time < end
+            } else {
+                start <= time
+            }
+        }) || self
+            .ranges(today.pred())
+            .any(|(start, end)| start >= end && time < end)
+    }
+
+    /// Day names and ranges that can't be parsed, as `day` or `day: range`.
+    /// They're ignored, so a misspelled day isn't tracked at all.
+    pub fn invalid_entries(&self) -> Vec<String> {
+        let mut invalid = Vec::new();
+        for (day, ranges) in &self.0 {
+            if parse_weekday(day).is_none() {
+                invalid.push(day.clone());
+                continue;
+            }
+            invalid.extend(
+                ranges
+                    .iter()
+                    .filter(|range| parse_range(range).is_none())
+                    .map(|range| format!("{day}: {range}")),
+            );
+        }
+
+        invalid.sort();
+        invalid
+    }
+
+    fn ranges(&self, weekday: Weekday) -> impl Iterator<Item = (NaiveTime, NaiveTime)> + '_ {
+        self.0
+            .iter()
+            .filter(move |(day, _)| parse_weekday(day) == Some(weekday))
+            .flat_map(|(_, ranges)| ranges.iter().filter_map(|range| parse_range(range)))
+    }
+}
+
+fn parse_weekday(day: &str) -> Option<Weekday> {
+    day.trim().parse().ok()
+}
+
+fn parse_range(range: &str) -> Option<(NaiveTime, NaiveTime)> {
+    let (start, end) = range.split_once('-')?;
+    let parse = |time: &str| NaiveTime::parse_from_str(time.trim(), "%H:%M").ok();
+    let end = match end.trim() {
+        "24:00" => NaiveTime::MIN,
+        end => parse(end)?,
+    };
+
+    Some((parse(start)?, end))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    fn schedule(json: serde_json::Value) -> Schedule {
+        serde_json::from_value(json).unwrap()
+    }
+
+    /// 2024-01-01 was a Monday.
+    fn at(day: u32, time: &str) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 1, day)
+            .unwrap()
+            .and_time(NaiveTime::parse_from_str(time, "%H:%M").unwrap())
+    }
+
+    #[test]
+    fn empty_schedule_allows_everything() {
+        assert!(Schedule::default().allows(at(1, "03:00")));
+    }
+
+    #[test]
+    fn ranges_include_the_start_but_not_the_end() {
+        let schedule = schedule(serde_json::json!({ "mon": ["09:00-12:00", "13:00-17:30"] }));
+        assert!(!schedule.allows(at(1, "08:59")));
+        assert!(schedule.allows(at(1, "09:00")));
+        assert!(!schedule.allows(at(1, "12:00")));
+        assert!(schedule.allows(at(1, "17:29")));
+        assert!(!schedule.allows(at(1, "17:30")));
+    }
+
+    #[test]
+    fn unlisted_and_empty_days_are_not_tracked() {
+        let schedule = schedule(serde_json::json!({ "mon": ["09:00-17:00"], "sat": [] }));
+        assert!(!schedule.allows(at(2, "10:00")));
+        assert!(!schedule.allows(at(6, "10:00")));
+    }
+
+    #[test]
+    fn overnight_range_spills_into_the_next_day() {
+        let schedule = schedule(serde_json::json!({ "mon": ["22:00-02:00"] }));
+        assert!(!schedule.allows(at(1, "21:59")));
+        assert!(schedule.allows(at(1, "23:30")));
+        assert!(schedule.allows(at(2, "01:59")));
+        assert!(!schedule.allows(at(2, "02:00")));
+        // Only from the day the range is listed for.
+        assert!(!schedule.allows(at(1, "01:00")));
+    }
+
+    #[test]
+    fn sunday_night_spills_into_monday() {
+        let schedule = schedule(serde_json::json!({ "sun": ["23:00-01:00"] }));
+        assert!(schedule.allows(at(7, "23:00")));
+        assert!(schedule.allows(at(8, "00:30")));
+        assert!(!schedule.allows(at(8, "01:00")));
+    }
+
+    #[test]
+    fn range_to_24_00_runs_until_midnight() {
+        let schedule = schedule(serde_json::json!({ "fri": ["18:00-24:00"] }));
+        assert!(schedule.allows(at(5, "18:00")));
+        assert!(schedule.allows(at(5, "23:59")));
+        assert!(!schedule.allows(at(6, "00:00")));
+    }
+
+    #[test]
+    fn wall_clock_time_decides_across_dst_changes() {
+        // Clocks went forward on 2024-03-31 in Europe and back on 2024-10-27;
+        // the window stays at the same local times on both days.
+        let schedule = schedule(serde_json::json!({ "sun": ["09:00-17:00"] }));
+        for date in [
+            NaiveDate::from_ymd_opt(2024, 3, 31),
+            NaiveDate::from_ymd_opt(2024, 10, 27),
+        ] {
+            let date = date.unwrap();
+            assert!(schedule.allows(date.and_hms_opt(9, 0, 0).unwrap()));
+            assert!(!schedule.allows(date.and_hms_opt(17, 0, 0).unwrap()));
+        }
+    }
+
+    #[test]
+    fn day_names_ignore_case_and_may_be_spelled_out() {
+        let schedule = schedule(serde_json::json!({ "Monday": ["09:00-17:00"] }));
+        assert!(schedule.allows(at(1, "10:00")));
+        assert!(schedule.invalid_entries().is_empty());
+    }
+
+    #[test]
+    fn invalid_days_and_ranges_are_reported() {
+        let schedule = schedule(serde_json::json!({
+            "tues": ["09:00-17:00"],
+            "wed": ["9-17", "10:00-12:00", "12:00"],
+        }));
+        assert_eq!(
+            schedule.invalid_entries(),
+            ["tues", "wed: 12:00", "wed: 9-17"]
+        );
+        // The valid range still counts.
+        assert!(schedule.allows(at(3, "11:00")));
+        assert!(!schedule.allows(at(2, "11:00")));
+    }
+}
@@ -0,0 +1,599 @@
+use std::{
+    collections::HashMap,
+    env, fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use chrono::TimeDelta;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::schedule::Schedule;
+
+/// Settings from Zed's `lsp.wakatime.settings`. Every field has a default and
+/// unknown keys are ignored, so partial or newer settings objects still load.
+#[derive(Deserialize, Serialize, Default, Debug, Clone)]
+#[serde(default)]
+pub struct Setting {
+    pub api_key: Option<String>,
+    pub api_url: Option<String>,
+    pub status_format: Option<String>,
+    pub idle_timeout_seconds: Option<u64>,
+    /// Default throttle for both heartbeat kinds.
+    pub heartbeat_interval_seconds: Option<u64>,
+    /// Throttle for non-write heartbeats to the same file.
+    pub interval_seconds: Option<u64>,
+    /// Throttle for write heartbeats to the same file.
+    pub write_interval_seconds: Option<u64>,
+    pub restore_grace_seconds: Option<u64>,
+    /// Branch sent as `--branch`; unset leaves detection to wakatime-cli.
+    #[serde(alias = "branch_override")]
+    pub branch: Option<String>,
+    pub project_prefix: Option<String>,
+    pub first_run_check: Option<bool>,
+    pub schedule: Schedule,
+    /// Canonicalize entity paths so symlinked checkouts count as one project.
+    pub resolve_symlinks: bool,
+    pub project_roots: Vec<String>,
+    /// Globs of files not to track, e.g. `node_modules/**`, or regexes
+    /// prefixed with `regex:`.
+    pub exclude: Vec<String>,
+    /// Patterns of excluded files to track anyway, in the same forms.
+    pub include: Vec<String>,
+    pub spawn_strategy: SpawnStrategy,
+    /// How long batched heartbeats wait for others to share a process with.
+    pub batch_window_seconds: Option<u64>,
+    /// Refresh today's coding time in the background this often.
+    pub today_refresh_seconds: Option<u64>,
+    /// Consecutive wakatime-cli failures before heartbeats are held back.
+    pub circuit_breaker_threshold: Option<u32>,
+    pub circuit_breaker_timeout_seconds: Option<u64>,
+    /// How long wakatime-cli may run before it's asked to exit.
+    pub process_timeout_seconds: Option<u64>,
+    /// wakatime-cli processes sending heartbeats at once.
+    pub max_concurrent_cli: Option<usize>,
+    /// Where copies of documents that aren't on disk are written.
+    pub temp_dir: Option<String>,
+    /// Proxy passed to wakatime-cli; unset falls back to `HTTPS_PROXY` and co.
+    pub proxy: Option<String>,
+    /// Certificates for a self-hosted API, passed as `--ssl-certs-file`.
+    #[serde(alias = "ssl_certs_file")]
+    pub ca_bundle_path: Option<String>,
+    pub no_ssl_verify: bool,
+    pub position_granularity: PositionGranularity,
+    /// Send heartbeats for files under `.git/` and git's message buffers.
+    pub include_git_internals: bool,
+    /// Log the wakatime-cli command for each heartbeat without running it.
+    pub dry_run: bool,
+    /// Language id -> WakaTime language name, on top of the built-in table.
+    pub language_mappings: HashMap<String, String>,
+    pub missing_key_behavior: MissingKeyBehavior,
+    #[serde(alias = "hide_filenames")]
+    pub hide_file_names: Hide,
+    pub hide_project_names: Hide,
+    pub hide_branch_names: Hide,
+    /// Machine name sent as `--hostname` instead of the system's.
+    pub hostname: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SpawnStrategy {
+    /// One wakatime-cli process per heartbeat.
+    #[default]
+    PerHeartbeat,
+    /// Heartbeats within a short window share one process via `--extra-heartbeats`.
+    Batched,
+}
+
+/// A `hide_*` privacy setting: `true` obfuscates every name, a list of regexes
+/// only those of entities matching one.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum Hide {
+    All(bool),
+    Matching(Vec<String>),
+}
+
+impl Default for Hide {
+    fn default() -> Self {
+        Hide::All(false)
+    }
+}
+
+impl Hide {
+    /// The value for the wakatime-cli flag, which takes `true` or regexes one
+    /// per line; `None` when nothing is hidden.
+    pub fn cli_value(&self) -> Option<String> {
+        match self {
+            Hide::All(true) => Some("true".to_string()),
+            Hide::All(false) => None,
+            Hide::Matching(patterns) => {
+                let patterns: Vec<&str> = patterns
+                    .iter()
+                    .map(|pattern| pattern.trim())
+                    .filter(|pattern| !pattern.is_empty())
+                    .collect();
+                (!patterns.is_empty()).then(|| patterns.join("\n"))
+            }
+        }
+    }
+
+    /// Whether names of `entity` are hidden: all of them for `true`, else
+    /// those of entities a pattern matches. Like wakatime-cli, invalid
+    /// patterns match nothing.
+    pub fn hides(&self, entity: &str) -> bool {
+        match self {
+            Hide::All(all) => *all,
+            Hide::Matching(patterns) => patterns
+                .iter()
+                .map(|pattern| pattern.trim())
+                .filter(|pattern| !pattern.is_empty())
+                .any(|pattern| Regex::new(pattern).is_ok_and(|regex| regex.is_match(entity))),
+        }
+    }
+}
+
+/// What happens to heartbeats while no API key is configured anywhere.
+#[derive(Deserialize, Serialize, Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum MissingKeyBehavior {
+    /// Ask for a key once and keep sending, leaving it to wakatime-cli.
+    #[default]
+    Prompt,
+    /// Hold heartbeats in the offline queue until a key is configured.
+    Queue,
+    /// Drop heartbeats.
+    Disable,
+}
+
+/// Which heartbeats carry `--lineno`/`--cursorpos`.
+#[derive(Deserialize, Serialize, Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum PositionGranularity {
+    /// Every heartbeat with a known position.
+    #[default]
+    Always,
+    WritesOnly,
+    /// Every Nth heartbeat, e.g. `{ "every-nth": 5 }`.
+    EveryNth(u64),
+}
+
+impl PositionGranularity {
+    pub fn includes(self, is_write: bool, sequence: u64) -> bool {
+        match self {
+            PositionGranularity::Always => true,
+            PositionGranularity::WritesOnly => is_write,
+            PositionGranularity::EveryNth(n) => n <= 1 || sequence % n == 0,
+        }
+    }
+}
+
+impl Setting {
+    /// Deserializes settings, dropping only the keys whose values don't fit
+    /// instead of rejecting the whole object. Returns the dropped keys.
+    pub fn from_value_lenient(value: serde_json::Value) -> (Setting, Vec<String>) {
+        if let Ok(setting) = serde_json::from_value(value.clone()) {
+            return (setting, Vec::new());
+        }
+
+        let serde_json::Value::Object(object) = value else {
+            return (Setting::default(), Vec::new());
+        };
+
+        let mut valid = serde_json::Map::new();
+        let mut invalid = Vec::new();
+        for (key, value) in object {
+            let single = serde_json::json!({ key.clone(): value.clone() });
+            if serde_json::from_value::<Setting>(single).is_ok() {
+                valid.insert(key, value);
+            } else {
+                invalid.push(key);
+            }
+        }
+
+        let setting = serde_json::from_value(serde_json::Value::Object(valid)).unwrap_or_default();
+        (setting, invalid)
+    }
+
+    /// Resolves settings the same way for the language server and the CLI:
+    /// explicit values win, then `WAKATIME_API_KEY`, then `~/.wakatime.cfg`.
+    pub fn resolve(overrides: Setting) -> Setting {
+        Self::resolve_with(
+            overrides,
+            |name| env::var(name).ok(),
+            || read_wakatime_cfg().unwrap_or_default(),
+        )
+    }
+
+    /// `resolve`, reading the environment through `env` and the parsed
+    /// `~/.wakatime.cfg` through `cfg`. Empty values are skipped per source,
+    /// so `"api_key": ""` falls back to the next one.
+    fn resolve_with(
+        mut overrides: Setting,
+        env: impl Fn(&str) -> Option<String>,
+        cfg: impl FnOnce() -> Setting,
+    ) -> Setting {
+        let cfg = cfg();
+        let non_empty = |value: Option<String>| value.filter(|s| !s.is_empty());
+
+        overrides.api_key = non_empty(overrides.api_key)
+            .or_else(|| non_empty(env("WAKATIME_API_KEY")))
+            .or_else(|| non_empty(cfg.api_key));
+        overrides.api_url = non_empty(overrides.api_url).or_else(|| non_empty(cfg.api_url));
+
+        overrides
+    }
+
+    /// Minimum time between non-write heartbeats for the same file.
+    pub fn interval(&self) -> TimeDelta {
+        self.interval_seconds
+            .or(self.heartbeat_interval_seconds)
+            .map_or(TimeDelta::minutes(2), seconds)
+    }
+
+    /// Minimum time between write heartbeats for the same file. Writes are
+    /// always sent unless configured otherwise.
+    pub fn write_interval(&self) -> TimeDelta {
+        self.write_interval_seconds
+            .or(self.heartbeat_interval_seconds)
+            .map_or(TimeDelta::zero(), seconds)
+    }
+
+    /// How long without edits counts as idle; `0` disables idle detection.
+    pub fn idle_timeout(&self) -> Duration {
+        Duration::from_secs(self.idle_timeout_seconds.unwrap_or(300))
+    }
+
+    /// How long after startup `did_open` doesn't send heartbeats, so restoring
+    /// a session with many tabs isn't counted as activity.
+    pub fn restore_grace(&self) -> Duration {
+        Duration::from_secs(self.restore_grace_seconds.unwrap_or(10))
+    }
+
+    /// `ca_bundle_path`, else `$CA_BUNDLE` or `$REQUESTS_CA_BUNDLE`.
+    pub fn ca_bundle(&self) -> Option<PathBuf> {
+        self.ca_bundle_path
+            .clone()
+            .filter(|path| !path.is_empty())
+            .or_else(|| env::var("CA_BUNDLE").ok().filter(|path| !path.is_empty()))
+            .or_else(|| {
+                env::var("REQUESTS_CA_BUNDLE")
+                    .ok()
+                    .filter(|path| !path.is_empty())
+            })
+            .map(PathBuf::from)
+    }
+
+    pub fn temp_dir(&self) -> PathBuf {
+        self.temp_dir
+            .as_deref()
+            .filter(|dir| !dir.is_empty())
+            .map_or_else(env::temp_dir, PathBuf::from)
+    }
+
+    pub fn process_timeout(&self) -> Duration {
+        Duration::from_secs(self.process_timeout_seconds.unwrap_or(60))
+    }
+
+    pub fn max_concurrent_cli(&self) -> usize {
+        self.max_concurrent_cli.unwrap_or(4).max(1)
+    }
+
+    pub fn circuit_breaker_threshold(&self) -> u32 {
+        self.circuit_breaker_threshold.unwrap_or(5)
+    }
+
+    /// How often today's coding time is refreshed for the status, if at all.
+    pub fn today_refresh(&self) -> Option<Duration> {
+        self.today_refresh_seconds
+            .filter(|seconds| *seconds > 0)
+            .map(|seconds| Duration::from_secs(seconds.max(60)))
+    }
+
+    /// With the batched spawn strategy, heartbeats within this window share
+    /// one wakatime-cli process.
+    pub fn batch_window(&self) -> Duration {
+        Duration::from_secs(self.batch_window_seconds.unwrap_or(5))
+    }
+
+    /// How long heartbeats are queued instead of sent once the circuit opens.
+    pub fn circuit_breaker_timeout(&self) -> Duration {
+        Duration::from_secs(self.circuit_breaker_timeout_seconds.unwrap_or(300))
+    }
+}
+
+/// Converts a configured number of seconds, capped at a year so date
+/// arithmetic can't overflow.
+fn seconds(seconds: u64) -> TimeDelta {
+    TimeDelta::seconds(seconds.min(365 * 24 * 60 * 60) as i64)
+}
+
+/// The directory holding `.wakatime.cfg`, honoring `$WAKATIME_HOME`.
+pub fn wakatime_home() -> Option<PathBuf> {
+    if let Some(home) = env::var_os("WAKATIME_HOME").filter(|s| !s.is_empty()) {
+        return Some(PathBuf::from(home));
+    }
+
+    env::var_os("HOME")
+        .or_else(|| env::var_os("USERPROFILE"))
+        .map(PathBuf::from)
+}
+
+pub fn wakatime_cfg_path() -> Option<PathBuf> {
+    wakatime_home().map(|home| home.join(".wakatime.cfg"))
+}
+
+/// Reads `api_key` and `api_url` from the `[settings]` section of `~/.wakatime.cfg`.
+pub fn read_wakatime_cfg() -> Option<Setting> {
+    read_cfg_file(&wakatime_cfg_path()?)
+}
+
+/// `None` only when the file can't be read; invalid UTF-8, as from a file
+/// saved in another encoding, is replaced rather than failing startup.
+fn read_cfg_file(path: &Path) -> Option<Setting> {
+    let content = fs::read(path).ok()?;
+    Some(parse_wakatime_cfg(&String::from_utf8_lossy(&content)))
+}
+
+fn parse_wakatime_cfg(content: &str) -> Setting {
+    let mut setting = Setting::default();
+    let mut in_settings = false;
+
+    for line in content.trim_start_matches('\u{feff}').lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+
+        if line.starts_with('[') && line.ends_with(']') {
+            in_settings = line[1..line.len() - 1].trim() == "settings";
+            continue;
+        }
+
+        if !in_settings {
+            continue;
+        }
+
+        // The first separator counts, so `api_url: https://host/?a=b` keeps
+        // its value whole.
+        let Some(separator) = line.find(['=', ':']) else {
+            continue;
+        };
+        let (key, value) = (&line[..separator], &line[separator + 1..]);
+
+        let value = value.trim();
+        if value.is_empty() {
+            continue;
+        }
+
+        match key.trim() {
+            "api_key" => setting.api_key = Some(value.to_string()),
+            "api_url" => setting.api_url = Some(value.to_string()),
+            _ => {}
+        }
+    }
+
+    setting
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn intervals_default_to_two_minutes_and_every_write() {
+        let setting = Setting::default();
+        assert_eq!(setting.interval(), TimeDelta::minutes(2));
+        assert_eq!(setting.write_interval(), TimeDelta::zero());
+    }
+
+    #[test]
+    fn heartbeat_interval_is_the_default_for_both_kinds() {
+        let setting = Setting {
+            heartbeat_interval_seconds: Some(90),
+            ..Default::default()
+        };
+        assert_eq!(setting.interval(), TimeDelta::seconds(90));
+        assert_eq!(setting.write_interval(), TimeDelta::seconds(90));
+    }
+
+    #[test]
+    fn specific_intervals_override_the_default() {
+        let setting = Setting {
+            heartbeat_interval_seconds: Some(90),
+            interval_seconds: Some(120),
+            write_interval_seconds: Some(30),
+            ..Default::default()
+        };
+        assert_eq!(setting.interval(), TimeDelta::seconds(120));
+        assert_eq!(setting.write_interval(), TimeDelta::seconds(30));
+
+        let setting = Setting {
+            heartbeat_interval_seconds: Some(90),
+            write_interval_seconds: Some(30),
+            ..Default::default()
+        };
+        assert_eq!(setting.interval(), TimeDelta::seconds(90));
+        assert_eq!(setting.write_interval(), TimeDelta::seconds(30));
+    }
+
+    #[test]
+    fn unknown_keys_keep_the_known_ones() {
+        let setting: Setting = serde_json::from_value(serde_json::json!({
+            "api_key": "waka_0123",
+            "future_feature": { "enabled": true },
+            "other_plugin": ["a", "b"],
+            "interval_seconds": 60,
+        }))
+        .unwrap();
+
+        assert_eq!(setting.api_key.as_deref(), Some("waka_0123"));
+        assert_eq!(setting.interval_seconds, Some(60));
+        assert!(setting.exclude.is_empty());
+    }
+
+    #[test]
+    fn proxy_and_tls_settings_are_parsed() {
+        let setting: Setting = serde_json::from_value(serde_json::json!({
+            "proxy": "http://proxy.corp:3128",
+            "ssl_certs_file": "/etc/ssl/corp.pem",
+            "no_ssl_verify": true,
+        }))
+        .unwrap();
+
+        assert_eq!(setting.proxy.as_deref(), Some("http://proxy.corp:3128"));
+        assert_eq!(setting.ca_bundle_path.as_deref(), Some("/etc/ssl/corp.pem"));
+        assert!(setting.no_ssl_verify);
+    }
+
+    #[test]
+    fn partial_settings_take_defaults() {
+        let setting: Setting = serde_json::from_value(serde_json::json!({
+            "resolve_symlinks": true,
+        }))
+        .unwrap();
+
+        assert!(setting.resolve_symlinks);
+        assert_eq!(setting.api_key, None);
+        assert_eq!(setting.spawn_strategy, SpawnStrategy::default());
+    }
+
+    fn with_api(api_key: Option<&str>, api_url: Option<&str>) -> Setting {
+        Setting {
+            api_key: api_key.map(str::to_string),
+            api_url: api_url.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    fn no_env(_: &str) -> Option<String> {
+        None
+    }
+
+    fn key_env(name: &str) -> Option<String> {
+        (name == "WAKATIME_API_KEY").then(|| "waka_env".to_string())
+    }
+
+    #[test]
+    fn explicit_settings_win() {
+        let resolved = Setting::resolve_with(
+            with_api(Some("waka_lsp"), Some("https://lsp.example/api")),
+            key_env,
+            || with_api(Some("waka_cfg"), Some("https://cfg.example/api")),
+        );
+        assert_eq!(resolved.api_key.as_deref(), Some("waka_lsp"));
+        assert_eq!(resolved.api_url.as_deref(), Some("https://lsp.example/api"));
+    }
+
+    #[test]
+    fn empty_api_key_falls_back_to_the_environment() {
+        let resolved = Setting::resolve_with(with_api(Some(""), None), key_env, || {
+            with_api(Some("waka_cfg"), None)
+        });
+        assert_eq!(resolved.api_key.as_deref(), Some("waka_env"));
+    }
+
+    #[test]
+    fn empty_api_key_and_environment_fall_back_to_the_cfg() {
+        let empty_env = |name: &str| (name == "WAKATIME_API_KEY").then(String::new);
+        let resolved = Setting::resolve_with(with_api(Some(""), Some("")), empty_env, || {
+            with_api(Some("waka_cfg"), Some("https://cfg.example/api"))
+        });
+        assert_eq!(resolved.api_key.as_deref(), Some("waka_cfg"));
+        assert_eq!(resolved.api_url.as_deref(), Some("https://cfg.example/api"));
+    }
+
+    #[test]
+    fn empty_values_everywhere_resolve_to_none() {
+        let resolved = Setting::resolve_with(with_api(Some(""), Some("")), no_env, || {
+            with_api(Some(""), Some(""))
+        });
+        assert_eq!(resolved.api_key, None);
+        assert_eq!(resolved.api_url, None);
+    }
+
+    #[test]
+    fn cfg_settings_section_provides_the_key_and_url() {
+        let content = "# shared with other editors
+[settings]
+debug = false
+api_key = waka_0123
+api_url=https://wakapi.example/api
+
+[internal]
+api_key = waka_internal
+";
+        let setting = parse_wakatime_cfg(content);
+        assert_eq!(setting.api_key.as_deref(), Some("waka_0123"));
+        assert_eq!(
+            setting.api_url.as_deref(),
+            Some("https://wakapi.example/api")
+        );
+    }
+
+    #[test]
+    fn cfg_value_keeps_later_separators() {
+        let setting = parse_wakatime_cfg(
+            "[settings]\napi_url: https://wakapi.example/api?team=a\napi_key = waka:0123\n",
+        );
+        assert_eq!(
+            setting.api_url.as_deref(),
+            Some("https://wakapi.example/api?team=a")
+        );
+        assert_eq!(setting.api_key.as_deref(), Some("waka:0123"));
+    }
+
+    #[test]
+    fn hide_applies_to_all_or_matching_entities() {
+        let entity = "/home/user/clients/acme/main.rs";
+        assert!(Hide::All(true).hides(entity));
+        assert!(!Hide::All(false).hides(entity));
+        assert!(Hide::Matching(vec!["/clients/".to_string()]).hides(entity));
+        assert!(!Hide::Matching(vec!["/personal/".to_string(), " ".to_string()]).hides(entity));
+        // Invalid patterns are skipped like wakatime-cli does.
+        assert!(Hide::Matching(vec!["(".to_string(), "acme".to_string()]).hides(entity));
+        assert!(!Hide::Matching(vec!["(".to_string()]).hides(entity));
+    }
+
+    #[test]
+    fn cfg_ignores_comments_empty_values_and_other_sections() {
+        let content = "\u{feff}[settings]
+; api_key = waka_commented
+api_key =
+no separator here
+[ settings ]
+api_url = https://wakapi.example/api
+[settings.extra]
+api_key = waka_other
+";
+        let setting = parse_wakatime_cfg(content);
+        assert_eq!(setting.api_key, None);
+        assert_eq!(
+            setting.api_url.as_deref(),
+            Some("https://wakapi.example/api")
+        );
+    }
+
+    #[test]
+    fn cfg_before_any_section_is_ignored() {
+        let setting = parse_wakatime_cfg("api_key = waka_0123\n");
+        assert_eq!(setting.api_key, None);
+    }
+
+    #[test]
+    fn malformed_cfg_file_still_loads() {
+        let dir = crate::test_support::temp_dir("wakatime-cfg");
+        let path = dir.join(".wakatime.cfg");
+        fs::write(
+            &path,
+            b"[settings]\napi_key = waka_0123\nhostname = caf\xe9\n",
+        )
+        .unwrap();
+
+        let setting = read_cfg_file(&path).unwrap();
+        assert_eq!(setting.api_key.as_deref(), Some("waka_0123"));
+        assert!(read_cfg_file(&dir.join("missing.cfg")).is_none());
+    }
+}
@@ -0,0 +1,266 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use tower_lsp::{lsp_types::*, Client};
+
+use crate::settings::{wakatime_cfg_path, wakatime_home};
+
+const DASHBOARD_URL: &str = "https://wakatime.com/api-key";
+
+fn first_run_marker() -> Option<PathBuf> {
+    wakatime_home().map(|home| {
+        home.join(".wakatime")
+            .join("zed-wakatime-first-run-complete")
+    })
+}
+
+pub fn first_run_complete() -> bool {
+    first_run_marker().is_some_and(|marker| marker.exists())
+}
+
+fn mark_first_run_complete() {
+    if let Some(marker) = first_run_marker() {
+        if let Some(dir) = marker.parent() {
+            fs::create_dir_all(dir).ok();
+        }
+        fs::write(marker, b"").ok();
+    }
+}
+
+/// Asks the user to configure an API key. LSP has no input box, so "Enter
+/// Key" opens `~/.wakatime.cfg` with an `api_key` line ready to be filled in.
+pub async fn run_first_run_wizard(client: Client) {
+    let actions = ["Open Dashboard", "Enter Key", "Skip"]
+        .into_iter()
+        .map(|title| MessageActionItem {
+            title: title.to_string(),
+            properties: Default::default(),
+        })
+        .collect();
+
+    let choice = client
+        .show_message_request(
+            MessageType::INFO,
+            "WakaTime: API key not configured. Open dashboard to get your key?",
+            Some(actions),
+        )
+        .await;
+
+    match choice.ok().flatten().map(|item| item.title).as_deref() {
+        Some("Open Dashboard") => {
+            if let Ok(uri) = Url::parse(DASHBOARD_URL) {
+                show_document(&client, uri, true).await;
+            }
+        }
+        Some("Enter Key") => match prepare_cfg_file() {
+            Ok(path) => {
+                if let Ok(uri) = Url::from_file_path(&path) {
+                    show_document(&client, uri, false).await;
+                }
+                client
+                    .show_message(
+                        MessageType::INFO,
+                        format!(
+                            "WakaTime: add your key as `api_key = ...` under [settings] in {}",
+                            path.display()
+                        ),
+                    )
+                    .await;
+            }
+            Err(err) => {
+                client
+                    .show_message(
+                        MessageType::WARNING,
+                        format!("WakaTime: {err}, set api_key in Zed's lsp settings instead"),
+                    )
+                    .await;
+            }
+        },
+        // Dismissing the prompt asks again next time.
+        None => return,
+        _ => {}
+    }
+
+    mark_first_run_complete();
+}
+
+/// Makes sure `~/.wakatime.cfg` exists with a `[settings]` section holding an
+/// `api_key` line.
+fn prepare_cfg_file() -> Result<PathBuf, String> {
+    let path = wakatime_cfg_path().ok_or("could not locate ~/.wakatime.cfg")?;
+    prepare_cfg_file_at(&path)
+        .map_err(|err| format!("could not prepare {}: {err}", path.display()))?;
+    Ok(path)
+}
+
+/// Adds an empty `api_key` line to the file at `path`, creating it if
+/// missing. A file that can't be read as text is left alone: rewriting it
+/// would lose whatever other tools keep there.
+fn prepare_cfg_file_at(path: &Path) -> io::Result<()> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err),
+    };
+
+    match with_api_key_line(&content) {
+        Some(content) => fs::write(path, content),
+        None => Ok(()),
+    }
+}
+
+/// `content` with an empty `api_key` line at the top of its `[settings]`
+/// section, added if missing, or None when the section has an `api_key`.
+fn with_api_key_line(content: &str) -> Option<String> {
+    let newline = if content.contains("\r\n") {
+        "\r\n"
+    } else {
+        "\n"
+    };
+    let mut in_settings = false;
+    let mut settings_end = None;
+    let mut offset = 0;
+
+    for line in content.split_inclusive('\n') {
+        offset += line.len();
+        let line = line.trim().trim_start_matches('\u{feff}');
+        if line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+
+        if let Some(section) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            in_settings = section.trim() == "settings";
+            if in_settings && settings_end.is_none() {
+                settings_end = Some(offset);
+            }
+            continue;
+        }
+
+        let key = line.split(['=', ':']).next().unwrap_or_default();
+        if in_settings && key.len() < line.len() && key.trim() == "api_key" {
+            return None;
+        }
+    }
+
+    let mut content = content.to_string();
+    match settings_end {
+        Some(end) => {
+            let separator = if content[..end].ends_with('\n') {
+                ""
+            } else {
+                newline
+            };
+            content.insert_str(end, &format!("{separator}api_key = {newline}"));
+        }
+        None => {
+            if !content.is_empty() && !content.ends_with('\n') {
+                content.push_str(newline);
+            }
+            content.push_str(&format!("[settings]{newline}api_key = {newline}"));
+        }
+    }
+    Some(content)
+}
+
+async fn show_document(client: &Client, uri: Url, external: bool) {
+    client
+        .show_document(ShowDocumentParams {
+            uri,
+            external: Some(external),
+            take_focus: Some(true),
+            selection: None,
+        })
+        .await
+        .ok();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_dir;
+
+    fn prepared(name: &str, content: Option<&str>) -> String {
+        let path = temp_dir(name).join(".wakatime.cfg");
+        if let Some(content) = content {
+            fs::write(&path, content).unwrap();
+        }
+        prepare_cfg_file_at(&path).unwrap();
+        fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn missing_file_is_created_with_a_key_line() {
+        assert_eq!(prepared("cfg-missing", None), "[settings]\napi_key = \n");
+    }
+
+    #[test]
+    fn key_line_goes_under_the_existing_settings_section() {
+        assert_eq!(
+            prepared(
+                "cfg-section",
+                Some("[settings]\ndebug = true\n[other]\nx = 1\n")
+            ),
+            "[settings]\napi_key = \ndebug = true\n[other]\nx = 1\n"
+        );
+        assert_eq!(
+            prepared("cfg-section-crlf", Some("[settings]\r\ndebug = true\r\n")),
+            "[settings]\r\napi_key = \r\ndebug = true\r\n"
+        );
+    }
+
+    #[test]
+    fn settings_section_is_appended_when_missing() {
+        assert_eq!(
+            prepared("cfg-no-section", Some("[internal]\nx = 1")),
+            "[internal]\nx = 1\n[settings]\napi_key = \n"
+        );
+    }
+
+    #[test]
+    fn file_with_a_key_is_left_alone() {
+        for content in [
+            "[settings]\napi_key = waka_0123\n",
+            "[settings]\napi_key=\n",
+            "[settings]\n  api_key : waka_0123\n",
+        ] {
+            assert_eq!(prepared("cfg-key", Some(content)), content);
+        }
+    }
+
+    #[test]
+    fn only_the_exact_key_in_settings_counts() {
+        assert_eq!(
+            prepared(
+                "cfg-similar-keys",
+                Some("[settings]\napi_key_vault_cmd = pass waka\n# api_key = old\n")
+            ),
+            "[settings]\napi_key = \napi_key_vault_cmd = pass waka\n# api_key = old\n"
+        );
+        assert_eq!(
+            prepared("cfg-other-section", Some("[internal]\napi_key = x\n")),
+            "[internal]\napi_key = x\n[settings]\napi_key = \n"
+        );
+    }
+
+    #[test]
+    fn non_utf8_file_is_left_untouched() {
+        let path = temp_dir("cfg-non-utf8").join(".wakatime.cfg");
+        let content = b"[settings]\nproject = caf\xe9\n";
+        fs::write(&path, content).unwrap();
+
+        assert!(prepare_cfg_file_at(&path).is_err());
+        assert_eq!(fs::read(&path).unwrap(), content);
+    }
+
+    #[test]
+    fn unreadable_file_is_left_untouched() {
+        // A directory can't be read as a file, whoever runs the tests.
+        let path = temp_dir("cfg-unreadable").join(".wakatime.cfg");
+        fs::create_dir(&path).unwrap();
+
+        assert!(prepare_cfg_file_at(&path).is_err());
+        assert!(path.is_dir());
+    }
+}
@@ -0,0 +1,160 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use serde_json::{json, Value};
+
+/// Number of recent wakatime-cli latencies kept for percentiles.
+const LATENCY_SAMPLES: usize = 100;
+
+#[derive(Default)]
+pub struct Stats {
+    heartbeats_sent: AtomicU64,
+    restore_opens_suppressed: AtomicU64,
+    heartbeats_dry_run: AtomicU64,
+    latencies: Mutex<VecDeque<Duration>>,
+}
+
+impl Stats {
+    pub fn record_latency(&self, latency: Duration) {
+        self.heartbeats_sent.fetch_add(1, Ordering::Relaxed);
+
+        let mut latencies = self.latencies.lock().unwrap();
+        if latencies.len() == LATENCY_SAMPLES {
+            latencies.pop_front();
+        }
+        latencies.push_back(latency);
+    }
+
+    pub fn record_restore_open_suppressed(&self) {
+        self.restore_opens_suppressed
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_dry_run(&self, heartbeats: u64) {
+        self.heartbeats_dry_run
+            .fetch_add(heartbeats, Ordering::Relaxed);
+    }
+
+    pub fn reset(&self) {
+        self.heartbeats_sent.store(0, Ordering::Relaxed);
+        self.restore_opens_suppressed.store(0, Ordering::Relaxed);
+        self.heartbeats_dry_run.store(0, Ordering::Relaxed);
+        self.latencies.lock().unwrap().clear();
+    }
+
+    pub fn to_json(&self) -> Value {
+        let latencies = self.latencies.lock().unwrap();
+        let mut sorted: Vec<Duration> = latencies.iter().copied().collect();
+        sorted.sort();
+
+        let percentile =
+            |p: usize| (!sorted.is_empty()).then(|| millis(sorted[(sorted.len() - 1) * p / 100]));
+        let avg = (!sorted.is_empty())
+            .then(|| millis(sorted.iter().sum::<Duration>() / sorted.len() as u32));
+
+        json!({
+            "heartbeats_sent": self.heartbeats_sent.load(Ordering::Relaxed),
+            "restore_opens_suppressed": self.restore_opens_suppressed.load(Ordering::Relaxed),
+            "heartbeats_dry_run": self.heartbeats_dry_run.load(Ordering::Relaxed),
+            "latency_ms": {
+                "last": latencies.back().copied().map(millis),
+                "avg": avg,
+                "p50": percentile(50),
+                "p95": percentile(95),
+                "max": sorted.last().copied().map(millis),
+                "samples": sorted.len(),
+            },
+        })
+    }
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record_millis(stats: &Stats, samples: impl IntoIterator<Item = u64>) {
+        for sample in samples {
+            stats.record_latency(Duration::from_millis(sample));
+        }
+    }
+
+    #[test]
+    fn no_samples_have_no_latencies() {
+        let latency = Stats::default().to_json()["latency_ms"].clone();
+        assert_eq!(
+            latency,
+            json!({
+                "last": null,
+                "avg": null,
+                "p50": null,
+                "p95": null,
+                "max": null,
+                "samples": 0,
+            })
+        );
+    }
+
+    #[test]
+    fn latencies_of_known_samples() {
+        let stats = Stats::default();
+        record_millis(&stats, [30, 100, 10, 80, 50, 20, 90, 60, 40, 70]);
+
+        let json = stats.to_json();
+        assert_eq!(json["heartbeats_sent"], 10);
+        assert_eq!(
+            json["latency_ms"],
+            json!({
+                "last": 70,
+                "avg": 55,
+                "p50": 50,
+                "p95": 90,
+                "max": 100,
+                "samples": 10,
+            })
+        );
+    }
+
+    #[test]
+    fn only_the_latest_samples_are_kept() {
+        let stats = Stats::default();
+        record_millis(&stats, 1..=150);
+
+        let json = stats.to_json();
+        // Every heartbeat counts, only the last 100 latencies do.
+        assert_eq!(json["heartbeats_sent"], 150);
+        assert_eq!(
+            json["latency_ms"],
+            json!({
+                "last": 150,
+                "avg": 100,
+                "p50": 100,
+                "p95": 145,
+                "max": 150,
+                "samples": LATENCY_SAMPLES,
+            })
+        );
+    }
+
+    #[test]
+    fn reset_clears_counts_and_samples() {
+        let stats = Stats::default();
+        record_millis(&stats, [10, 20]);
+        stats.record_dry_run(3);
+        stats.reset();
+
+        let json = stats.to_json();
+        assert_eq!(json["heartbeats_sent"], 0);
+        assert_eq!(json["heartbeats_dry_run"], 0);
+        assert_eq!(json["latency_ms"]["samples"], 0);
+    }
+}
@@ -0,0 +1,509 @@
+use std::{
+    collections::VecDeque,
+    ffi::OsString,
+    io::{self, BufRead, BufReader, Write},
+    path::Path,
+    process::{ChildStdin, Command, ExitStatus, Stdio},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use serde_json::Value;
+
+/// With `--restart-on-crash`, a server failing within this long of starting
+/// is restarted, up to `MAX_RESTARTS` times, `RESTART_DELAY` apart.
+const CRASH_WINDOW: Duration = Duration::from_secs(30);
+const MAX_RESTARTS: u32 = 3;
+const RESTART_DELAY: Duration = Duration::from_secs(2);
+
+/// Runs the server as a child process, relaying stdin and stdout so that a
+/// restarted server can be handed the client's `initialize` and
+/// `initialized` again. Blocks, so call it before any async runtime starts.
+pub fn run() -> ! {
+    let exe = match std::env::current_exe() {
+        Ok(exe) => exe,
+        Err(e) => {
+            eprintln!("wakatime-ls: can't locate own executable to supervise: {e}");
+            std::process::exit(1);
+        }
+    };
+    let args: Vec<_> = std::env::args_os()
+        .skip(1)
+        .filter(|arg| arg != "--restart-on-crash")
+        .collect();
+
+    let relay = Arc::new(Relay::default());
+    thread::spawn({
+        let relay = relay.clone();
+        move || {
+            let mut stdin = io::stdin().lock();
+            while let Ok(Some(message)) = read_message(&mut stdin) {
+                relay.relay_to_server(message);
+            }
+            relay.close();
+        }
+    });
+
+    let code = supervise(|| serve(&exe, &args, &relay), thread::sleep);
+    std::process::exit(code)
+}
+
+/// Starts the server once, attached to `relay`, and waits for it to exit
+/// and for its output to be forwarded.
+fn serve(exe: &Path, args: &[OsString], relay: &Relay<ChildStdin>) -> io::Result<ExitStatus> {
+    let mut child = Command::new(exe)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", exe.display())))?;
+
+    let (Some(stdin), Some(stdout)) = (child.stdin.take(), child.stdout.take()) else {
+        return Err(io::Error::other("server stdio isn't piped"));
+    };
+    relay.attach(stdin);
+
+    let status = thread::scope(|scope| {
+        scope.spawn(|| {
+            let mut stdout = BufReader::new(stdout);
+            let mut client = io::stdout();
+            while let Ok(Some(message)) = read_message(&mut stdout) {
+                if relay.passes_to_client(&message) {
+                    let written = client.write_all(&message.raw).and_then(|()| client.flush());
+                    if written.is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+        child.wait()
+    });
+    relay.detach();
+    status
+}
+
+/// Runs the server through `start` until it exits successfully, runs past
+/// `CRASH_WINDOW`, or has crashed more than `MAX_RESTARTS` times, calling
+/// `wait` with `RESTART_DELAY` before each restart. Returns the exit code.
+fn supervise(
+    mut start: impl FnMut() -> io::Result<ExitStatus>,
+    mut wait: impl FnMut(Duration),
+) -> i32 {
+    let mut crashes = 0;
+    loop {
+        let started = Instant::now();
+        let status = match start() {
+            Ok(status) => status,
+            Err(e) => {
+                eprintln!("wakatime-ls: failed to start {e}");
+                return 1;
+            }
+        };
+
+        if status.success() || started.elapsed() >= CRASH_WINDOW {
+            return status.code().unwrap_or(1);
+        }
+
+        crashes += 1;
+        if crashes > MAX_RESTARTS {
+            eprintln!(
+                "wakatime-ls: server failed within {}s of starting {crashes} times in a row, \
+                 last with {status}; giving up. Run `wakatime-ls doctor` to check the setup.",
+                CRASH_WINDOW.as_secs()
+            );
+            return status.code().unwrap_or(1);
+        }
+
+        eprintln!(
+            "wakatime-ls: server exited with {status} after {:.1}s, restarting ({crashes}/{MAX_RESTARTS})",
+            started.elapsed().as_secs_f64()
+        );
+        wait(RESTART_DELAY);
+    }
+}
+
+/// One JSON-RPC message with its headers, as read from the stream.
+#[derive(Debug, Clone, PartialEq)]
+struct Message {
+    raw: Vec<u8>,
+    body_start: usize,
+}
+
+impl Message {
+    fn json(&self) -> Option<Value> {
+        serde_json::from_slice(&self.raw[self.body_start..]).ok()
+    }
+}
+
+/// Reads the next message, or `None` at the end of the stream.
+fn read_message(reader: &mut impl BufRead) -> io::Result<Option<Message>> {
+    let mut raw = Vec::new();
+    let mut length = None;
+    loop {
+        let start = raw.len();
+        if reader.read_until(b'\n', &mut raw)? == 0 {
+            if raw.is_empty() {
+                return Ok(None);
+            }
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let line = String::from_utf8_lossy(&raw[start..]);
+        let line = line.trim();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+
+    let Some(length) = length else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "message without a Content-Length header",
+        ));
+    };
+    let body_start = raw.len();
+    raw.resize(body_start + length, 0);
+    reader.read_exact(&mut raw[body_start..])?;
+    Ok(Some(Message { raw, body_start }))
+}
+
+/// Passes messages between the client and whichever server is running.
+/// Messages are only ever written whole, so a server dying mid-stream
+/// doesn't break the framing for the next one.
+struct Relay<W> {
+    server: Mutex<ToServer<W>>,
+    initialize: Mutex<Initialize>,
+}
+
+struct ToServer<W> {
+    writer: Option<W>,
+    /// `initialize` and `initialized` as delivered, replayed on restart.
+    handshake: Vec<Message>,
+    /// Messages that arrived while no server was running.
+    pending: VecDeque<Message>,
+    client_closed: bool,
+}
+
+/// The client's `initialize` request, so a restarted server's response to
+/// the replay isn't passed on once the client has had one.
+#[derive(Default)]
+struct Initialize {
+    id: Option<Value>,
+    answered: bool,
+}
+
+impl<W> Default for Relay<W> {
+    fn default() -> Self {
+        Relay {
+            server: Mutex::new(ToServer {
+                writer: None,
+                handshake: Vec::new(),
+                pending: VecDeque::new(),
+                client_closed: false,
+            }),
+            initialize: Mutex::new(Initialize::default()),
+        }
+    }
+}
+
+impl<W: Write> Relay<W> {
+    fn relay_to_server(&self, message: Message) {
+        let mut server = self.server.lock().unwrap();
+        server.pending.push_back(message);
+        self.deliver(&mut server);
+    }
+
+    /// Hands a newly started server the handshake, then whatever arrived
+    /// while none was running.
+    fn attach(&self, mut writer: W) {
+        let mut server = self.server.lock().unwrap();
+        for message in &server.handshake {
+            if write(&mut writer, message).is_err() {
+                return;
+            }
+        }
+        server.writer = Some(writer);
+        self.deliver(&mut server);
+    }
+
+    /// Called once the server has exited. Returns its stdin.
+    fn detach(&self) -> Option<W> {
+        self.server.lock().unwrap().writer.take()
+    }
+
+    /// The client closed stdin, which closes the server's too.
+    fn close(&self) {
+        let mut server = self.server.lock().unwrap();
+        server.client_closed = true;
+        server.writer = None;
+    }
+
+    /// Whether a message from the server goes on to the client.
+    fn passes_to_client(&self, message: &Message) -> bool {
+        let mut initialize = self.initialize.lock().unwrap();
+        let Some(id) = initialize.id.as_ref() else {
+            return true;
+        };
+        let is_response = message
+            .json()
+            .is_some_and(|json| json.get("method").is_none() && json.get("id") == Some(id));
+        if !is_response {
+            return true;
+        }
+        !std::mem::replace(&mut initialize.answered, true)
+    }
+
+    fn deliver(&self, server: &mut ToServer<W>) {
+        if server.client_closed {
+            server.writer = None;
+            return;
+        }
+        while let Some(message) = server.pending.pop_front() {
+            let Some(writer) = server.writer.as_mut() else {
+                server.pending.push_front(message);
+                return;
+            };
+            if write(writer, &message).is_err() {
+                server.writer = None;
+                server.pending.push_front(message);
+                return;
+            }
+
+            let json = message.json().unwrap_or_default();
+            match json.get("method").and_then(Value::as_str) {
+                Some("initialize") => {
+                    self.initialize.lock().unwrap().id = json.get("id").cloned();
+                    server.handshake.push(message);
+                }
+                Some("initialized") => server.handshake.push(message),
+                _ => {}
+            }
+        }
+    }
+}
+
+fn write(writer: &mut impl Write, message: &Message) -> io::Result<()> {
+    writer.write_all(&message.raw)?;
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    /// A server's stdin, which fails once the server is gone.
+    #[derive(Default)]
+    struct Pipe {
+        bytes: Vec<u8>,
+        broken: bool,
+    }
+
+    impl Write for Pipe {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.broken {
+                return Err(io::ErrorKind::BrokenPipe.into());
+            }
+            self.bytes.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn message(json: Value) -> Message {
+        let body = json.to_string();
+        let raw = format!("Content-Length: {}\r\n\r\n{body}", body.len()).into_bytes();
+        Message {
+            body_start: raw.len() - body.len(),
+            raw,
+        }
+    }
+
+    fn request(id: u64, method: &str) -> Message {
+        message(json!({"jsonrpc": "2.0", "id": id, "method": method, "params": {}}))
+    }
+
+    fn notification(method: &str) -> Message {
+        message(json!({"jsonrpc": "2.0", "method": method, "params": {}}))
+    }
+
+    fn response(id: u64) -> Message {
+        message(json!({"jsonrpc": "2.0", "id": id, "result": {}}))
+    }
+
+    /// The methods of the messages a server received, in order.
+    fn received(pipe: Option<Pipe>) -> Vec<String> {
+        let bytes = pipe.unwrap().bytes;
+        let mut reader = bytes.as_slice();
+        let mut methods = Vec::new();
+        while let Some(message) = read_message(&mut reader).unwrap() {
+            let json = message.json().unwrap();
+            methods.push(json["method"].as_str().unwrap_or_default().to_string());
+        }
+        methods
+    }
+
+    /// Exit statuses for `supervise`, one per start, then successes.
+    #[cfg(unix)]
+    fn exits(codes: &[i32]) -> impl FnMut() -> io::Result<ExitStatus> + '_ {
+        use std::os::unix::process::ExitStatusExt;
+
+        let mut codes = codes.iter();
+        move || {
+            let code = codes.next().copied().unwrap_or(0);
+            Ok(ExitStatus::from_raw(code << 8))
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn crashing_server_is_restarted_until_it_runs() {
+        let mut starts = 0;
+        let mut waits = Vec::new();
+        let mut next = exits(&[101, 101]);
+
+        let code = supervise(
+            || {
+                starts += 1;
+                next()
+            },
+            |delay| waits.push(delay),
+        );
+
+        assert_eq!(code, 0);
+        assert_eq!(starts, 3);
+        assert_eq!(waits, [RESTART_DELAY, RESTART_DELAY]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn supervisor_gives_up_after_the_restarts() {
+        let mut starts = 0;
+        let mut waits = 0;
+        let mut next = exits(&[101, 101, 101, 101, 101]);
+
+        let code = supervise(
+            || {
+                starts += 1;
+                next()
+            },
+            |_| waits += 1,
+        );
+
+        assert_eq!(code, 101);
+        assert_eq!(starts, MAX_RESTARTS + 1);
+        assert_eq!(waits, MAX_RESTARTS);
+    }
+
+    #[test]
+    fn supervisor_fails_when_the_server_cannot_start() {
+        let mut waits = 0;
+
+        let code = supervise(
+            || Err(io::Error::from(io::ErrorKind::NotFound)),
+            |_| waits += 1,
+        );
+
+        assert_eq!(code, 1);
+        assert_eq!(waits, 0);
+    }
+
+    #[test]
+    fn messages_are_read_whole() {
+        let first = request(1, "initialize");
+        let second = notification("initialized");
+        let mut bytes = b"content-length: ".to_vec();
+        bytes.extend_from_slice(&first.raw[b"Content-Length: ".len()..]);
+        bytes.extend_from_slice(&second.raw);
+        let mut reader = bytes.as_slice();
+
+        let read = read_message(&mut reader).unwrap().unwrap();
+        assert_eq!(read.json(), first.json());
+        assert_eq!(read_message(&mut reader).unwrap(), Some(second));
+        assert_eq!(read_message(&mut reader).unwrap(), None);
+
+        let mut truncated = &first.raw[..first.raw.len() - 1];
+        assert!(read_message(&mut truncated).is_err());
+        let mut headerless: &[u8] = b"Content-Type: x\r\n\r\n{}";
+        assert!(read_message(&mut headerless).is_err());
+    }
+
+    #[test]
+    fn messages_wait_for_a_server() {
+        let relay = Relay::<Pipe>::default();
+        relay.relay_to_server(request(1, "initialize"));
+        relay.attach(Pipe::default());
+        relay.relay_to_server(notification("initialized"));
+
+        assert_eq!(received(relay.detach()), ["initialize", "initialized"]);
+    }
+
+    #[test]
+    fn restarted_server_is_initialized_again() {
+        let relay = Relay::<Pipe>::default();
+        relay.attach(Pipe::default());
+        relay.relay_to_server(request(1, "initialize"));
+        assert!(relay.passes_to_client(&response(1)));
+        relay.relay_to_server(notification("initialized"));
+        relay.relay_to_server(notification("textDocument/didOpen"));
+        relay.detach();
+
+        relay.relay_to_server(notification("textDocument/didChange"));
+        relay.attach(Pipe::default());
+
+        assert_eq!(
+            received(relay.detach()),
+            ["initialize", "initialized", "textDocument/didChange"]
+        );
+        assert!(!relay.passes_to_client(&response(1)));
+        assert!(relay.passes_to_client(&response(2)));
+        assert!(relay.passes_to_client(&request(1, "workspace/configuration")));
+    }
+
+    #[test]
+    fn unanswered_initialize_is_answered_by_the_restarted_server() {
+        let relay = Relay::<Pipe>::default();
+        relay.attach(Pipe::default());
+        relay.relay_to_server(request(1, "initialize"));
+        relay.detach();
+
+        relay.attach(Pipe::default());
+        assert_eq!(received(relay.detach()), ["initialize"]);
+        assert!(relay.passes_to_client(&response(1)));
+    }
+
+    #[test]
+    fn message_to_a_dead_server_goes_to_the_next() {
+        let relay = Relay::<Pipe>::default();
+        relay.attach(Pipe {
+            broken: true,
+            ..Default::default()
+        });
+        relay.relay_to_server(notification("textDocument/didSave"));
+        assert!(relay.detach().is_none());
+
+        relay.attach(Pipe::default());
+        assert_eq!(received(relay.detach()), ["textDocument/didSave"]);
+    }
+
+    #[test]
+    fn closing_stdin_closes_the_server() {
+        let relay = Relay::<Pipe>::default();
+        relay.attach(Pipe::default());
+        relay.close();
+        relay.relay_to_server(notification("exit"));
+        assert!(relay.detach().is_none());
+
+        relay.attach(Pipe::default());
+        assert!(relay.detach().is_none());
+    }
+}
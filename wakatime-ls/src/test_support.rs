@@ -0,0 +1,10 @@
+use std::{fs, path::PathBuf};
+
+/// A fresh, empty directory for one test, named after it so tests running in
+/// parallel never share files.
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("wakatime-ls-{}-{name}", std::process::id()));
+    fs::remove_dir_all(&dir).ok();
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
@@ -0,0 +1,195 @@
+use serde_json::Value;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TodayTime {
+    pub total_minutes: u64,
+}
+
+impl TodayTime {
+    pub fn hours(&self) -> u64 {
+        self.total_minutes / 60
+    }
+
+    pub fn minutes(&self) -> u64 {
+        self.total_minutes % 60
+    }
+}
+
+/// Output of `wakatime-cli --today --output json`, with the total parsed out
+/// of either the JSON payload or the plain "3 hrs 12 mins" text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Today {
+    pub text: String,
+    pub time: Option<TodayTime>,
+}
+
+pub fn parse_today(output: &str) -> Today {
+    let output = output.trim();
+
+    if let Ok(value) = serde_json::from_str::<Value>(output) {
+        let grand_total = value
+            .pointer("/data/grand_total")
+            .or_else(|| value.get("grand_total"));
+        let text = value
+            .get("text")
+            .or_else(|| grand_total.and_then(|total| total.get("text")))
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string();
+        let time = grand_total
+            .and_then(|total| total.get("total_seconds"))
+            .and_then(Value::as_f64)
+            .map(|seconds| TodayTime {
+                total_minutes: (seconds / 60.0) as u64,
+            })
+            .or_else(|| parse_today_text(&text));
+
+        return Today { text, time };
+    }
+
+    Today {
+        text: output.to_string(),
+        time: parse_today_text(output),
+    }
+}
+
+/// Parses the cli's human readable total, e.g. "3 hrs 12 mins" or "1 hr 1 min".
+fn parse_today_text(text: &str) -> Option<TodayTime> {
+    let mut total_minutes = 0;
+    let mut found = false;
+    let mut words = text.split_whitespace();
+
+    while let Some(word) = words.next() {
+        let Ok(n) = word.parse::<u64>() else {
+            continue;
+        };
+        match words.next() {
+            Some(unit) if unit.starts_with("hr") => total_minutes += n * 60,
+            Some(unit) if unit.starts_with("min") => total_minutes += n,
+            _ => continue,
+        }
+        found = true;
+    }
+
+    found.then_some(TodayTime { total_minutes })
+}
+
+const PLACEHOLDERS: &[&str] = &["hours", "minutes", "total_minutes", "project"];
+
+/// Checks that every `{...}` in a `status_format` template is a known placeholder.
+pub fn validate_format(template: &str) -> bool {
+    let mut rest = template;
+
+    while let Some(start) = rest.find(['{', '}']) {
+        if rest[start..].starts_with('}') {
+            return false;
+        }
+        let Some(end) = rest[start..].find('}') else {
+            return false;
+        };
+        if !PLACEHOLDERS.contains(&&rest[start + 1..start + end]) {
+            return false;
+        }
+        rest = &rest[start + end + 1..];
+    }
+
+    true
+}
+
+/// Renders a validated `status_format` template.
+pub fn format_today(template: &str, time: TodayTime, project: Option<&str>) -> String {
+    template
+        .replace("{total_minutes}", &time.total_minutes.to_string())
+        .replace("{hours}", &time.hours().to_string())
+        .replace("{minutes}", &format!("{:02}", time.minutes()))
+        .replace("{project}", project.unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn minutes(total_minutes: u64) -> Option<TodayTime> {
+        Some(TodayTime { total_minutes })
+    }
+
+    #[test]
+    fn json_total_seconds_wins_over_the_text() {
+        let today = parse_today(
+            r#"{"data": {"grand_total": {"text": "3 hrs 12 mins", "total_seconds": 11579.9}}}"#,
+        );
+        assert_eq!(today.text, "3 hrs 12 mins");
+        assert_eq!(today.time, minutes(192));
+    }
+
+    #[test]
+    fn json_without_seconds_falls_back_to_its_text() {
+        let today = parse_today(r#"{"text": "1 hr 1 min"}"#);
+        assert_eq!(today.text, "1 hr 1 min");
+        assert_eq!(today.time, minutes(61));
+
+        let today = parse_today(r#"{"grand_total": {"text": "45 mins"}}"#);
+        assert_eq!(today.text, "45 mins");
+        assert_eq!(today.time, minutes(45));
+    }
+
+    #[test]
+    fn plain_text_output_is_parsed() {
+        let today = parse_today("  2 hrs 5 mins\n");
+        assert_eq!(today.text, "2 hrs 5 mins");
+        assert_eq!(today.time, minutes(125));
+    }
+
+    #[test]
+    fn text_units_are_singular_or_plural() {
+        assert_eq!(parse_today_text("1 hr 1 min"), minutes(61));
+        assert_eq!(parse_today_text("3 hrs"), minutes(180));
+        assert_eq!(parse_today_text("0 mins"), minutes(0));
+    }
+
+    #[test]
+    fn text_without_a_duration_has_no_time() {
+        assert_eq!(parse_today_text(""), None);
+        assert_eq!(parse_today_text("Start coding"), None);
+        assert_eq!(parse_today_text("12 secs"), None);
+        assert_eq!(parse_today("not json").time, None);
+    }
+
+    #[test]
+    fn known_placeholders_are_valid() {
+        assert!(validate_format(""));
+        assert!(validate_format("no placeholders"));
+        assert!(validate_format("{hours}:{minutes}"));
+        assert!(validate_format("{total_minutes} min on {project}"));
+    }
+
+    #[test]
+    fn unknown_or_unbalanced_placeholders_are_invalid() {
+        assert!(!validate_format("{days}"));
+        assert!(!validate_format("{}"));
+        assert!(!validate_format("{hours"));
+        assert!(!validate_format("hours}"));
+        assert!(!validate_format("{hours}}"));
+        assert!(!validate_format("{{hours}}"));
+    }
+
+    #[test]
+    fn minutes_are_zero_padded() {
+        let time = TodayTime { total_minutes: 65 };
+        assert_eq!(format_today("{hours}:{minutes}", time, None), "1:05");
+        assert_eq!(
+            format_today("{total_minutes} min", time, Some("api")),
+            "65 min"
+        );
+
+        let time = TodayTime { total_minutes: 600 };
+        assert_eq!(format_today("{hours}h{minutes}", time, None), "10h00");
+    }
+
+    #[test]
+    fn project_renders_empty_without_one() {
+        let time = TodayTime { total_minutes: 5 };
+        assert_eq!(format_today("[{project}]", time, Some("api")), "[api]");
+        assert_eq!(format_today("[{project}]", time, None), "[]");
+    }
+}
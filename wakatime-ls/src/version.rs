@@ -0,0 +1,43 @@
+use std::time::Duration;
+
+use crate::runner::CommandRunner;
+
+const VERSION_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Runs `<binary> --version` and extracts the version, or `"unknown"` when the
+/// binary is missing, fails, or doesn't answer within three seconds.
+pub async fn binary_version(runner: &dyn CommandRunner, binary: &str) -> String {
+    let args = ["--version".to_string()];
+    // Dropping the run on timeout kills the process.
+    let output = tokio::time::timeout(
+        VERSION_TIMEOUT,
+        runner.run(binary, &args, None, VERSION_TIMEOUT),
+    )
+    .await;
+
+    match output {
+        Ok(Ok(output)) if output.status.success() => {
+            parse_binary_version(&String::from_utf8_lossy(&output.stdout))
+                .map_or_else(|| "unknown".to_string(), |version| version.to_string())
+        }
+        _ => "unknown".to_string(),
+    }
+}
+
+/// Parses `--version` output into a comparable version, so `1.10.0` sorts
+/// after `1.9.0` and pre-releases before their release.
+pub fn parse_binary_version(output: &str) -> Option<semver::Version> {
+    semver::Version::parse(&parse_version_output(output)?).ok()
+}
+
+/// Extracts the version from output like `v1.90.0`, `wakatime-cli/1.90.0` or
+/// `wakatime_ls 0.4.0`.
+pub fn parse_version_output(output: &str) -> Option<String> {
+    let token = output.split_whitespace().last()?;
+    let token = token.rsplit('/').next().unwrap_or(token);
+    let token = token.trim_start_matches('v');
+
+    token
+        .starts_with(|c: char| c.is_ascii_digit())
+        .then(|| token.to_string())
+}